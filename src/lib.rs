@@ -5,8 +5,11 @@ extern crate env_logger;
 extern crate futures;
 extern crate itertools;
 extern crate log;
+#[cfg(feature = "opcua")]
+extern crate opcua;
 extern crate regex;
-extern crate snap7_sys;
+#[cfg(feature = "serialport")]
+extern crate serialport;
 extern crate url;
 
 pub mod plc_driver;
@@ -1,42 +1,32 @@
-mod plc_driver;
-
-extern crate bit_vec;
-extern crate chrono;
-extern crate clap;
-extern crate env_logger;
-extern crate futures;
-extern crate itertools;
-extern crate log;
-extern crate regex;
-extern crate snap7_sys;
-extern crate url;
-
-use bit_vec::BitVec;
+extern crate box_edge;
+
 use chrono::Local;
 use env_logger::Builder;
-use futures::future::{ok, Either};
-use futures::{Future, Stream};
 use log::{error, info, warn, LevelFilter};
-use regex::Regex;
-use serde::{Deserialize, Serialize};
 use serde_json::Result;
-use std::convert::TryInto;
 use std::io::Write;
-use std::time::Duration;
 use std::{env, process};
 
-use plc_driver::s7::Client;
-use plc_driver::{ETag, ETagRW, ETagValue, ETagtype};
+use box_edge::plc_driver::registry::Registry;
+use box_edge::plc_driver::{ERangePolicy, ETag, ETagValue, ETagtype};
+
+fn log_read(result: std::result::Result<ETagValue, String>) {
+    match result {
+        Ok(value) => info!("{:#?}", value),
+        Err(err) => warn!("Read failed: {}", err),
+    }
+}
 
 fn main() {
     // Initialize the logger from the environment
     Builder::new()
         .format(|buf, record| {
+            let style = buf.default_level_style(record.level());
             writeln!(
                 buf,
-                "{} [{}] <{}:{}> - {}",
+                "{} [{style}{}{style:#}] <{}:{}> - {}",
                 Local::now().format("%Y-%m-%d %H:%M:%S"),
-                buf.default_styled_level(record.level()),
+                record.level(),
                 record.module_path().unwrap_or("<unnamed>"),
                 record.line().unwrap_or(0),
                 record.args()
@@ -46,214 +36,268 @@ fn main() {
         .init();
     info!("I am here");
 
-    let mut client = Client::new();
-    info!("Connected: {}", client.connected());
-
-    client.connect("10.0.0.230", 0, 1);
+    let url = env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("s7://10.0.0.230?rack=0&slot=1"));
+    let registry = Registry::default();
+    let mut client = match registry.open(url.as_str()) {
+        Ok(client) => client,
+        Err(err) => {
+            error!("{}, available: {:?}", err, registry.schemes());
+            process::exit(1);
+        }
+    };
+    info!("Status: {:?}", client.status());
 
-    info!("Connected: {}", client.connected());
+    if let Err(err) = client.connect() {
+        error!("Connect to {} failed: {}", client.url(), err);
+        process::exit(1);
+    }
 
-    warn!("{:#?}", client);
+    info!("Status: {:?}", client.status());
 
-    let mut tag_for_read = ETag {
+    let tag_for_read = ETag {
         name: String::from("test"),
         address: String::from("DB2W2"),
         datatype: ETagtype::INT,
+        range: ERangePolicy::Reject,
     };
-    client.write_tag(&mut tag_for_read, ETagValue::Int(8712));
-    info!("{:#?}", client.read_tag(&tag_for_read).unwrap());
+    if let Err(err) = client.write_tag(&tag_for_read, ETagValue::Int(8712)) {
+        warn!("Write failed: {}", err);
+    }
+    log_read(client.read_tag(&tag_for_read));
 
-    let mut tag_for_read = ETag {
+    let tag_for_read = ETag {
         name: String::from("test"),
         address: String::from("DB2D4"),
         datatype: ETagtype::REAL,
+        range: ERangePolicy::Reject,
     };
-    client.write_tag(&mut tag_for_read, ETagValue::Real(565.25));
-    info!("{:#?}", client.read_tag(&tag_for_read).unwrap());
+    if let Err(err) = client.write_tag(&tag_for_read, ETagValue::Real(565.25)) {
+        warn!("Write failed: {}", err);
+    }
+    log_read(client.read_tag(&tag_for_read));
 
-    let mut tag_for_read = ETag {
+    let tag_for_read = ETag {
         name: String::from("test"),
         address: String::from("DB2X9.0"),
         datatype: ETagtype::BOOL,
+        range: ERangePolicy::Reject,
     };
-    info!("{:#?}", client.read_tag(&tag_for_read).unwrap());
+    log_read(client.read_tag(&tag_for_read));
 
-    let mut tag_for_read = ETag {
+    let tag_for_read = ETag {
         name: String::from("test"),
         address: String::from("DB2X9.1"),
         datatype: ETagtype::BOOL,
+        range: ERangePolicy::Reject,
     };
-    client.write_tag(&mut tag_for_read, ETagValue::Bool(true));
-    info!("{:#?}", client.read_tag(&tag_for_read).unwrap());
+    if let Err(err) = client.write_tag(&tag_for_read, ETagValue::Bool(true)) {
+        warn!("Write failed: {}", err);
+    }
+    log_read(client.read_tag(&tag_for_read));
 
-    let mut tag_for_read = ETag {
+    let tag_for_read = ETag {
         name: String::from("test"),
         address: String::from("DB2X9.2"),
         datatype: ETagtype::BOOL,
+        range: ERangePolicy::Reject,
     };
-    info!("{:#?}", client.read_tag(&tag_for_read).unwrap());
+    log_read(client.read_tag(&tag_for_read));
 
-    let mut tag_for_read = ETag {
+    let tag_for_read = ETag {
         name: String::from("test"),
         address: String::from("DB2X9.3"),
         datatype: ETagtype::BOOL,
+        range: ERangePolicy::Reject,
     };
-    info!("{:#?}", client.read_tag(&tag_for_read).unwrap());
+    log_read(client.read_tag(&tag_for_read));
 
-    let mut tag_for_read = ETag {
+    let tag_for_read = ETag {
         name: String::from("test"),
         address: String::from("DB2X9.4"),
         datatype: ETagtype::BOOL,
+        range: ERangePolicy::Reject,
     };
-    info!("{:#?}", client.read_tag(&tag_for_read).unwrap());
+    log_read(client.read_tag(&tag_for_read));
 
-    let mut tag_for_read = ETag {
+    let tag_for_read = ETag {
         name: String::from("test"),
         address: String::from("DB2X9.5"),
         datatype: ETagtype::BOOL,
+        range: ERangePolicy::Reject,
     };
-    client.write_tag(&mut tag_for_read, ETagValue::Bool(false));
-    info!("{:#?}", client.read_tag(&tag_for_read).unwrap());
+    if let Err(err) = client.write_tag(&tag_for_read, ETagValue::Bool(false)) {
+        warn!("Write failed: {}", err);
+    }
+    log_read(client.read_tag(&tag_for_read));
 
-    let mut tag_for_read = ETag {
+    let tag_for_read = ETag {
         name: String::from("test"),
         address: String::from("DB2X9.6"),
         datatype: ETagtype::BOOL,
+        range: ERangePolicy::Reject,
     };
-    info!("{:#?}", client.read_tag(&tag_for_read).unwrap());
+    log_read(client.read_tag(&tag_for_read));
 
-    let mut tag_for_read = ETag {
+    let tag_for_read = ETag {
         name: String::from("test"),
         address: String::from("DB2X9.7"),
         datatype: ETagtype::BOOL,
+        range: ERangePolicy::Reject,
     };
-    info!("{:#?}", client.read_tag(&tag_for_read).unwrap());
+    log_read(client.read_tag(&tag_for_read));
 
-    let mut tag_for_read = ETag {
+    let tag_for_read = ETag {
         name: String::from("test"),
         address: String::from("DB2D10"),
         datatype: ETagtype::DINT,
+        range: ERangePolicy::Reject,
     };
-    client.write_tag(&mut tag_for_read, ETagValue::Int(5842651));
-    info!("{:#?}", client.read_tag(&tag_for_read).unwrap());
+    if let Err(err) = client.write_tag(&tag_for_read, ETagValue::Int(5842651)) {
+        warn!("Write failed: {}", err);
+    }
+    log_read(client.read_tag(&tag_for_read));
 
     let tags = vec![
         ETag {
             name: String::from("test"),
             address: String::from("DB2W0"),
             datatype: ETagtype::INT,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2W2"),
             datatype: ETagtype::INT,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2D4"),
             datatype: ETagtype::REAL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.0"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.1"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.2"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.3"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.4"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.5"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.6"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.7"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MW104"),
             datatype: ETagtype::INT,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MW102"),
             datatype: ETagtype::INT,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MD110"),
             datatype: ETagtype::REAL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MX100.7"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MX100.6"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MX100.5"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MX100.4"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MX100.3"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MX100.2"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MX100.1"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MX100.0"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
     ];
 
-    let results = client.read_list(&tags).unwrap();
-    for r in results {
-        info!("{:#?}", r.unwrap())
-    }
+    match client.read_list(&tags) {
+        Ok(results) => results.into_iter().for_each(log_read),
+        Err(err) => warn!("Read failed: {}", err),
+    };
 
     let tags = vec![
         (
@@ -261,6 +305,7 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("DB2X9.7"),
                 datatype: ETagtype::BOOL,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Bool(true),
         ),
@@ -269,6 +314,7 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("DB2W0"),
                 datatype: ETagtype::INT,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Int(546),
         ),
@@ -277,6 +323,7 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("DB2X9.0"),
                 datatype: ETagtype::BOOL,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Bool(false),
         ),
@@ -285,6 +332,7 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("DB2X9.4"),
                 datatype: ETagtype::BOOL,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Bool(false),
         ),
@@ -293,6 +341,7 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("DB2X9.5"),
                 datatype: ETagtype::BOOL,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Bool(false),
         ),
@@ -301,6 +350,7 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("DB2W2"),
                 datatype: ETagtype::INT,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Int(854),
         ),
@@ -309,6 +359,7 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("DB2D4"),
                 datatype: ETagtype::REAL,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Real(856.32),
         ),
@@ -317,6 +368,7 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("DB2X9.1"),
                 datatype: ETagtype::BOOL,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Bool(true),
         ),
@@ -325,6 +377,7 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("DB2X9.2"),
                 datatype: ETagtype::BOOL,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Bool(true),
         ),
@@ -333,6 +386,7 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("MW100"),
                 datatype: ETagtype::INT,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Int(3405),
         ),
@@ -341,6 +395,7 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("MD102"),
                 datatype: ETagtype::DINT,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Int(96646598),
         ),
@@ -349,6 +404,7 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("MD106"),
                 datatype: ETagtype::REAL,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Real(0.002),
         ),
@@ -357,6 +413,7 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("MX10.5"),
                 datatype: ETagtype::BOOL,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Bool(true),
         ),
@@ -365,6 +422,7 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("MX10.2"),
                 datatype: ETagtype::BOOL,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Bool(true),
         ),
@@ -373,6 +431,7 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("IX0.0"),
                 datatype: ETagtype::BOOL,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Bool(false),
         ),
@@ -381,123 +440,141 @@ fn main() {
                 name: String::from("test"),
                 address: String::from("QX0.0"),
                 datatype: ETagtype::BOOL,
+                range: ERangePolicy::Reject,
             },
             ETagValue::Bool(false),
         ),
     ];
 
-    client.write_list(&tags);
+    if let Err(err) = client.write_list(&tags) {
+        warn!("Write failed: {}", err);
+    }
 
     let tags = vec![
         ETag {
             name: String::from("test"),
             address: String::from("DB2W0"),
             datatype: ETagtype::INT,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2W2"),
             datatype: ETagtype::INT,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2D4"),
             datatype: ETagtype::REAL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.0"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.1"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.2"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.3"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.4"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.5"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.6"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("DB2X9.7"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MW100"),
             datatype: ETagtype::INT,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MD102"),
             datatype: ETagtype::DINT,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MD106"),
             datatype: ETagtype::REAL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MX10.5"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("MX10.2"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("IX0.0"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETag {
             name: String::from("test"),
             address: String::from("QX0.0"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
     ];
 
     match client.read_list(&tags) {
-        Ok(results) => {
-            for r in results {
-                info!("{:#?}", r.unwrap())
-            }
-        }
-        Err(err) => info!("{:#?}", err),
+        Ok(results) => results.into_iter().for_each(log_read),
+        Err(err) => warn!("Read failed: {}", err),
     };
 
-    info!("Connected: {}", client.connected());
+    info!("Status: {:?}", client.status());
     client.close();
-    info!("Connected: {}", client.connected());
+    info!("Status: {:?}", client.status());
 
     let tag = ETag {
         name: String::from("test"),
         address: String::from("DB2X9.1"),
         datatype: ETagtype::BOOL,
+        range: ERangePolicy::Reject,
     };
 
     let value = ETagValue::Real(3.5);
@@ -507,6 +584,7 @@ fn main() {
             name: String::from("test"),
             address: String::from("DB2X9.1"),
             datatype: ETagtype::BOOL,
+            range: ERangePolicy::Reject,
         },
         ETagValue::Real(3.5),
     );
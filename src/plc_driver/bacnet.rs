@@ -0,0 +1,1336 @@
+use super::registry::{self, param, Registry};
+use super::{ETag, ETagRW, ETagValue, ETagtype};
+use log::{info, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const BACNET_PORT: u16 = 47808;
+const BVLC_TYPE: u8 = 0x81;
+const BVLC_UNICAST: u8 = 0x0A;
+const BVLC_BROADCAST: u8 = 0x0B;
+const BVLC_FORWARDED: u8 = 0x04;
+const NPDU_VERSION: u8 = 0x01;
+const NPDU_EXPECTING_REPLY: u8 = 0x04;
+
+const PDU_CONFIRMED: u8 = 0x00;
+const PDU_UNCONFIRMED: u8 = 0x10;
+const PDU_SIMPLE_ACK: u8 = 0x20;
+const PDU_COMPLEX_ACK: u8 = 0x30;
+const PDU_ERROR: u8 = 0x50;
+const PDU_REJECT: u8 = 0x60;
+const PDU_ABORT: u8 = 0x70;
+
+const SERVICE_I_AM: u8 = 0x00;
+const SERVICE_WHO_IS: u8 = 0x08;
+const SERVICE_READ_PROPERTY: u8 = 0x0C;
+const SERVICE_READ_PROPERTY_MULTIPLE: u8 = 0x0E;
+const SERVICE_WRITE_PROPERTY: u8 = 0x0F;
+
+// Unsegmented responses up to 1476 bytes, the BACnet/IP maximum
+const MAX_APDU_ACCEPTED: u8 = 0x05;
+const REJECT_UNRECOGNIZED_SERVICE: u8 = 0x09;
+
+const TAG_NULL: u8 = 0;
+const TAG_BOOLEAN: u8 = 1;
+const TAG_UNSIGNED: u8 = 2;
+const TAG_SIGNED: u8 = 3;
+const TAG_REAL: u8 = 4;
+const TAG_DOUBLE: u8 = 5;
+const TAG_ENUMERATED: u8 = 9;
+const TAG_OBJECT_ID: u8 = 12;
+
+pub const PROP_PRESENT_VALUE: u32 = 85;
+
+// Object types, abbreviated as in most BACnet tools
+const OBJECT_TYPES: &[(&str, &str, u16)] = &[
+    ("AI", "analog-input", 0),
+    ("AO", "analog-output", 1),
+    ("AV", "analog-value", 2),
+    ("BI", "binary-input", 3),
+    ("BO", "binary-output", 4),
+    ("BV", "binary-value", 5),
+    ("DEV", "device", 8),
+    ("LOOP", "loop", 12),
+    ("MI", "multi-state-input", 13),
+    ("MO", "multi-state-output", 14),
+    ("MV", "multi-state-value", 19),
+    ("ACC", "accumulator", 23),
+    ("PC", "pulse-converter", 24),
+    ("IV", "integer-value", 45),
+    ("PIV", "positive-integer-value", 48),
+];
+
+const PROPERTIES: &[(&str, u32)] = &[
+    ("cov-increment", 22),
+    ("deadband", 25),
+    ("event-state", 36),
+    ("high-limit", 45),
+    ("low-limit", 59),
+    ("max-pres-value", 65),
+    ("min-pres-value", 69),
+    ("number-of-states", 74),
+    ("out-of-service", 81),
+    ("present-value", 85),
+    ("relinquish-default", 104),
+    ("reliability", 103),
+    ("setpoint", 108),
+    ("status-flags", 111),
+    ("system-status", 112),
+    ("units", 117),
+    ("vendor-identifier", 120),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ObjectId {
+    pub object_type: u16,
+    pub instance: u32,
+}
+
+impl ObjectId {
+    fn encode(&self) -> [u8; 4] {
+        ((self.object_type as u32) << 22 | (self.instance & 0x3F_FFFF)).to_be_bytes()
+    }
+
+    fn decode(b: &[u8]) -> Self {
+        let v = u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+        Self {
+            object_type: (v >> 22) as u16,
+            instance: v & 0x3F_FFFF,
+        }
+    }
+
+    fn is_analog(&self) -> bool {
+        matches!(self.object_type, 0..=2)
+    }
+
+    fn is_binary(&self) -> bool {
+        matches!(self.object_type, 3..=5)
+    }
+
+    fn is_multistate(&self) -> bool {
+        matches!(self.object_type, 13 | 14 | 19)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BacnetAddress {
+    pub object: ObjectId,
+    pub property: u32,
+    pub index: Option<u32>,
+    // Write priority 1-16, the client default when not set
+    pub priority: Option<u8>,
+    pub datatype: ETagtype,
+}
+
+pub fn address_regex() -> Regex {
+    Regex::new(r"^([A-Za-z-]+):(\d{1,7})/([a-z0-9-]+)(?:\[(\d+)\])?(?:@(\d{1,2}))?$").unwrap()
+}
+
+impl BacnetAddress {
+    // `AI:3/present-value`, `AO:1/present-value@8`, `MV:2/number-of-states`,
+    // `analog-value:7/85`, `DEV:1001/vendor-identifier`, `AO:1/87[16]`
+    pub fn parse(reg: &Regex, address: &str, datatype: ETagtype) -> Result<Self, String> {
+        let invalid = || format!("Invalid BACnet address {}", address);
+        let r = reg.captures(address).ok_or_else(invalid)?;
+        let name = r.get(1).unwrap().as_str();
+        let object_type = OBJECT_TYPES
+            .iter()
+            .find(|(short, long, _)| short.eq_ignore_ascii_case(name) || *long == name)
+            .map(|t| t.2)
+            .ok_or_else(|| format!("Unknown BACnet object type {}", name))?;
+        let instance: u32 = r.get(2).unwrap().as_str().parse().map_err(|_| invalid())?;
+        let prop = r.get(3).unwrap().as_str();
+        let property = match prop.parse::<u32>() {
+            Ok(id) => id,
+            Err(_) => PROPERTIES
+                .iter()
+                .find(|p| p.0 == prop)
+                .map(|p| p.1)
+                .ok_or_else(|| format!("Unknown BACnet property {}", prop))?,
+        };
+        let index = match r.get(4) {
+            Some(i) => Some(i.as_str().parse().map_err(|_| invalid())?),
+            None => None,
+        };
+        let priority = match r.get(5) {
+            Some(p) => match p.as_str().parse::<u8>() {
+                Ok(p) if (1..=16).contains(&p) => Some(p),
+                _ => return Err(format!("Invalid priority in {}", address)),
+            },
+            None => None,
+        };
+        if instance > 0x3F_FFFF {
+            return Err(invalid());
+        }
+        Ok(Self {
+            object: ObjectId {
+                object_type,
+                instance,
+            },
+            property,
+            index,
+            priority,
+            datatype,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BacnetConfig {
+    // Priority of writes to commandable properties, None writes without one
+    pub priority: Option<u8>,
+    // Properties in one ReadPropertyMultiple, responses are not segmented
+    pub max_properties: usize,
+    pub timeout_ms: u64,
+    pub retries: u8,
+}
+
+impl Default for BacnetConfig {
+    fn default() -> Self {
+        Self {
+            priority: Some(16),
+            max_properties: 20,
+            timeout_ms: 3000,
+            retries: 2,
+        }
+    }
+}
+
+// A device answering Who-Is
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IAm {
+    pub device: u32,
+    pub address: SocketAddr,
+    pub max_apdu: u32,
+    pub segmentation: u8,
+    pub vendor: u32,
+}
+
+// Application tagged values as far as ETagValue can hold them
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Value {
+    Null,
+    Bool(bool),
+    Unsigned(u64),
+    Signed(i64),
+    Real(f64),
+    Enumerated(u64),
+    Other(u8),
+}
+
+fn tag(out: &mut Vec<u8>, number: u8, context: bool, len: usize) {
+    let class = if context { 0x08 } else { 0x00 };
+    let lvt = if len < 5 { len as u8 } else { 5 };
+    out.push(number << 4 | class | lvt);
+    if len >= 5 {
+        if len < 254 {
+            out.push(len as u8);
+        } else {
+            out.push(254);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+    }
+}
+
+fn opening(out: &mut Vec<u8>, number: u8) {
+    out.push(number << 4 | 0x0E);
+}
+
+fn closing(out: &mut Vec<u8>, number: u8) {
+    out.push(number << 4 | 0x0F);
+}
+
+fn unsigned_bytes(v: u64) -> Vec<u8> {
+    let bytes = v.to_be_bytes();
+    let skip = bytes.iter().take(7).take_while(|b| **b == 0).count();
+    bytes[skip..].to_vec()
+}
+
+fn signed_bytes(v: i64) -> Vec<u8> {
+    let bytes = v.to_be_bytes();
+    let mut skip = 0;
+    while skip < 7 {
+        let (b, next) = (bytes[skip], bytes[skip + 1]);
+        if (b == 0x00 && next & 0x80 == 0) || (b == 0xFF && next & 0x80 != 0) {
+            skip += 1;
+        } else {
+            break;
+        }
+    }
+    bytes[skip..].to_vec()
+}
+
+fn context_unsigned(out: &mut Vec<u8>, number: u8, v: u64) {
+    let bytes = unsigned_bytes(v);
+    tag(out, number, true, bytes.len());
+    out.extend_from_slice(&bytes);
+}
+
+fn context_object(out: &mut Vec<u8>, number: u8, object: &ObjectId) {
+    tag(out, number, true, 4);
+    out.extend_from_slice(&object.encode());
+}
+
+fn encode_app(out: &mut Vec<u8>, value: Value) {
+    match value {
+        Value::Null => tag(out, TAG_NULL, false, 0),
+        Value::Bool(v) => tag(out, TAG_BOOLEAN, false, v as usize),
+        Value::Unsigned(v) => {
+            let bytes = unsigned_bytes(v);
+            tag(out, TAG_UNSIGNED, false, bytes.len());
+            out.extend_from_slice(&bytes);
+        }
+        Value::Signed(v) => {
+            let bytes = signed_bytes(v);
+            tag(out, TAG_SIGNED, false, bytes.len());
+            out.extend_from_slice(&bytes);
+        }
+        Value::Real(v) => {
+            tag(out, TAG_REAL, false, 4);
+            out.extend_from_slice(&(v as f32).to_be_bytes());
+        }
+        Value::Enumerated(v) => {
+            let bytes = unsigned_bytes(v);
+            tag(out, TAG_ENUMERATED, false, bytes.len());
+            out.extend_from_slice(&bytes);
+        }
+        Value::Other(_) => {}
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Tag {
+    Opening(u8),
+    Closing(u8),
+    Context(u8, usize),
+    // Booleans carry their value in the length field
+    Application(u8, usize),
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn done(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let b = self
+            .buf
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| String::from("Truncated BACnet APDU"))?;
+        self.pos += n;
+        Ok(b)
+    }
+
+    fn peek(&self) -> Result<Tag, String> {
+        let mut r = Reader {
+            buf: self.buf,
+            pos: self.pos,
+        };
+        r.tag()
+    }
+
+    fn tag(&mut self) -> Result<Tag, String> {
+        let b = self.take(1)?[0];
+        let mut number = b >> 4;
+        if number == 0x0F {
+            number = self.take(1)?[0];
+        }
+        let context = b & 0x08 != 0;
+        let lvt = b & 0x07;
+        if context && lvt == 6 {
+            return Ok(Tag::Opening(number));
+        }
+        if context && lvt == 7 {
+            return Ok(Tag::Closing(number));
+        }
+        let len = match lvt {
+            5 => match self.take(1)?[0] {
+                254 => {
+                    let b = self.take(2)?;
+                    u16::from_be_bytes([b[0], b[1]]) as usize
+                }
+                255 => {
+                    let b = self.take(4)?;
+                    u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize
+                }
+                n => n as usize,
+            },
+            n => n as usize,
+        };
+        Ok(if context {
+            Tag::Context(number, len)
+        } else {
+            Tag::Application(number, len)
+        })
+    }
+
+    fn expect(&mut self, expected: Tag) -> Result<(), String> {
+        match self.tag()? {
+            t if t == expected => Ok(()),
+            t => Err(format!("Unexpected BACnet tag {:?}", t)),
+        }
+    }
+
+    fn unsigned(&mut self, len: usize) -> Result<u64, String> {
+        Ok(self
+            .take(len)?
+            .iter()
+            .fold(0u64, |acc, b| acc << 8 | *b as u64))
+    }
+
+    fn context_unsigned(&mut self, number: u8) -> Result<u64, String> {
+        match self.tag()? {
+            Tag::Context(n, len) if n == number => self.unsigned(len),
+            t => Err(format!("Unexpected BACnet tag {:?}", t)),
+        }
+    }
+
+    fn context_object(&mut self, number: u8) -> Result<ObjectId, String> {
+        match self.tag()? {
+            Tag::Context(n, 4) if n == number => Ok(ObjectId::decode(self.take(4)?)),
+            t => Err(format!("Unexpected BACnet tag {:?}", t)),
+        }
+    }
+
+    // An optional context tagged unsigned, such as an array index
+    fn optional_unsigned(&mut self, number: u8) -> Result<Option<u64>, String> {
+        match self.peek()? {
+            Tag::Context(n, _) if n == number => self.context_unsigned(number).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn app_value(&mut self) -> Result<Value, String> {
+        match self.tag()? {
+            Tag::Application(TAG_NULL, _) => Ok(Value::Null),
+            Tag::Application(TAG_BOOLEAN, v) => Ok(Value::Bool(v != 0)),
+            Tag::Application(TAG_UNSIGNED, len) => self.unsigned(len).map(Value::Unsigned),
+            Tag::Application(TAG_SIGNED, len) => {
+                let b = self.take(len)?;
+                let init = if b.first().is_some_and(|b| b & 0x80 != 0) {
+                    -1i64
+                } else {
+                    0
+                };
+                Ok(Value::Signed(
+                    b.iter().fold(init, |acc, b| acc << 8 | *b as i64),
+                ))
+            }
+            Tag::Application(TAG_REAL, 4) => {
+                let b = self.take(4)?;
+                Ok(Value::Real(
+                    f32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64
+                ))
+            }
+            Tag::Application(TAG_DOUBLE, 8) => {
+                let b = self.take(8)?;
+                let mut a = [0u8; 8];
+                a.copy_from_slice(b);
+                Ok(Value::Real(f64::from_be_bytes(a)))
+            }
+            Tag::Application(TAG_ENUMERATED, len) => self.unsigned(len).map(Value::Enumerated),
+            Tag::Application(number, len) => {
+                self.take(len)?;
+                Ok(Value::Other(number))
+            }
+            t => Err(format!("Unexpected BACnet tag {:?}", t)),
+        }
+    }
+
+    // Values up to the closing tag `number`, which is consumed
+    fn values(&mut self, number: u8) -> Result<Vec<Value>, String> {
+        let mut values = Vec::new();
+        loop {
+            match self.peek()? {
+                Tag::Closing(n) if n == number => {
+                    self.tag()?;
+                    return Ok(values);
+                }
+                Tag::Opening(n) => {
+                    // Constructed data ETagValue has no place for
+                    self.tag()?;
+                    self.values(n)?;
+                    values.push(Value::Other(n));
+                }
+                _ => values.push(self.app_value()?),
+            }
+        }
+    }
+
+    fn error(&mut self) -> Result<String, String> {
+        match (self.app_value()?, self.app_value()?) {
+            (Value::Enumerated(class), Value::Enumerated(code)) => {
+                Ok(error_text(class as u32, code as u32))
+            }
+            _ => Err(String::from("Invalid BACnet error")),
+        }
+    }
+}
+
+pub fn reject_text(reason: u8) -> String {
+    match reason {
+        REJECT_UNRECOGNIZED_SERVICE => String::from("BACnet reject: unrecognized service"),
+        reason => format!("BACnet reject reason {}", reason),
+    }
+}
+
+pub fn error_text(class: u32, code: u32) -> String {
+    let class = match class {
+        0 => "device",
+        1 => "object",
+        2 => "property",
+        3 => "resources",
+        4 => "security",
+        5 => "services",
+        7 => "communication",
+        _ => "other",
+    };
+    let code = match code {
+        0 => String::from("other"),
+        2 => String::from("configuration-in-progress"),
+        3 => String::from("device-busy"),
+        9 => String::from("invalid-data-type"),
+        25 => String::from("operational-problem"),
+        27 => String::from("read-access-denied"),
+        31 => String::from("unknown-object"),
+        32 => String::from("unknown-property"),
+        37 => String::from("value-out-of-range"),
+        40 => String::from("write-access-denied"),
+        42 => String::from("invalid-array-index"),
+        44 => String::from("not-cov-property"),
+        50 => String::from("property-is-not-an-array"),
+        code => format!("code {}", code),
+    };
+    format!("BACnet error {}/{}", class, code)
+}
+
+fn to_value(value: Value, datatype: ETagtype) -> Result<ETagValue, String> {
+    let v = match value {
+        Value::Bool(v) => ETagValue::Bool(v),
+        Value::Unsigned(v) | Value::Enumerated(v) => ETagValue::Int(v as i64),
+        Value::Signed(v) => ETagValue::Int(v),
+        Value::Real(v) => ETagValue::Real(v),
+        Value::Null => return Err(String::from("Null value")),
+        Value::Other(tag) => return Err(format!("Unsupported BACnet datatype {}", tag)),
+    };
+    Ok(match (datatype, v) {
+        (ETagtype::BOOL, ETagValue::Int(v)) => ETagValue::Bool(v != 0),
+        (ETagtype::BOOL, ETagValue::Real(v)) => ETagValue::Bool(v != 0.0),
+        (ETagtype::INT, ETagValue::Real(v)) | (ETagtype::DINT, ETagValue::Real(v)) => {
+            let (min, max) = datatype.range().unwrap();
+            ETagValue::Int((v.round() as i64).max(min).min(max))
+        }
+        (ETagtype::INT, ETagValue::Bool(v)) | (ETagtype::DINT, ETagValue::Bool(v)) => {
+            ETagValue::Int(v as i64)
+        }
+        (ETagtype::REAL, ETagValue::Int(v)) => ETagValue::Real(v as f64),
+        (ETagtype::REAL, ETagValue::Bool(v)) => ETagValue::Real(v as i64 as f64),
+        (_, v) => v,
+    })
+}
+
+// Present values have a fixed type per object; other properties follow the tag
+fn from_value(addr: &BacnetAddress, write: ETagValue) -> Result<Value, String> {
+    let as_i64 = match write {
+        ETagValue::Bool(v) => v as i64,
+        ETagValue::Int(v) => v,
+        ETagValue::Real(v) => v.round() as i64,
+        _ => return Err(format!("Cannot write {} to BACnet", write.type_name())),
+    };
+    if addr.property == PROP_PRESENT_VALUE {
+        if addr.object.is_analog() {
+            return Ok(Value::Real(match write {
+                ETagValue::Real(v) => v,
+                _ => as_i64 as f64,
+            }));
+        }
+        if addr.object.is_binary() {
+            return Ok(Value::Enumerated((as_i64 != 0) as u64));
+        }
+        if addr.object.is_multistate() {
+            return Ok(Value::Unsigned(as_i64.max(0) as u64));
+        }
+    }
+    Ok(match write {
+        ETagValue::Bool(v) => Value::Bool(v),
+        ETagValue::Int(v) if v >= 0 => Value::Unsigned(v as u64),
+        ETagValue::Int(v) => Value::Signed(v),
+        ETagValue::Real(v) => Value::Real(v),
+        // Rejected above
+        _ => Value::Null,
+    })
+}
+
+struct Connection {
+    socket: UdpSocket,
+    invoke_id: u8,
+    broken: bool,
+}
+
+#[derive(Debug)]
+pub struct Client {
+    host: String,
+    config: BacnetConfig,
+    reg: Regex,
+    // Cleared when the device rejects ReadPropertyMultiple
+    multiple: Mutex<bool>,
+    conn: Mutex<Option<Connection>>,
+}
+
+impl std::fmt::Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Connection")
+            .field("peer", &self.socket.peer_addr().ok())
+            .finish()
+    }
+}
+
+fn bvlc(function: u8, npdu: &[u8]) -> Vec<u8> {
+    let mut frame = vec![BVLC_TYPE, function];
+    frame.extend_from_slice(&((npdu.len() + 4) as u16).to_be_bytes());
+    frame.extend_from_slice(npdu);
+    frame
+}
+
+// The APDU of a BACnet/IP frame and the address of a forwarded sender
+fn parse_frame(frame: &[u8], from: SocketAddr) -> Option<(&[u8], SocketAddr)> {
+    if frame.len() < 6 || frame[0] != BVLC_TYPE {
+        return None;
+    }
+    let (npdu, from) = match frame[1] {
+        BVLC_UNICAST | BVLC_BROADCAST => (&frame[4..], from),
+        BVLC_FORWARDED if frame.len() >= 10 => {
+            let b = &frame[4..10];
+            let addr =
+                SocketAddr::from(([b[0], b[1], b[2], b[3]], u16::from_be_bytes([b[4], b[5]])));
+            (&frame[10..], addr)
+        }
+        _ => return None,
+    };
+    if npdu.len() < 2 || npdu[0] != NPDU_VERSION || npdu[1] & 0x80 != 0 {
+        return None;
+    }
+    let control = npdu[1];
+    let mut pos = 2;
+    if control & 0x20 != 0 {
+        pos += 3 + *npdu.get(pos + 2)? as usize;
+    }
+    if control & 0x08 != 0 {
+        pos += 3 + *npdu.get(pos + 2)? as usize;
+    }
+    if control & 0x20 != 0 {
+        pos += 1;
+    }
+    npdu.get(pos..).map(|apdu| (apdu, from))
+}
+
+// Broadcasts Who-Is and collects I-Am answers until `timeout` has passed.
+// I-Am is usually broadcast to port 47808, which is bound when it is free.
+pub fn discover(
+    broadcast: &str,
+    range: Option<(u32, u32)>,
+    timeout: Duration,
+) -> Result<Vec<IAm>, String> {
+    let target: SocketAddr = if broadcast.contains(':') {
+        broadcast.to_socket_addrs()
+    } else {
+        (broadcast, BACNET_PORT).to_socket_addrs()
+    }
+    .map_err(|e| e.to_string())?
+    .next()
+    .ok_or_else(|| format!("Cannot resolve {}", broadcast))?;
+    let socket = UdpSocket::bind(("0.0.0.0", BACNET_PORT))
+        .or_else(|_| UdpSocket::bind("0.0.0.0:0"))
+        .map_err(|e| e.to_string())?;
+    socket.set_broadcast(true).map_err(|e| e.to_string())?;
+
+    let mut npdu = vec![NPDU_VERSION, 0x00, PDU_UNCONFIRMED, SERVICE_WHO_IS];
+    if let Some((low, high)) = range {
+        context_unsigned(&mut npdu, 0, low as u64);
+        context_unsigned(&mut npdu, 1, high as u64);
+    }
+    socket
+        .send_to(&bvlc(BVLC_BROADCAST, &npdu), target)
+        .map_err(|e| e.to_string())?;
+
+    let mut devices: Vec<IAm> = Vec::new();
+    let deadline = Instant::now() + timeout;
+    let mut buf = vec![0u8; 1500];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left == Duration::from_millis(0) {
+            break;
+        }
+        socket
+            .set_read_timeout(Some(left))
+            .map_err(|e| e.to_string())?;
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(r) => r,
+            Err(_) => break,
+        };
+        let (apdu, from) = match parse_frame(&buf[..len], from) {
+            Some(r) => r,
+            None => continue,
+        };
+        if apdu.len() < 2 || apdu[0] != PDU_UNCONFIRMED || apdu[1] != SERVICE_I_AM {
+            continue;
+        }
+        let mut r = Reader::new(&apdu[2..]);
+        let iam = (|| -> Result<IAm, String> {
+            let device = match (r.tag()?, r.take(4)) {
+                (Tag::Application(TAG_OBJECT_ID, 4), Ok(b)) => ObjectId::decode(b).instance,
+                _ => return Err(String::from("Invalid I-Am")),
+            };
+            match (r.app_value()?, r.app_value()?, r.app_value()?) {
+                (Value::Unsigned(max_apdu), Value::Enumerated(seg), Value::Unsigned(vendor)) => {
+                    Ok(IAm {
+                        device,
+                        address: from,
+                        max_apdu: max_apdu as u32,
+                        segmentation: seg as u8,
+                        vendor: vendor as u32,
+                    })
+                }
+                _ => Err(String::from("Invalid I-Am")),
+            }
+        })();
+        match iam {
+            Ok(iam) if !devices.iter().any(|d| d.device == iam.device) => {
+                info!("BACnet device {} at {}", iam.device, iam.address);
+                devices.push(iam);
+            }
+            Ok(_) => {}
+            Err(err) => warn!("{} from {}", err, from),
+        }
+    }
+    Ok(devices)
+}
+
+impl Client {
+    pub fn new(config: BacnetConfig) -> Self {
+        Self {
+            host: String::new(),
+            config,
+            reg: address_regex(),
+            multiple: Mutex::new(true),
+            conn: Mutex::new(None),
+        }
+    }
+
+    // `host` is `address` or `address:port`
+    pub fn connect(&mut self, host: &str) {
+        self.host = host.to_owned();
+        match self.open(host) {
+            Ok(conn) => {
+                info!("BACnet/IP client for {}", host);
+                *self.conn.lock().unwrap() = Some(conn);
+            }
+            Err(err) => {
+                info!("Connect to {} failed: {}", host, err);
+                *self.conn.lock().unwrap() = None;
+            }
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.conn.lock().unwrap().take();
+    }
+
+    pub fn connected(&mut self) -> bool {
+        self.conn.lock().unwrap().is_some()
+    }
+
+    pub fn conv_address(&self, address: &str, datatype: ETagtype) -> Result<BacnetAddress, String> {
+        BacnetAddress::parse(&self.reg, address, datatype)
+    }
+
+    fn open(&self, host: &str) -> Result<Connection, String> {
+        let addr: SocketAddr = if host.contains(':') {
+            host.to_socket_addrs()
+        } else {
+            (host, BACNET_PORT).to_socket_addrs()
+        }
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("Cannot resolve {}", host))?;
+        let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+        socket.connect(addr).map_err(|e| e.to_string())?;
+        socket
+            .set_read_timeout(Some(Duration::from_millis(self.config.timeout_ms)))
+            .map_err(|e| e.to_string())?;
+        Ok(Connection {
+            socket,
+            invoke_id: 0,
+            broken: false,
+        })
+    }
+
+    fn with_conn<T, F>(&self, f: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, String>,
+    {
+        let mut guard = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let result = match guard.as_mut() {
+            Some(conn) => f(conn),
+            None => return Err(String::from("Not connected")),
+        };
+        if guard.as_ref().is_some_and(|c| c.broken) {
+            guard.take();
+        }
+        result
+    }
+
+    // Sends a confirmed request and returns the service data of the ack
+    pub fn request(&self, service: u8, data: &[u8]) -> Result<Vec<u8>, String> {
+        let retries = self.config.retries;
+        self.with_conn(|conn| {
+            conn.invoke_id = conn.invoke_id.wrapping_add(1);
+            let invoke_id = conn.invoke_id;
+            let mut npdu = vec![
+                NPDU_VERSION,
+                NPDU_EXPECTING_REPLY,
+                PDU_CONFIRMED,
+                MAX_APDU_ACCEPTED,
+                invoke_id,
+                service,
+            ];
+            npdu.extend_from_slice(data);
+            let frame = bvlc(BVLC_UNICAST, &npdu);
+            let mut buf = vec![0u8; 1500];
+            for attempt in 0..=retries {
+                let r = conn.socket.send(&frame);
+                conn.io(r)?;
+                loop {
+                    let len = match conn.socket.recv(&mut buf) {
+                        Ok(len) => len,
+                        Err(ref e)
+                            if e.kind() == std::io::ErrorKind::WouldBlock
+                                || e.kind() == std::io::ErrorKind::TimedOut =>
+                        {
+                            if attempt < retries {
+                                warn!("BACnet request {} timed out, retrying", invoke_id);
+                            }
+                            break;
+                        }
+                        Err(e) => {
+                            conn.broken = true;
+                            return Err(e.to_string());
+                        }
+                    };
+                    let from = conn.socket.peer_addr().map_err(|e| e.to_string())?;
+                    let apdu = match parse_frame(&buf[..len], from) {
+                        Some((apdu, _)) if apdu.len() >= 3 && apdu[1] == invoke_id => apdu,
+                        _ => continue,
+                    };
+                    return match apdu[0] & 0xF0 {
+                        PDU_SIMPLE_ACK => Ok(Vec::new()),
+                        PDU_COMPLEX_ACK if apdu[0] & 0x08 != 0 => {
+                            Err(String::from("Segmented BACnet responses are not supported"))
+                        }
+                        PDU_COMPLEX_ACK => Ok(apdu[3..].to_vec()),
+                        PDU_ERROR => Reader::new(&apdu[3..]).error().and_then(Err),
+                        PDU_REJECT => Err(reject_text(apdu[2])),
+                        PDU_ABORT => Err(format!("BACnet abort reason {}", apdu[2])),
+                        _ => Err(String::from("Unexpected BACnet PDU")),
+                    };
+                }
+            }
+            Err(String::from("BACnet request timed out"))
+        })
+    }
+
+    fn read_property(&self, addr: &BacnetAddress) -> Result<ETagValue, String> {
+        let mut data = Vec::new();
+        context_object(&mut data, 0, &addr.object);
+        context_unsigned(&mut data, 1, addr.property as u64);
+        if let Some(index) = addr.index {
+            context_unsigned(&mut data, 2, index as u64);
+        }
+        let ack = self.request(SERVICE_READ_PROPERTY, &data)?;
+        let mut r = Reader::new(&ack);
+        r.context_object(0)?;
+        r.context_unsigned(1)?;
+        r.optional_unsigned(2)?;
+        r.expect(Tag::Opening(3))?;
+        let values = r.values(3)?;
+        to_value(
+            values.first().cloned().unwrap_or(Value::Null),
+            addr.datatype,
+        )
+    }
+
+    fn read_property_multiple(
+        &self,
+        addrs: &[BacnetAddress],
+    ) -> Result<Vec<Result<ETagValue, String>>, String> {
+        let mut data = Vec::new();
+        for addr in addrs {
+            context_object(&mut data, 0, &addr.object);
+            opening(&mut data, 1);
+            context_unsigned(&mut data, 0, addr.property as u64);
+            if let Some(index) = addr.index {
+                context_unsigned(&mut data, 1, index as u64);
+            }
+            closing(&mut data, 1);
+        }
+        let ack = self.request(SERVICE_READ_PROPERTY_MULTIPLE, &data)?;
+
+        // Results come back in request order, one object spec each
+        let mut r = Reader::new(&ack);
+        let mut results = Vec::with_capacity(addrs.len());
+        while !r.done() {
+            r.context_object(0)?;
+            r.expect(Tag::Opening(1))?;
+            loop {
+                if let Tag::Closing(1) = r.peek()? {
+                    r.tag()?;
+                    break;
+                }
+                r.context_unsigned(2)?;
+                r.optional_unsigned(3)?;
+                let datatype = addrs
+                    .get(results.len())
+                    .map(|a| a.datatype)
+                    .ok_or_else(|| String::from("Unexpected ReadPropertyMultiple result"))?;
+                match r.tag()? {
+                    Tag::Opening(4) => {
+                        let values = r.values(4)?;
+                        results.push(to_value(
+                            values.first().cloned().unwrap_or(Value::Null),
+                            datatype,
+                        ));
+                    }
+                    Tag::Opening(5) => {
+                        let err = r.error()?;
+                        r.expect(Tag::Closing(5))?;
+                        results.push(Err(err));
+                    }
+                    t => return Err(format!("Unexpected BACnet tag {:?}", t)),
+                }
+            }
+        }
+        if results.len() != addrs.len() {
+            return Err(String::from("Incomplete ReadPropertyMultiple response"));
+        }
+        Ok(results)
+    }
+
+    fn read_addresses(
+        &self,
+        addrs: &[BacnetAddress],
+    ) -> Result<Vec<Result<ETagValue, String>>, String> {
+        let mut results = Vec::with_capacity(addrs.len());
+        for chunk in addrs.chunks(self.config.max_properties.max(1)) {
+            if *self.multiple.lock().unwrap() {
+                match self.read_property_multiple(chunk) {
+                    Ok(values) => {
+                        results.extend(values);
+                        continue;
+                    }
+                    Err(err) if err == reject_text(REJECT_UNRECOGNIZED_SERVICE) => {
+                        info!("{} does not support ReadPropertyMultiple", self.host);
+                        *self.multiple.lock().unwrap() = false;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            results.extend(chunk.iter().map(|addr| self.read_property(addr)));
+        }
+        Ok(results)
+    }
+
+    fn write_property(&self, addr: &BacnetAddress, value: Value) -> Result<bool, String> {
+        let mut data = Vec::new();
+        context_object(&mut data, 0, &addr.object);
+        context_unsigned(&mut data, 1, addr.property as u64);
+        if let Some(index) = addr.index {
+            context_unsigned(&mut data, 2, index as u64);
+        }
+        opening(&mut data, 3);
+        encode_app(&mut data, value);
+        closing(&mut data, 3);
+        if let Some(priority) = addr.priority.or(self.config.priority) {
+            context_unsigned(&mut data, 4, priority as u64);
+        }
+        self.request(SERVICE_WRITE_PROPERTY, &data).map(|_| true)
+    }
+
+    fn write_address(
+        &self,
+        tag: &ETag,
+        addr: &BacnetAddress,
+        write: ETagValue,
+    ) -> Result<bool, String> {
+        let write = tag.datatype.coerce(write, tag.range)?;
+        self.write_property(addr, from_value(addr, write)?)
+    }
+
+    // Writes Null at the tag's priority, handing the output back to lower ones
+    pub fn relinquish(&self, tag: &ETag) -> Result<bool, String> {
+        let addr = self.conv_address(tag.address.as_str(), tag.datatype)?;
+        self.write_property(&addr, Value::Null)
+    }
+}
+
+impl Connection {
+    fn io<T>(&mut self, r: std::io::Result<T>) -> Result<T, String> {
+        r.map_err(|e| {
+            self.broken = true;
+            e.to_string()
+        })
+    }
+}
+
+impl ETagRW for Client {
+    fn read_tag(&self, tag: &ETag) -> Result<ETagValue, String> {
+        let addr = self.conv_address(tag.address.as_str(), tag.datatype)?;
+        self.read_property(&addr)
+    }
+    fn read_list(&self, tags: &Vec<ETag>) -> Result<Vec<Result<ETagValue, String>>, String> {
+        let addrs = tags
+            .iter()
+            .map(|tag| self.conv_address(tag.address.as_str(), tag.datatype))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        self.read_addresses(&addrs)
+    }
+    fn write_tag(&self, tag: &ETag, write: ETagValue) -> Result<bool, String> {
+        let addr = self.conv_address(tag.address.as_str(), tag.datatype)?;
+        self.write_address(tag, &addr, write)
+    }
+    fn write_list(
+        &self,
+        tags: &Vec<(ETag, ETagValue)>,
+    ) -> Result<Vec<Result<bool, String>>, String> {
+        let addrs = tags
+            .iter()
+            .map(|t| self.conv_address(t.0.address.as_str(), t.0.datatype))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        Ok(tags
+            .iter()
+            .zip(addrs)
+            .map(|((tag, write), addr)| self.write_address(tag, &addr, write.clone()))
+            .collect())
+    }
+}
+
+// bacnet://host[:port]?priority=16&max_properties=20, priority=none writes
+// without a priority
+pub fn register(registry: &mut Registry) {
+    registry.register_device(
+        "bacnet",
+        |url| {
+            let host = registry::host(url)?;
+            let default = BacnetConfig::default();
+            let priority = match param(url, "priority", String::from("16"))?.as_str() {
+                "none" => None,
+                p => match p.parse::<u8>() {
+                    Ok(p) if (1..=16).contains(&p) => Some(p),
+                    _ => return Err(format!("Invalid priority {} in {}", p, url)),
+                },
+            };
+            let mut client = Client::new(BacnetConfig {
+                priority,
+                max_properties: param(url, "max_properties", default.max_properties)?,
+                timeout_ms: param(url, "timeout_ms", default.timeout_ms)?,
+                retries: param(url, "retries", default.retries)?,
+            });
+            client.connect(host.as_str());
+            if client.connected() {
+                Ok(client)
+            } else {
+                Err(format!("Connect to {} failed", host))
+            }
+        },
+        Client::connected,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plc_driver::tests::tag;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[derive(Default)]
+    struct Device {
+        // Value and write priority by object and property
+        properties: BTreeMap<(ObjectId, u32), (Value, Option<u64>)>,
+        // Answers ReadPropertyMultiple with a reject when false
+        multiple: bool,
+        requests: Vec<u8>,
+    }
+
+    fn error(apdu: &mut Vec<u8>, class: u64, code: u64) {
+        apdu[0] = PDU_ERROR;
+        apdu.truncate(3);
+        encode_app(apdu, Value::Enumerated(class));
+        encode_app(apdu, Value::Enumerated(code));
+    }
+
+    fn read(device: &Device, object: ObjectId, property: u32) -> Result<Value, (u64, u64)> {
+        match device.properties.get(&(object, property)) {
+            Some((value, _)) => Ok(*value),
+            None if device.properties.keys().any(|k| k.0 == object) => Err((2, 32)),
+            None => Err((1, 31)),
+        }
+    }
+
+    // Answers a confirmed request the way a device with `properties` would.
+    // Analog present values only take REAL, as in the standard.
+    fn execute(request: &[u8], device: &mut Device) -> Vec<u8> {
+        let (invoke_id, service) = (request[2], request[3]);
+        device.requests.push(service);
+        let mut r = Reader::new(&request[4..]);
+        let mut apdu = vec![PDU_COMPLEX_ACK, invoke_id, service];
+        match service {
+            SERVICE_READ_PROPERTY => {
+                let object = r.context_object(0).unwrap();
+                let property = r.context_unsigned(1).unwrap() as u32;
+                match read(device, object, property) {
+                    Ok(value) => {
+                        context_object(&mut apdu, 0, &object);
+                        context_unsigned(&mut apdu, 1, property as u64);
+                        opening(&mut apdu, 3);
+                        encode_app(&mut apdu, value);
+                        closing(&mut apdu, 3);
+                    }
+                    Err((class, code)) => error(&mut apdu, class, code),
+                }
+            }
+            SERVICE_READ_PROPERTY_MULTIPLE if !device.multiple => {
+                apdu = vec![PDU_REJECT, invoke_id, REJECT_UNRECOGNIZED_SERVICE];
+            }
+            SERVICE_READ_PROPERTY_MULTIPLE => {
+                while !r.done() {
+                    let object = r.context_object(0).unwrap();
+                    r.expect(Tag::Opening(1)).unwrap();
+                    context_object(&mut apdu, 0, &object);
+                    opening(&mut apdu, 1);
+                    while r.peek().unwrap() != Tag::Closing(1) {
+                        let property = r.context_unsigned(0).unwrap() as u32;
+                        context_unsigned(&mut apdu, 2, property as u64);
+                        match read(device, object, property) {
+                            Ok(value) => {
+                                opening(&mut apdu, 4);
+                                encode_app(&mut apdu, value);
+                                closing(&mut apdu, 4);
+                            }
+                            Err((class, code)) => {
+                                opening(&mut apdu, 5);
+                                encode_app(&mut apdu, Value::Enumerated(class));
+                                encode_app(&mut apdu, Value::Enumerated(code));
+                                closing(&mut apdu, 5);
+                            }
+                        }
+                    }
+                    r.tag().unwrap();
+                    closing(&mut apdu, 1);
+                }
+            }
+            SERVICE_WRITE_PROPERTY => {
+                let object = r.context_object(0).unwrap();
+                let property = r.context_unsigned(1).unwrap() as u32;
+                r.expect(Tag::Opening(3)).unwrap();
+                let value = r.values(3).unwrap()[0];
+                let priority = r.optional_unsigned(4).unwrap();
+                let analog = object.is_analog() && property == PROP_PRESENT_VALUE;
+                match (value, read(device, object, property)) {
+                    (_, Err((class, code))) => error(&mut apdu, class, code),
+                    (Value::Real(_), _) | (Value::Null, _) => {
+                        device
+                            .properties
+                            .insert((object, property), (value, priority));
+                        apdu = vec![PDU_SIMPLE_ACK, invoke_id, service];
+                    }
+                    _ if analog => error(&mut apdu, 2, 9),
+                    _ => {
+                        device
+                            .properties
+                            .insert((object, property), (value, priority));
+                        apdu = vec![PDU_SIMPLE_ACK, invoke_id, service];
+                    }
+                }
+            }
+            _ => apdu = vec![PDU_REJECT, invoke_id, REJECT_UNRECOGNIZED_SERVICE],
+        }
+        apdu
+    }
+
+    fn server(device: Device) -> (String, Arc<Mutex<Device>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let host = socket.local_addr().unwrap().to_string();
+        let device = Arc::new(Mutex::new(device));
+        let shared = device.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 1500];
+            while let Ok((len, from)) = socket.recv_from(&mut buf) {
+                let (request, _) = parse_frame(&buf[..len], from).unwrap();
+                let mut npdu = vec![NPDU_VERSION, 0x00];
+                npdu.extend(execute(request, &mut shared.lock().unwrap()));
+                socket.send_to(&bvlc(BVLC_UNICAST, &npdu), from).unwrap();
+            }
+        });
+        (host, device)
+    }
+
+    fn object(object_type: u16, instance: u32) -> ObjectId {
+        ObjectId {
+            object_type,
+            instance,
+        }
+    }
+
+    fn device(multiple: bool) -> Device {
+        let mut device = Device {
+            multiple,
+            ..Device::default()
+        };
+        let properties = [
+            (object(0, 1), PROP_PRESENT_VALUE, Value::Real(21.5)),
+            (object(5, 2), PROP_PRESENT_VALUE, Value::Enumerated(1)),
+            (object(19, 3), PROP_PRESENT_VALUE, Value::Unsigned(2)),
+            (object(1, 4), PROP_PRESENT_VALUE, Value::Real(7.6)),
+            (object(8, 1001), 120, Value::Unsigned(260)),
+            (object(45, 6), PROP_PRESENT_VALUE, Value::Signed(-300)),
+        ];
+        for (object, property, value) in properties.iter() {
+            device
+                .properties
+                .insert((*object, *property), (*value, None));
+        }
+        device
+    }
+
+    fn client(host: &str) -> Client {
+        let mut client = Client::new(BacnetConfig {
+            timeout_ms: 1000,
+            retries: 0,
+            ..BacnetConfig::default()
+        });
+        client.connect(host);
+        assert!(client.connected());
+        client
+    }
+
+    fn read_back(multiple: bool) -> Vec<u8> {
+        let (host, device) = server(device(multiple));
+        let client = client(&host);
+        let tags = vec![
+            tag("AI:1/present-value", ETagtype::REAL),
+            tag("BV:2/present-value", ETagtype::BOOL),
+            tag("MV:3/present-value", ETagtype::INT),
+            tag("AO:4/85", ETagtype::DINT),
+            tag("DEV:1001/vendor-identifier", ETagtype::INT),
+            tag("IV:6/present-value", ETagtype::INT),
+            tag("AI:1/units", ETagtype::INT),
+            tag("AI:9/present-value", ETagtype::REAL),
+        ];
+        assert_eq!(
+            client.read_list(&tags).unwrap(),
+            vec![
+                Ok(ETagValue::Real(21.5)),
+                Ok(ETagValue::Bool(true)),
+                Ok(ETagValue::Int(2)),
+                Ok(ETagValue::Int(8)),
+                Ok(ETagValue::Int(260)),
+                Ok(ETagValue::Int(-300)),
+                Err(String::from("BACnet error property/unknown-property")),
+                Err(String::from("BACnet error object/unknown-object")),
+            ]
+        );
+        let requests = device.lock().unwrap().requests.clone();
+        requests
+    }
+
+    #[test]
+    fn reads_with_read_property_multiple() {
+        assert_eq!(read_back(true), vec![SERVICE_READ_PROPERTY_MULTIPLE]);
+    }
+
+    #[test]
+    fn falls_back_to_read_property() {
+        let requests = read_back(false);
+        assert_eq!(requests[0], SERVICE_READ_PROPERTY_MULTIPLE);
+        assert_eq!(requests[1..], [SERVICE_READ_PROPERTY; 8]);
+    }
+
+    #[test]
+    fn writes_present_values_in_the_object_datatype() {
+        let (host, device) = server(device(true));
+        let client = client(&host);
+        let writes = vec![
+            (tag("AO:4/present-value", ETagtype::INT), ETagValue::Int(40)),
+            (
+                tag("AI:1/present-value@8", ETagtype::DINT),
+                ETagValue::Int(-3),
+            ),
+            (
+                tag("BV:2/present-value", ETagtype::BOOL),
+                ETagValue::Bool(false),
+            ),
+            (tag("MV:3/present-value", ETagtype::INT), ETagValue::Int(4)),
+            (
+                tag("IV:6/present-value", ETagtype::DINT),
+                ETagValue::Int(-70_000),
+            ),
+            (
+                tag("AI:9/present-value", ETagtype::REAL),
+                ETagValue::Real(1.0),
+            ),
+        ];
+        let written = client.write_list(&writes).unwrap();
+        assert_eq!(
+            written[..5],
+            [Ok(true), Ok(true), Ok(true), Ok(true), Ok(true)]
+        );
+        assert_eq!(
+            written[5],
+            Err(String::from("BACnet error object/unknown-object"))
+        );
+        {
+            let properties = &device.lock().unwrap().properties;
+            let stored = |object, property| properties[&(object, property)];
+            assert_eq!(
+                stored(object(1, 4), PROP_PRESENT_VALUE),
+                (Value::Real(40.0), Some(16))
+            );
+            assert_eq!(
+                stored(object(0, 1), PROP_PRESENT_VALUE),
+                (Value::Real(-3.0), Some(8))
+            );
+            assert_eq!(
+                stored(object(5, 2), PROP_PRESENT_VALUE),
+                (Value::Enumerated(0), Some(16))
+            );
+            assert_eq!(
+                stored(object(19, 3), PROP_PRESENT_VALUE),
+                (Value::Unsigned(4), Some(16))
+            );
+            assert_eq!(
+                stored(object(45, 6), PROP_PRESENT_VALUE),
+                (Value::Signed(-70_000), Some(16))
+            );
+        }
+        assert_eq!(client.relinquish(&writes[0].0), Ok(true));
+        assert_eq!(
+            device.lock().unwrap().properties[&(object(1, 4), PROP_PRESENT_VALUE)],
+            (Value::Null, Some(16))
+        );
+    }
+}
@@ -0,0 +1,737 @@
+use super::registry::{self, param, Registry};
+use super::{ETag, ETagRW, ETagValue, ETagtype};
+use log::info;
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const ENIP_PORT: u16 = 44818;
+const ENIP_HEADER_LEN: usize = 24;
+const ENIP_REGISTER_SESSION: u16 = 0x65;
+const ENIP_UNREGISTER_SESSION: u16 = 0x66;
+const ENIP_SEND_RR_DATA: u16 = 0x6F;
+const CPF_NULL_ADDRESS: u16 = 0x0000;
+const CPF_UNCONNECTED_DATA: u16 = 0x00B2;
+const CIP_READ_TAG: u8 = 0x4C;
+const CIP_WRITE_TAG: u8 = 0x4D;
+const CIP_MULTIPLE_SERVICE: u8 = 0x0A;
+const CIP_UNCONNECTED_SEND: u8 = 0x52;
+const CIP_REPLY: u8 = 0x80;
+const CIP_MESSAGE_ROUTER: [u8; 4] = [0x20, 0x02, 0x24, 0x01];
+const CIP_CONNECTION_MANAGER: [u8; 4] = [0x20, 0x06, 0x24, 0x01];
+// Unconnected messages are limited to 504 bytes on most Logix controllers
+const CIP_MAX_MESSAGE: usize = 480;
+
+pub const CIP_BOOL: u16 = 0xC1;
+pub const CIP_SINT: u16 = 0xC2;
+pub const CIP_INT: u16 = 0xC3;
+pub const CIP_DINT: u16 = 0xC4;
+pub const CIP_LINT: u16 = 0xC5;
+pub const CIP_USINT: u16 = 0xC6;
+pub const CIP_UINT: u16 = 0xC7;
+pub const CIP_UDINT: u16 = 0xC8;
+pub const CIP_REAL: u16 = 0xCA;
+pub const CIP_LREAL: u16 = 0xCB;
+pub const CIP_DWORD: u16 = 0xD3;
+
+struct Connection {
+    stream: TcpStream,
+    session: u32,
+    broken: bool,
+}
+
+#[derive(Debug)]
+pub struct Client {
+    host: String,
+    // Backplane slot of the controller, None for controllers addressed
+    // directly (CompactLogix built-in port, Micro800)
+    slot: Option<u8>,
+    timeout: Duration,
+    conn: Mutex<Option<Connection>>,
+}
+
+impl std::fmt::Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Connection")
+            .field("peer", &self.stream.peer_addr().ok())
+            .field("session", &self.session)
+            .finish()
+    }
+}
+
+// Tag name as a CIP symbolic path: `Program:Main.Counts[3].Value`
+pub fn tag_path(name: &str) -> Result<Vec<u8>, String> {
+    let invalid = || format!("Invalid tag name {}", name);
+    let mut path = Vec::new();
+    for segment in name.split('.') {
+        let (symbol, indexes) = match segment.find('[') {
+            Some(i) if segment.ends_with(']') => {
+                (&segment[..i], &segment[i + 1..segment.len() - 1])
+            }
+            Some(_) => return Err(invalid()),
+            None => (segment, ""),
+        };
+        if symbol.is_empty() || symbol.len() > 255 {
+            return Err(invalid());
+        }
+        path.push(0x91);
+        path.push(symbol.len() as u8);
+        path.extend_from_slice(symbol.as_bytes());
+        if symbol.len() % 2 == 1 {
+            path.push(0x00);
+        }
+        for index in indexes.split(',').filter(|i| !i.is_empty()) {
+            let index: u32 = index.trim().parse().map_err(|_| invalid())?;
+            if index <= 0xFF {
+                path.extend_from_slice(&[0x28, index as u8]);
+            } else if index <= 0xFFFF {
+                path.extend_from_slice(&[0x29, 0x00]);
+                path.extend_from_slice(&(index as u16).to_le_bytes());
+            } else {
+                path.extend_from_slice(&[0x2A, 0x00]);
+                path.extend_from_slice(&index.to_le_bytes());
+            }
+        }
+    }
+    Ok(path)
+}
+
+fn request(service: u8, path: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut req = vec![service, (path.len() / 2) as u8];
+    req.extend_from_slice(path);
+    req.extend_from_slice(data);
+    req
+}
+
+pub fn type_code(datatype: ETagtype) -> u16 {
+    match datatype {
+        ETagtype::BOOL => CIP_BOOL,
+        ETagtype::INT => CIP_INT,
+        ETagtype::DINT => CIP_DINT,
+        ETagtype::REAL => CIP_REAL,
+    }
+}
+
+pub fn decode_value(code: u16, data: &[u8], datatype: ETagtype) -> Result<ETagValue, String> {
+    let short = || String::from("Short CIP value");
+    let value = match code {
+        CIP_BOOL => ETagValue::Bool(*data.first().ok_or_else(short)? != 0),
+        CIP_SINT => ETagValue::Int(*data.first().ok_or_else(short)? as i8 as i64),
+        CIP_USINT => ETagValue::Int(*data.first().ok_or_else(short)? as i64),
+        CIP_INT | CIP_UINT => {
+            let v: [u8; 2] = data.get(0..2).ok_or_else(short)?.try_into().unwrap();
+            if code == CIP_INT {
+                ETagValue::Int(i16::from_le_bytes(v) as i64)
+            } else {
+                ETagValue::Int(u16::from_le_bytes(v) as i64)
+            }
+        }
+        CIP_DINT | CIP_UDINT | CIP_DWORD | CIP_REAL => {
+            let v: [u8; 4] = data.get(0..4).ok_or_else(short)?.try_into().unwrap();
+            match code {
+                CIP_DINT => ETagValue::Int(i32::from_le_bytes(v) as i64),
+                CIP_REAL => ETagValue::Real(f32::from_le_bytes(v) as f64),
+                _ => ETagValue::Int(u32::from_le_bytes(v) as i64),
+            }
+        }
+        CIP_LINT | CIP_LREAL => {
+            let v: [u8; 8] = data.get(0..8).ok_or_else(short)?.try_into().unwrap();
+            if code == CIP_LINT {
+                ETagValue::Int(i64::from_le_bytes(v))
+            } else {
+                ETagValue::Real(f64::from_le_bytes(v))
+            }
+        }
+        c => return Err(format!("Unsupported CIP type 0x{:04X}", c)),
+    };
+    match (datatype, &value) {
+        (ETagtype::BOOL, &ETagValue::Int(v)) => Ok(ETagValue::Bool(v != 0)),
+        (ETagtype::REAL, &ETagValue::Int(v)) => Ok(ETagValue::Real(v as f64)),
+        (ETagtype::BOOL, ETagValue::Bool(_)) => Ok(value),
+        (ETagtype::INT, ETagValue::Int(_)) | (ETagtype::DINT, ETagValue::Int(_)) => Ok(value),
+        (ETagtype::REAL, ETagValue::Real(_)) => Ok(value),
+        _ => Err(format!(
+            "CIP type 0x{:04X} does not match {:?}",
+            code, datatype
+        )),
+    }
+}
+
+pub fn encode_value(write: ETagValue, tag: &ETag) -> Result<Vec<u8>, String> {
+    let write = tag.datatype.coerce(write, tag.range)?;
+    let mut data = type_code(tag.datatype).to_le_bytes().to_vec();
+    data.extend_from_slice(&1u16.to_le_bytes());
+    match (tag.datatype, write) {
+        (ETagtype::BOOL, ETagValue::Bool(v)) => data.push(if v { 0xFF } else { 0x00 }),
+        (ETagtype::INT, ETagValue::Int(v)) => data.extend_from_slice(&(v as i16).to_le_bytes()),
+        (ETagtype::DINT, ETagValue::Int(v)) => data.extend_from_slice(&(v as i32).to_le_bytes()),
+        (ETagtype::REAL, ETagValue::Real(v)) => data.extend_from_slice(&(v as f32).to_le_bytes()),
+        _ => return Err(String::from("Invalid datatype for write value")),
+    }
+    Ok(data)
+}
+
+// Splits a reply into its general status and the data that follows
+fn reply_data(service: u8, reply: &[u8]) -> Result<&[u8], String> {
+    if reply.len() < 4 || reply[0] != service | CIP_REPLY {
+        return Err(String::from("Invalid CIP reply"));
+    }
+    let ext = reply[3] as usize * 2;
+    if reply.len() < 4 + ext {
+        return Err(String::from("Invalid CIP reply"));
+    }
+    match reply[2] {
+        0x00 => Ok(&reply[4 + ext..]),
+        // Partial transfer and embedded service errors carry data too
+        0x06 | 0x1E => Ok(&reply[4 + ext..]),
+        status => Err(status_text(status)),
+    }
+}
+
+pub fn status_text(status: u8) -> String {
+    match status {
+        0x04 => String::from("Path segment error"),
+        0x05 => String::from("Path destination unknown"),
+        0x06 => String::from("Partial transfer"),
+        0x08 => String::from("Service not supported"),
+        0x0A => String::from("Attribute list error"),
+        0x13 => String::from("Not enough data"),
+        0x1E => String::from("Embedded service error"),
+        0x26 => String::from("Path size invalid"),
+        0xFF => String::from("General error"),
+        s => format!("CIP status 0x{:02X}", s),
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self {
+            host: String::new(),
+            slot: Some(0),
+            timeout: Duration::from_secs(3),
+            conn: Mutex::new(None),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn connect(&mut self, host: &str, slot: Option<u8>) {
+        self.host = host.to_owned();
+        self.slot = slot;
+        match self.open(host) {
+            Ok(conn) => {
+                info!("EtherNet/IP session {:08X} on {}", conn.session, host);
+                *self.conn.lock().unwrap() = Some(conn);
+            }
+            Err(err) => {
+                info!("Connect to {} failed: {}", host, err);
+                *self.conn.lock().unwrap() = None;
+            }
+        }
+    }
+
+    pub fn close(&mut self) {
+        if let Some(mut conn) = self.conn.lock().unwrap().take() {
+            let _ = conn.send(ENIP_UNREGISTER_SESSION, &[]);
+        }
+    }
+
+    pub fn connected(&mut self) -> bool {
+        self.conn.lock().unwrap().is_some()
+    }
+
+    fn open(&self, host: &str) -> Result<Connection, String> {
+        let addr = if host.contains(':') {
+            host.to_socket_addrs()
+        } else {
+            (host, ENIP_PORT).to_socket_addrs()
+        }
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("Cannot resolve {}", host))?;
+        let stream = TcpStream::connect_timeout(&addr, self.timeout).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(|e| e.to_string())?;
+        stream
+            .set_write_timeout(Some(self.timeout))
+            .map_err(|e| e.to_string())?;
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        let mut conn = Connection {
+            stream,
+            session: 0,
+            broken: false,
+        };
+        // Protocol version 1, no options
+        conn.send(ENIP_REGISTER_SESSION, &[0x01, 0x00, 0x00, 0x00])?;
+        let (header, _) = conn.recv()?;
+        conn.session = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        Ok(conn)
+    }
+
+    fn with_conn<T, F>(&self, f: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, String>,
+    {
+        let mut guard = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let result = match guard.as_mut() {
+            Some(conn) => f(conn),
+            None => return Err(String::from("Not connected")),
+        };
+        if guard.as_ref().is_some_and(|c| c.broken) {
+            guard.take();
+        }
+        result
+    }
+
+    // Sends one request to the controller's message router, through the
+    // backplane when a slot is configured
+    fn message(&self, conn: &mut Connection, req: &[u8]) -> Result<Vec<u8>, String> {
+        let msg = match self.slot {
+            Some(slot) => {
+                let mut msg = request(CIP_UNCONNECTED_SEND, &CIP_CONNECTION_MANAGER, &[0x0A, 0x0E]);
+                msg.extend_from_slice(&(req.len() as u16).to_le_bytes());
+                msg.extend_from_slice(req);
+                if req.len() % 2 == 1 {
+                    msg.push(0x00);
+                }
+                msg.extend_from_slice(&[0x01, 0x00, 0x01, slot]);
+                msg
+            }
+            None => req.to_vec(),
+        };
+        conn.send_rr_data(&msg)
+    }
+
+    // Packs the requests into as few Multiple Service Packets as fit in a
+    // message and returns one reply per request
+    fn batch(&self, reqs: &[Vec<u8>]) -> Result<Vec<Result<Vec<u8>, String>>, String> {
+        let mut replies = Vec::with_capacity(reqs.len());
+        let mut start = 0;
+        while start < reqs.len() {
+            let mut end = start;
+            let mut size = 8;
+            while end < reqs.len()
+                && (end == start || size + 2 + reqs[end].len() <= CIP_MAX_MESSAGE)
+            {
+                size += 2 + reqs[end].len();
+                end += 1;
+            }
+            let chunk = &reqs[start..end];
+            if chunk.len() == 1 {
+                replies.push(Ok(self.with_conn(|conn| self.message(conn, &chunk[0]))?));
+            } else {
+                let mut data = (chunk.len() as u16).to_le_bytes().to_vec();
+                let mut offset = 2 + 2 * chunk.len();
+                for req in chunk {
+                    data.extend_from_slice(&(offset as u16).to_le_bytes());
+                    offset += req.len();
+                }
+                for req in chunk {
+                    data.extend_from_slice(req);
+                }
+                let msg = request(CIP_MULTIPLE_SERVICE, &CIP_MESSAGE_ROUTER, &data);
+                let reply = self.with_conn(|conn| self.message(conn, &msg))?;
+                let body = reply_data(CIP_MULTIPLE_SERVICE, &reply)?;
+                replies.extend(split_multiple(body, chunk.len())?.into_iter().map(Ok));
+            }
+            start = end;
+        }
+        Ok(replies)
+    }
+}
+
+fn split_multiple(body: &[u8], count: usize) -> Result<Vec<Vec<u8>>, String> {
+    let invalid = || String::from("Invalid Multiple Service reply");
+    if body.len() < 2 + 2 * count || u16::from_le_bytes([body[0], body[1]]) as usize != count {
+        return Err(invalid());
+    }
+    let offsets: Vec<usize> = (0..count)
+        .map(|i| u16::from_le_bytes([body[2 + i * 2], body[3 + i * 2]]) as usize)
+        .collect();
+    (0..count)
+        .map(|i| {
+            let end = offsets.get(i + 1).copied().unwrap_or(body.len());
+            body.get(offsets[i]..end)
+                .map(|r| r.to_vec())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+impl Connection {
+    // The stream is left in an unknown state after an I/O error
+    fn io<T>(&mut self, r: std::io::Result<T>) -> Result<T, String> {
+        r.map_err(|e| {
+            self.broken = true;
+            e.to_string()
+        })
+    }
+
+    fn send(&mut self, command: u16, data: &[u8]) -> Result<(), String> {
+        let mut frame = Vec::with_capacity(ENIP_HEADER_LEN + data.len());
+        frame.extend_from_slice(&command.to_le_bytes());
+        frame.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        frame.extend_from_slice(&self.session.to_le_bytes());
+        frame.extend_from_slice(&[0u8; 16]);
+        frame.extend_from_slice(data);
+        let r = self.stream.write_all(&frame);
+        self.io(r)
+    }
+
+    fn recv(&mut self) -> Result<([u8; ENIP_HEADER_LEN], Vec<u8>), String> {
+        let mut header = [0u8; ENIP_HEADER_LEN];
+        let r = self.stream.read_exact(&mut header);
+        self.io(r)?;
+        let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+        let mut data = vec![0u8; len];
+        let r = self.stream.read_exact(&mut data);
+        self.io(r)?;
+        let status = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if status != 0 {
+            return Err(format!("EtherNet/IP status 0x{:08X}", status));
+        }
+        Ok((header, data))
+    }
+
+    fn send_rr_data(&mut self, msg: &[u8]) -> Result<Vec<u8>, String> {
+        let mut data = vec![0u8; 6];
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&CPF_NULL_ADDRESS.to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data.extend_from_slice(&CPF_UNCONNECTED_DATA.to_le_bytes());
+        data.extend_from_slice(&(msg.len() as u16).to_le_bytes());
+        data.extend_from_slice(msg);
+        self.send(ENIP_SEND_RR_DATA, &data)?;
+
+        let (header, data) = self.recv()?;
+        if u16::from_le_bytes([header[0], header[1]]) != ENIP_SEND_RR_DATA {
+            self.broken = true;
+            return Err(String::from("Unexpected EtherNet/IP reply"));
+        }
+        // Walk the common packet format items up to the unconnected data
+        let count = data
+            .get(6..8)
+            .map_or(0, |c| u16::from_le_bytes([c[0], c[1]]));
+        let mut pos = 8;
+        for _ in 0..count {
+            let item = data.get(pos..pos + 4).ok_or("Invalid CPF item")?;
+            let kind = u16::from_le_bytes([item[0], item[1]]);
+            let len = u16::from_le_bytes([item[2], item[3]]) as usize;
+            let body = data.get(pos + 4..pos + 4 + len).ok_or("Invalid CPF item")?;
+            if kind == CPF_UNCONNECTED_DATA {
+                return Ok(body.to_vec());
+            }
+            pos += 4 + len;
+        }
+        Err(String::from("No data in EtherNet/IP reply"))
+    }
+}
+
+impl ETagRW for Client {
+    fn read_tag(&self, tag: &ETag) -> Result<ETagValue, String> {
+        self.read_list(&vec![tag.clone()])?.pop().unwrap()
+    }
+    fn read_list(&self, tags: &Vec<ETag>) -> Result<Vec<Result<ETagValue, String>>, String> {
+        let reqs = tags
+            .iter()
+            .map(|tag| tag_path(&tag.address).map(|p| request(CIP_READ_TAG, &p, &[0x01, 0x00])))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        Ok(self
+            .batch(&reqs)?
+            .into_iter()
+            .zip(tags)
+            .map(|(reply, tag)| {
+                let reply = reply?;
+                let data = reply_data(CIP_READ_TAG, &reply)?;
+                if data.len() < 2 {
+                    return Err(String::from("Short CIP value"));
+                }
+                let code = u16::from_le_bytes([data[0], data[1]]);
+                decode_value(code, &data[2..], tag.datatype)
+            })
+            .collect())
+    }
+    fn write_tag(&self, tag: &ETag, write: ETagValue) -> Result<bool, String> {
+        self.write_list(&vec![(tag.clone(), write)])?.pop().unwrap()
+    }
+    fn write_list(
+        &self,
+        tags: &Vec<(ETag, ETagValue)>,
+    ) -> Result<Vec<Result<bool, String>>, String> {
+        let paths = tags
+            .iter()
+            .map(|t| tag_path(&t.0.address))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        let converted: Vec<Result<Vec<u8>, String>> = tags
+            .iter()
+            .map(|t| encode_value(t.1.clone(), &t.0))
+            .collect();
+        let reqs: Vec<Vec<u8>> = paths
+            .iter()
+            .zip(converted.iter())
+            .filter_map(|(path, data)| {
+                data.as_ref()
+                    .ok()
+                    .map(|data| request(CIP_WRITE_TAG, path, data))
+            })
+            .collect();
+        let mut written = self.batch(&reqs)?.into_iter();
+        Ok(converted
+            .into_iter()
+            .map(|data| match data {
+                Ok(_) => written
+                    .next()
+                    .unwrap()
+                    .and_then(|reply| reply_data(CIP_WRITE_TAG, &reply).map(|_| true)),
+                Err(err) => Err(err),
+            })
+            .collect())
+    }
+}
+
+// cip://host[:port]?slot=0, slot=none for a device without backplane routing
+pub fn register(registry: &mut Registry) {
+    registry.register_device(
+        "cip",
+        |url| {
+            let host = registry::host(url)?;
+            let slot = match param(url, "slot", String::from("0"))?.as_str() {
+                "none" => None,
+                slot => Some(
+                    slot.parse()
+                        .map_err(|_| format!("Invalid slot {} in {}", slot, url))?,
+                ),
+            };
+            let mut client = Client::new();
+            client.connect(host.as_str(), slot);
+            if client.connected() {
+                Ok(client)
+            } else {
+                Err(format!("Connect to {} failed", host))
+            }
+        },
+        Client::connected,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plc_driver::tests::tag;
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    type Tags = HashMap<String, (u16, Vec<u8>)>;
+
+    // Symbol of a single segment path, the only kind these tests use
+    fn symbol(path: &[u8]) -> String {
+        String::from_utf8(path[2..2 + path[1] as usize].to_vec()).unwrap()
+    }
+
+    fn execute(req: &[u8], tags: &mut Tags) -> Vec<u8> {
+        let path_len = req[1] as usize * 2;
+        let path = &req[2..2 + path_len];
+        let data = &req[2 + path_len..];
+        match req[0] {
+            // The routed request is answered as if it came directly
+            CIP_UNCONNECTED_SEND => {
+                let len = u16::from_le_bytes([data[2], data[3]]) as usize;
+                execute(&data[4..4 + len], tags)
+            }
+            CIP_MULTIPLE_SERVICE => {
+                let count = u16::from_le_bytes([data[0], data[1]]) as usize;
+                let offsets: Vec<usize> = (0..count)
+                    .map(|i| u16::from_le_bytes([data[2 + i * 2], data[3 + i * 2]]) as usize)
+                    .chain(std::iter::once(data.len()))
+                    .collect();
+                let replies: Vec<Vec<u8>> = (0..count)
+                    .map(|i| execute(&data[offsets[i]..offsets[i + 1]], tags))
+                    .collect();
+                let mut reply = vec![CIP_MULTIPLE_SERVICE | CIP_REPLY, 0, 0, 0];
+                reply.extend_from_slice(&(count as u16).to_le_bytes());
+                let mut offset = 2 + 2 * count;
+                for r in &replies {
+                    reply.extend_from_slice(&(offset as u16).to_le_bytes());
+                    offset += r.len();
+                }
+                replies.iter().for_each(|r| reply.extend_from_slice(r));
+                reply
+            }
+            CIP_READ_TAG => match tags.get(&symbol(path)) {
+                Some((code, value)) => {
+                    let mut reply = vec![CIP_READ_TAG | CIP_REPLY, 0, 0, 0];
+                    reply.extend_from_slice(&code.to_le_bytes());
+                    reply.extend_from_slice(value);
+                    reply
+                }
+                None => vec![CIP_READ_TAG | CIP_REPLY, 0, 0x05, 0],
+            },
+            CIP_WRITE_TAG => match tags.get_mut(&symbol(path)) {
+                Some((code, value)) if code.to_le_bytes() == data[0..2] => {
+                    *value = data[4..].to_vec();
+                    vec![CIP_WRITE_TAG | CIP_REPLY, 0, 0, 0]
+                }
+                Some(_) => vec![CIP_WRITE_TAG | CIP_REPLY, 0, 0xFF, 0],
+                None => vec![CIP_WRITE_TAG | CIP_REPLY, 0, 0x05, 0],
+            },
+            _ => vec![req[0] | CIP_REPLY, 0, 0x08, 0],
+        }
+    }
+
+    fn reply(stream: &mut TcpStream, header: &[u8], data: &[u8]) {
+        let mut frame = header[0..2].to_vec();
+        frame.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        frame.extend_from_slice(&0x0102_0304u32.to_le_bytes());
+        frame.extend_from_slice(&[0u8; 16]);
+        frame.extend_from_slice(data);
+        stream.write_all(&frame).unwrap();
+    }
+
+    // Answers register session and unconnected SendRRData messages
+    fn serve(mut stream: TcpStream, tags: Arc<Mutex<Tags>>) {
+        let mut header = [0u8; ENIP_HEADER_LEN];
+        while stream.read_exact(&mut header).is_ok() {
+            let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+            let mut data = vec![0u8; len];
+            stream.read_exact(&mut data).unwrap();
+            match u16::from_le_bytes([header[0], header[1]]) {
+                ENIP_REGISTER_SESSION => reply(&mut stream, &header, &data),
+                ENIP_SEND_RR_DATA => {
+                    // Null address item followed by the unconnected data item
+                    let msg = &data[16..];
+                    let body = execute(msg, &mut tags.lock().unwrap());
+                    let mut out = data[0..12].to_vec();
+                    out.extend_from_slice(&CPF_UNCONNECTED_DATA.to_le_bytes());
+                    out.extend_from_slice(&(body.len() as u16).to_le_bytes());
+                    out.extend_from_slice(&body);
+                    reply(&mut stream, &header, &out);
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn server(tags: Tags) -> (String, Arc<Mutex<Tags>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let tags = Arc::new(Mutex::new(tags));
+        let shared = tags.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                serve(stream.unwrap(), shared.clone());
+            }
+        });
+        (host, tags)
+    }
+
+    fn client(host: &str, slot: Option<u8>) -> Client {
+        let mut client = Client::new();
+        client.connect(host, slot);
+        assert!(client.connected());
+        client
+    }
+
+    #[test]
+    fn reads_through_the_backplane() {
+        let mut tags = Tags::new();
+        tags.insert("Run".into(), (CIP_BOOL, vec![0x01]));
+        tags.insert("Speed".into(), (CIP_INT, (-300i16).to_le_bytes().to_vec()));
+        tags.insert(
+            "Count".into(),
+            (CIP_UDINT, 70_000u32.to_le_bytes().to_vec()),
+        );
+        tags.insert("Level".into(), (CIP_REAL, 2.5f32.to_le_bytes().to_vec()));
+        let (host, _) = server(tags);
+        let client = client(&host, Some(0));
+        assert_eq!(
+            client
+                .read_list(&vec![
+                    tag("Run", ETagtype::BOOL),
+                    tag("Speed", ETagtype::INT),
+                    tag("Count", ETagtype::DINT),
+                    tag("Level", ETagtype::REAL),
+                    tag("Missing", ETagtype::INT),
+                ])
+                .unwrap(),
+            vec![
+                Ok(ETagValue::Bool(true)),
+                Ok(ETagValue::Int(-300)),
+                Ok(ETagValue::Int(70_000)),
+                Ok(ETagValue::Real(2.5)),
+                Err(String::from("Path destination unknown")),
+            ]
+        );
+        assert_eq!(
+            client.read_tag(&tag("Speed", ETagtype::INT)),
+            Ok(ETagValue::Int(-300))
+        );
+    }
+
+    #[test]
+    fn batches_split_at_the_message_size() {
+        let names: Vec<String> = (0..40)
+            .map(|i| format!("Station_{:02}_Accumulated_Count", i))
+            .collect();
+        let tags = names
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.clone(), (CIP_DINT, (i as i32).to_le_bytes().to_vec())))
+            .collect();
+        let (host, _) = server(tags);
+        let client = client(&host, None);
+        let values = client
+            .read_list(&names.iter().map(|n| tag(n, ETagtype::DINT)).collect())
+            .unwrap();
+        let expected: Vec<Result<ETagValue, String>> =
+            (0..40).map(|i| Ok(ETagValue::Int(i))).collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn write_then_read_back() {
+        let mut tags = Tags::new();
+        tags.insert("Run".into(), (CIP_BOOL, vec![0x00]));
+        tags.insert("Setpoint".into(), (CIP_DINT, vec![0; 4]));
+        tags.insert("Level".into(), (CIP_REAL, vec![0; 4]));
+        let (host, shared) = server(tags);
+        let client = client(&host, Some(1));
+        let writes = vec![
+            (tag("Run", ETagtype::BOOL), ETagValue::Bool(true)),
+            (tag("Setpoint", ETagtype::DINT), ETagValue::Int(-12)),
+            (tag("Level", ETagtype::REAL), ETagValue::Real(0.5)),
+            (tag("Missing", ETagtype::INT), ETagValue::Int(1)),
+            (tag("Run", ETagtype::BOOL), ETagValue::Real(1.0)),
+        ];
+        let written = client.write_list(&writes).unwrap();
+        assert_eq!(
+            written[..4],
+            [
+                Ok(true),
+                Ok(true),
+                Ok(true),
+                Err(String::from("Path destination unknown"))
+            ]
+        );
+        assert!(written[4].is_err());
+        assert_eq!(shared.lock().unwrap()["Run"].1, vec![0xFF]);
+        let read: Vec<ETag> = writes[..3].iter().map(|w| w.0.clone()).collect();
+        let values: Vec<Result<ETagValue, String>> =
+            writes[..3].iter().map(|w| Ok(w.1.clone())).collect();
+        assert_eq!(client.read_list(&read).unwrap(), values);
+    }
+}
@@ -0,0 +1,836 @@
+use super::registry::{self, param, Registry};
+use super::{ETag, ETagRW, ETagValue, ETagtype};
+use log::{info, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const FINS_PORT: u16 = 9600;
+const FINS_HEADER_LEN: usize = 10;
+const FINS_TCP_MAGIC: &[u8; 4] = b"FINS";
+const FINS_TCP_NODE_REQUEST: u32 = 0;
+const FINS_TCP_NODE_RESPONSE: u32 = 1;
+const FINS_TCP_FRAME: u32 = 2;
+const CMD_MEMORY_READ: [u8; 2] = [0x01, 0x01];
+const CMD_MEMORY_WRITE: [u8; 2] = [0x01, 0x02];
+const CMD_MULTIPLE_READ: [u8; 2] = [0x01, 0x04];
+// Items of one multiple memory area read
+const MAX_MULTIPLE_ITEMS: usize = 128;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FinsArea {
+    CIO,
+    WR,
+    HR,
+    AR,
+    DM,
+}
+
+impl FinsArea {
+    pub fn word_code(&self) -> u8 {
+        match self {
+            FinsArea::CIO => 0xB0,
+            FinsArea::WR => 0xB1,
+            FinsArea::HR => 0xB2,
+            FinsArea::AR => 0xB3,
+            FinsArea::DM => 0x82,
+        }
+    }
+
+    pub fn bit_code(&self) -> u8 {
+        match self {
+            FinsArea::CIO => 0x30,
+            FinsArea::WR => 0x31,
+            FinsArea::HR => 0x32,
+            FinsArea::AR => 0x33,
+            FinsArea::DM => 0x02,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FinsAddress {
+    pub area: FinsArea,
+    pub word: u16,
+    pub bit: Option<u8>,
+    pub datatype: ETagtype,
+}
+
+pub fn address_regex() -> Regex {
+    Regex::new(r"^(CIO|D|W|H|A)(\d{1,5})(?:\.(\d{2}))?$").unwrap()
+}
+
+impl FinsAddress {
+    // `D100`, `W10.03`, `H5`, `CIO0.00`, `A20`
+    pub fn parse(reg: &Regex, address: &str, datatype: ETagtype) -> Result<Self, String> {
+        let invalid = || format!("Invalid FINS address {}", address);
+        let r = reg.captures(address).ok_or_else(invalid)?;
+        let area = match r.get(1).unwrap().as_str() {
+            "CIO" => FinsArea::CIO,
+            "D" => FinsArea::DM,
+            "W" => FinsArea::WR,
+            "H" => FinsArea::HR,
+            _ => FinsArea::AR,
+        };
+        let word: u16 = r.get(2).unwrap().as_str().parse().map_err(|_| invalid())?;
+        let bit = r.get(3).map(|b| b.as_str().parse::<u8>().unwrap());
+        let valid = match (datatype, bit) {
+            (ETagtype::BOOL, Some(b)) => b < 16,
+            (ETagtype::INT, None) => true,
+            (ETagtype::DINT, None) | (ETagtype::REAL, None) => word < u16::MAX,
+            _ => false,
+        };
+        if valid {
+            Ok(Self {
+                area,
+                word,
+                bit,
+                datatype,
+            })
+        } else {
+            Err(invalid())
+        }
+    }
+
+    fn words(&self) -> u16 {
+        match self.datatype {
+            ETagtype::DINT | ETagtype::REAL => 2,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FinsConfig {
+    pub tcp: bool,
+    pub dest_network: u8,
+    // 0 with FINS/TCP takes the node announced by the PLC
+    pub dest_node: u8,
+    pub dest_unit: u8,
+    pub src_network: u8,
+    // 0 with FINS/TCP takes the node assigned by the PLC
+    pub src_node: u8,
+    pub src_unit: u8,
+}
+
+enum Link {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+struct Connection {
+    link: Link,
+    dest_node: u8,
+    src_node: u8,
+    sid: u8,
+    broken: bool,
+}
+
+#[derive(Debug)]
+pub struct Client {
+    host: String,
+    config: FinsConfig,
+    timeout: Duration,
+    reg: Regex,
+    conn: Mutex<Option<Connection>>,
+}
+
+impl std::fmt::Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Connection")
+            .field("dest_node", &self.dest_node)
+            .field("src_node", &self.src_node)
+            .finish()
+    }
+}
+
+pub fn end_code_text(code: u16) -> String {
+    match code >> 8 {
+        0x01 => format!("Local node error 0x{:04X}", code),
+        0x02 => format!("Destination node error 0x{:04X}", code),
+        0x03 => format!("Controller error 0x{:04X}", code),
+        0x04 => format!("Service unsupported 0x{:04X}", code),
+        0x05 => format!("Routing table error 0x{:04X}", code),
+        0x10 => format!("Command format error 0x{:04X}", code),
+        0x11 => format!("Parameter error 0x{:04X}", code),
+        0x20 | 0x21 => format!("Read or write not possible 0x{:04X}", code),
+        0x22 => format!("Not executable in current mode 0x{:04X}", code),
+        _ => format!("FINS end code 0x{:04X}", code),
+    }
+}
+
+impl Client {
+    pub fn new(config: FinsConfig) -> Self {
+        Self {
+            host: String::new(),
+            config,
+            timeout: Duration::from_secs(3),
+            reg: address_regex(),
+            conn: Mutex::new(None),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    // `host` is `address` or `address:port`
+    pub fn connect(&mut self, host: &str) {
+        self.host = host.to_owned();
+        match self.open(host) {
+            Ok(conn) => {
+                info!(
+                    "FINS {} connected to {}, node {} -> {}",
+                    if self.config.tcp { "TCP" } else { "UDP" },
+                    host,
+                    conn.src_node,
+                    conn.dest_node
+                );
+                *self.conn.lock().unwrap() = Some(conn);
+            }
+            Err(err) => {
+                info!("Connect to {} failed: {}", host, err);
+                *self.conn.lock().unwrap() = None;
+            }
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.conn.lock().unwrap().take();
+    }
+
+    pub fn connected(&mut self) -> bool {
+        self.conn.lock().unwrap().is_some()
+    }
+
+    pub fn conv_address(&self, address: &str, datatype: ETagtype) -> Result<FinsAddress, String> {
+        FinsAddress::parse(&self.reg, address, datatype)
+    }
+
+    fn open(&self, host: &str) -> Result<Connection, String> {
+        let addr: SocketAddr = if host.contains(':') {
+            host.to_socket_addrs()
+        } else {
+            (host, FINS_PORT).to_socket_addrs()
+        }
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("Cannot resolve {}", host))?;
+
+        if !self.config.tcp {
+            let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+            socket.connect(addr).map_err(|e| e.to_string())?;
+            socket
+                .set_read_timeout(Some(self.timeout))
+                .map_err(|e| e.to_string())?;
+            // Without node configuration use the last octet of the IPs,
+            // the usual automatic address conversion of Omron CPUs
+            let dest_node = match (self.config.dest_node, addr) {
+                (0, SocketAddr::V4(a)) => a.ip().octets()[3],
+                (n, _) => n,
+            };
+            let src_node = match (self.config.src_node, socket.local_addr()) {
+                (0, Ok(SocketAddr::V4(a))) => a.ip().octets()[3],
+                (n, _) => n,
+            };
+            return Ok(Connection {
+                link: Link::Udp(socket),
+                dest_node,
+                src_node,
+                sid: 0,
+                broken: false,
+            });
+        }
+
+        let stream = TcpStream::connect_timeout(&addr, self.timeout).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(|e| e.to_string())?;
+        stream
+            .set_write_timeout(Some(self.timeout))
+            .map_err(|e| e.to_string())?;
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        let mut conn = Connection {
+            link: Link::Tcp(stream),
+            dest_node: self.config.dest_node,
+            src_node: self.config.src_node,
+            sid: 0,
+            broken: false,
+        };
+        let (command, data) = conn.tcp_exchange(
+            FINS_TCP_NODE_REQUEST,
+            &(self.config.src_node as u32).to_be_bytes(),
+        )?;
+        if command != FINS_TCP_NODE_RESPONSE || data.len() < 8 {
+            return Err(String::from("FINS/TCP node address exchange failed"));
+        }
+        conn.src_node = data[3];
+        if conn.dest_node == 0 {
+            conn.dest_node = data[7];
+        }
+        Ok(conn)
+    }
+
+    fn with_conn<T, F>(&self, f: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, String>,
+    {
+        let mut guard = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let result = match guard.as_mut() {
+            Some(conn) => f(conn),
+            None => return Err(String::from("Not connected")),
+        };
+        if guard.as_ref().is_some_and(|c| c.broken) {
+            guard.take();
+        }
+        result
+    }
+
+    // Sends a FINS command and returns the response data after the end code
+    pub fn command(&self, command: [u8; 2], data: &[u8]) -> Result<Vec<u8>, String> {
+        self.with_conn(|conn| {
+            conn.sid = conn.sid.wrapping_add(1);
+            let mut frame = vec![
+                0x80,
+                0x00,
+                0x02,
+                self.config.dest_network,
+                conn.dest_node,
+                self.config.dest_unit,
+                self.config.src_network,
+                conn.src_node,
+                self.config.src_unit,
+                conn.sid,
+            ];
+            frame.extend_from_slice(&command);
+            frame.extend_from_slice(data);
+            let resp = conn.exchange(&frame)?;
+            if resp.len() < FINS_HEADER_LEN + 4
+                || resp[FINS_HEADER_LEN..FINS_HEADER_LEN + 2] != command
+            {
+                return Err(String::from("Invalid FINS response"));
+            }
+            let code = u16::from_be_bytes([
+                resp[FINS_HEADER_LEN + 2] & 0x7F,
+                resp[FINS_HEADER_LEN + 3] & 0x3F,
+            ]);
+            if code != 0 {
+                return Err(end_code_text(code));
+            }
+            Ok(resp[FINS_HEADER_LEN + 4..].to_vec())
+        })
+    }
+
+    pub fn read_words(&self, area: FinsArea, word: u16, count: u16) -> Result<Vec<u16>, String> {
+        let mut data = vec![area.word_code()];
+        data.extend_from_slice(&word.to_be_bytes());
+        data.push(0x00);
+        data.extend_from_slice(&count.to_be_bytes());
+        let resp = self.command(CMD_MEMORY_READ, &data)?;
+        if resp.len() < count as usize * 2 {
+            return Err(String::from("Short FINS response"));
+        }
+        Ok(resp
+            .chunks(2)
+            .take(count as usize)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect())
+    }
+
+    pub fn write_words(&self, area: FinsArea, word: u16, values: &[u16]) -> Result<(), String> {
+        let mut data = vec![area.word_code()];
+        data.extend_from_slice(&word.to_be_bytes());
+        data.push(0x00);
+        data.extend_from_slice(&(values.len() as u16).to_be_bytes());
+        for v in values {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        self.command(CMD_MEMORY_WRITE, &data).map(|_| ())
+    }
+
+    pub fn write_bit(&self, area: FinsArea, word: u16, bit: u8, value: bool) -> Result<(), String> {
+        let mut data = vec![area.bit_code()];
+        data.extend_from_slice(&word.to_be_bytes());
+        data.extend_from_slice(&[bit, 0x00, 0x01, value as u8]);
+        self.command(CMD_MEMORY_WRITE, &data).map(|_| ())
+    }
+
+    // One multiple memory area read per chunk; a 32-bit value takes two
+    // word items, the low word first as Omron CPUs store it
+    fn read_addresses(
+        &self,
+        addrs: &[FinsAddress],
+    ) -> Result<Vec<Result<ETagValue, String>>, String> {
+        let mut items: Vec<(usize, u8, u16, u8)> = Vec::new();
+        for (i, addr) in addrs.iter().enumerate() {
+            match addr.bit {
+                Some(bit) => items.push((i, addr.area.bit_code(), addr.word, bit)),
+                None => {
+                    for w in 0..addr.words() {
+                        items.push((i, addr.area.word_code(), addr.word + w, 0));
+                    }
+                }
+            }
+        }
+        let mut raw: Vec<Vec<u8>> = vec![Vec::new(); addrs.len()];
+        for chunk in items.chunks(MAX_MULTIPLE_ITEMS) {
+            let mut data = Vec::with_capacity(chunk.len() * 4);
+            for (_, code, word, bit) in chunk {
+                data.push(*code);
+                data.extend_from_slice(&word.to_be_bytes());
+                data.push(*bit);
+            }
+            let resp = self.command(CMD_MULTIPLE_READ, &data)?;
+            let mut pos = 0;
+            for (i, code, _, _) in chunk {
+                let len = if *code & 0x80 != 0 { 2 } else { 1 };
+                match resp.get(pos..pos + 1 + len) {
+                    Some(item) if item[0] == *code => raw[*i].extend_from_slice(&item[1..]),
+                    _ => return Err(String::from("Invalid multiple memory area read response")),
+                }
+                pos += 1 + len;
+            }
+        }
+        Ok(addrs
+            .iter()
+            .zip(raw)
+            .map(|(addr, b)| match addr.datatype {
+                ETagtype::BOOL => Ok(ETagValue::Bool(b[0] & 0x01 != 0)),
+                ETagtype::INT => Ok(ETagValue::Int(i16::from_be_bytes([b[0], b[1]]) as i64)),
+                ETagtype::DINT => Ok(ETagValue::Int(
+                    i32::from_be_bytes([b[2], b[3], b[0], b[1]]) as i64
+                )),
+                ETagtype::REAL => Ok(ETagValue::Real(
+                    f32::from_be_bytes([b[2], b[3], b[0], b[1]]) as f64,
+                )),
+            })
+            .collect())
+    }
+
+    fn write_address(
+        &self,
+        tag: &ETag,
+        addr: &FinsAddress,
+        write: ETagValue,
+    ) -> Result<bool, String> {
+        let write = tag.datatype.coerce(write, tag.range)?;
+        match (addr.bit, write) {
+            (Some(bit), ETagValue::Bool(v)) => self.write_bit(addr.area, addr.word, bit, v)?,
+            (None, ETagValue::Int(v)) if tag.datatype == ETagtype::INT => {
+                self.write_words(addr.area, addr.word, &[v as i16 as u16])?
+            }
+            (None, ETagValue::Int(v)) => {
+                let v = v as i32 as u32;
+                self.write_words(addr.area, addr.word, &[v as u16, (v >> 16) as u16])?
+            }
+            (None, ETagValue::Real(v)) => {
+                let v = (v as f32).to_bits();
+                self.write_words(addr.area, addr.word, &[v as u16, (v >> 16) as u16])?
+            }
+            _ => return Err(String::from("Invalid datatype for write value")),
+        }
+        Ok(true)
+    }
+}
+
+impl Connection {
+    // The link is left in an unknown state after an I/O error
+    fn io<T>(&mut self, r: std::io::Result<T>) -> Result<T, String> {
+        r.map_err(|e| {
+            self.broken = true;
+            e.to_string()
+        })
+    }
+
+    fn tcp_exchange(&mut self, command: u32, data: &[u8]) -> Result<(u32, Vec<u8>), String> {
+        let mut frame = FINS_TCP_MAGIC.to_vec();
+        frame.extend_from_slice(&((8 + data.len()) as u32).to_be_bytes());
+        frame.extend_from_slice(&command.to_be_bytes());
+        frame.extend_from_slice(&0u32.to_be_bytes());
+        frame.extend_from_slice(data);
+        let stream = match &mut self.link {
+            Link::Tcp(stream) => stream,
+            Link::Udp(_) => unreachable!(),
+        };
+        let r = stream.write_all(&frame);
+        self.io(r)?;
+
+        let mut header = [0u8; 16];
+        let r = match &mut self.link {
+            Link::Tcp(stream) => stream.read_exact(&mut header),
+            Link::Udp(_) => unreachable!(),
+        };
+        self.io(r)?;
+        let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        if &header[0..4] != FINS_TCP_MAGIC || len < 8 {
+            self.broken = true;
+            return Err(String::from("Invalid FINS/TCP header"));
+        }
+        let error = u32::from_be_bytes([header[12], header[13], header[14], header[15]]);
+        let mut body = vec![0u8; len - 8];
+        let r = match &mut self.link {
+            Link::Tcp(stream) => stream.read_exact(&mut body),
+            Link::Udp(_) => unreachable!(),
+        };
+        self.io(r)?;
+        if error != 0 {
+            self.broken = true;
+            return Err(format!("FINS/TCP error 0x{:08X}", error));
+        }
+        let command = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+        Ok((command, body))
+    }
+
+    fn exchange(&mut self, frame: &[u8]) -> Result<Vec<u8>, String> {
+        let sid = self.sid;
+        if let Link::Tcp(_) = self.link {
+            let (command, resp) = self.tcp_exchange(FINS_TCP_FRAME, frame)?;
+            if command != FINS_TCP_FRAME {
+                self.broken = true;
+                return Err(String::from("Unexpected FINS/TCP command"));
+            }
+            if resp.len() >= FINS_HEADER_LEN && resp[9] != sid {
+                self.broken = true;
+                return Err(String::from("FINS response does not match request"));
+            }
+            return Ok(resp);
+        }
+
+        let r = match &self.link {
+            Link::Udp(socket) => socket.send(frame),
+            Link::Tcp(_) => unreachable!(),
+        };
+        self.io(r)?;
+        loop {
+            let mut buf = vec![0u8; 2048];
+            let r = match &self.link {
+                Link::Udp(socket) => socket.recv(&mut buf),
+                Link::Tcp(_) => unreachable!(),
+            };
+            // A UDP timeout only loses this request, the socket stays usable
+            let len = match r {
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Err(format!("No FINS response: {}", e));
+                }
+                r => self.io(r)?,
+            };
+            buf.truncate(len);
+            // A late answer to a timed out request may still arrive, the
+            // command was sent once so keep waiting for its own answer
+            if buf.len() >= FINS_HEADER_LEN && buf[9] != sid {
+                warn!("Dropping FINS response with SID {}", buf[9]);
+                continue;
+            }
+            return Ok(buf);
+        }
+    }
+}
+
+impl ETagRW for Client {
+    fn read_tag(&self, tag: &ETag) -> Result<ETagValue, String> {
+        let addr = self.conv_address(tag.address.as_str(), tag.datatype)?;
+        self.read_addresses(&[addr])?.pop().unwrap()
+    }
+    fn read_list(&self, tags: &Vec<ETag>) -> Result<Vec<Result<ETagValue, String>>, String> {
+        let addrs = tags
+            .iter()
+            .map(|tag| self.conv_address(tag.address.as_str(), tag.datatype))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        self.read_addresses(&addrs)
+    }
+    fn write_tag(&self, tag: &ETag, write: ETagValue) -> Result<bool, String> {
+        let addr = self.conv_address(tag.address.as_str(), tag.datatype)?;
+        self.write_address(tag, &addr, write)
+    }
+    fn write_list(
+        &self,
+        tags: &Vec<(ETag, ETagValue)>,
+    ) -> Result<Vec<Result<bool, String>>, String> {
+        let addrs = tags
+            .iter()
+            .map(|t| self.conv_address(t.0.address.as_str(), t.0.datatype))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        Ok(tags
+            .iter()
+            .zip(addrs)
+            .map(|((tag, write), addr)| self.write_address(tag, &addr, write.clone()))
+            .collect())
+    }
+}
+
+// fins://host[:port]?network=0&node=10 over UDP, fins+tcp:// over FINS/TCP
+pub fn register(registry: &mut Registry) {
+    for scheme in &["fins", "fins+tcp"] {
+        registry.register_device(
+            scheme,
+            |url| {
+                let host = registry::host(url)?;
+                let mut client = Client::new(FinsConfig {
+                    tcp: url.scheme() == "fins+tcp",
+                    dest_network: param(url, "network", 0)?,
+                    dest_node: param(url, "node", 0)?,
+                    dest_unit: param(url, "unit", 0)?,
+                    src_network: param(url, "src_network", 0)?,
+                    src_node: param(url, "src_node", 0)?,
+                    src_unit: param(url, "src_unit", 0)?,
+                });
+                client.connect(host.as_str());
+                if client.connected() {
+                    Ok(client)
+                } else {
+                    Err(format!("Connect to {} failed", host))
+                }
+            },
+            Client::connected,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plc_driver::tests::tag;
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    // Words keyed by word area code and word number
+    type Memory = HashMap<(u8, u16), u16>;
+
+    #[derive(Default)]
+    struct Plc {
+        memory: Memory,
+        commands: usize,
+        // Destination and source node of the last command
+        nodes: (u8, u8),
+    }
+
+    fn word_code(code: u8) -> u8 {
+        code | 0x80
+    }
+
+    fn bit(memory: &Memory, code: u8, word: u16, bit: u8) -> bool {
+        memory.get(&(word_code(code), word)).copied().unwrap_or(0) >> bit & 1 != 0
+    }
+
+    // Answers memory area reads and writes and multiple memory area reads
+    fn respond(frame: &[u8], plc: &mut Plc) -> Vec<u8> {
+        plc.commands += 1;
+        plc.nodes = (frame[4], frame[7]);
+        let command = [frame[10], frame[11]];
+        let data = &frame[12..];
+        let mut resp = vec![
+            0xC0, 0x00, 0x02, frame[6], frame[7], frame[8], frame[3], frame[4], frame[5], frame[9],
+            frame[10], frame[11], 0x00, 0x00,
+        ];
+        let memory = &mut plc.memory;
+        match command {
+            CMD_MULTIPLE_READ => {
+                for item in data.chunks(4) {
+                    let word = u16::from_be_bytes([item[1], item[2]]);
+                    resp.push(item[0]);
+                    if item[0] & 0x80 != 0 {
+                        let v = memory.get(&(item[0], word)).copied().unwrap_or(0);
+                        resp.extend_from_slice(&v.to_be_bytes());
+                    } else {
+                        resp.push(bit(memory, item[0], word, item[3]) as u8);
+                    }
+                }
+            }
+            CMD_MEMORY_READ => {
+                let word = u16::from_be_bytes([data[1], data[2]]);
+                let count = u16::from_be_bytes([data[4], data[5]]);
+                for w in word..word + count {
+                    let v = memory.get(&(data[0], w)).copied().unwrap_or(0);
+                    resp.extend_from_slice(&v.to_be_bytes());
+                }
+            }
+            CMD_MEMORY_WRITE if data[0] & 0x80 != 0 => {
+                let word = u16::from_be_bytes([data[1], data[2]]);
+                for (i, v) in data[6..].chunks(2).enumerate() {
+                    memory.insert((data[0], word + i as u16), u16::from_be_bytes([v[0], v[1]]));
+                }
+            }
+            CMD_MEMORY_WRITE => {
+                let word = u16::from_be_bytes([data[1], data[2]]);
+                let entry = memory.entry((word_code(data[0]), word)).or_default();
+                let mask = 1u16 << data[3];
+                *entry = if data[6] != 0 {
+                    *entry | mask
+                } else {
+                    *entry & !mask
+                };
+            }
+            _ => resp[12..14].copy_from_slice(&[0x04, 0x01]),
+        }
+        resp
+    }
+
+    // With `stale` every answer is preceded by a copy carrying the SID of
+    // the previous command, as a late answer to a timed out request. The
+    // first `unanswered` commands get no answer at all.
+    fn udp_server(stale: bool, unanswered: usize) -> (String, Arc<Mutex<Plc>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let host = socket.local_addr().unwrap().to_string();
+        let plc = Arc::new(Mutex::new(Plc::default()));
+        let shared = plc.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 2048];
+            for _ in 0..unanswered {
+                socket.recv_from(&mut buf).unwrap();
+            }
+            while let Ok((len, peer)) = socket.recv_from(&mut buf) {
+                let resp = respond(&buf[..len], &mut shared.lock().unwrap());
+                if stale {
+                    let mut late = resp.clone();
+                    late[9] = late[9].wrapping_sub(1);
+                    socket.send_to(&late, peer).unwrap();
+                }
+                socket.send_to(&resp, peer).unwrap();
+            }
+        });
+        (host, plc)
+    }
+
+    fn tcp_frame(command: u32, data: &[u8]) -> Vec<u8> {
+        let mut frame = FINS_TCP_MAGIC.to_vec();
+        frame.extend_from_slice(&((8 + data.len()) as u32).to_be_bytes());
+        frame.extend_from_slice(&command.to_be_bytes());
+        frame.extend_from_slice(&0u32.to_be_bytes());
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    // Assigns client node 10 and announces node 1
+    fn tcp_server() -> (String, Arc<Mutex<Plc>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let plc = Arc::new(Mutex::new(Plc::default()));
+        let shared = plc.clone();
+        thread::spawn(move || {
+            let mut stream = listener.incoming().next().unwrap().unwrap();
+            let mut header = [0u8; 16];
+            while stream.read_exact(&mut header).is_ok() {
+                let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+                let mut body = vec![0u8; len as usize - 8];
+                stream.read_exact(&mut body).unwrap();
+                let reply = match u32::from_be_bytes([header[8], header[9], header[10], header[11]])
+                {
+                    FINS_TCP_NODE_REQUEST => {
+                        tcp_frame(FINS_TCP_NODE_RESPONSE, &[0, 0, 0, 10, 0, 0, 0, 1])
+                    }
+                    _ => tcp_frame(FINS_TCP_FRAME, &respond(&body, &mut shared.lock().unwrap())),
+                };
+                stream.write_all(&reply).unwrap();
+            }
+        });
+        (host, plc)
+    }
+
+    fn client(host: &str, config: FinsConfig) -> Client {
+        let mut client = Client::new(config);
+        client.set_timeout(Duration::from_secs(1));
+        client.connect(host);
+        assert!(client.connected());
+        client
+    }
+
+    fn write_then_read_back(client: &Client, plc: &Mutex<Plc>) {
+        plc.lock().unwrap().memory.insert((0xB1, 3), 0x0001);
+        let writes = vec![
+            (tag("D100", ETagtype::INT), ETagValue::Int(-2)),
+            (tag("D200", ETagtype::DINT), ETagValue::Int(0x0001_0002)),
+            (tag("H7", ETagtype::REAL), ETagValue::Real(1.5)),
+            (tag("W3.05", ETagtype::BOOL), ETagValue::Bool(true)),
+            (tag("CIO0.00", ETagtype::BOOL), ETagValue::Bool(true)),
+        ];
+        assert_eq!(client.write_list(&writes).unwrap(), vec![Ok(true); 5]);
+        {
+            let memory = &plc.lock().unwrap().memory;
+            // The low word is stored first
+            assert_eq!(memory[&(0x82, 200)], 0x0002);
+            assert_eq!(memory[&(0x82, 201)], 0x0001);
+            assert_eq!(memory[&(0xB1, 3)], 0x0021);
+        }
+        let tags: Vec<ETag> = writes.iter().map(|w| w.0.clone()).collect();
+        let values: Vec<Result<ETagValue, String>> =
+            writes.iter().map(|w| Ok(w.1.clone())).collect();
+        assert_eq!(client.read_list(&tags).unwrap(), values);
+        assert_eq!(client.read_words(FinsArea::DM, 200, 2), Ok(vec![2, 1]));
+    }
+
+    #[test]
+    fn parses_addresses() {
+        let reg = address_regex();
+        let parse = |address, datatype| FinsAddress::parse(&reg, address, datatype);
+        assert!(parse("D100.03", ETagtype::BOOL).is_ok());
+        assert!(parse("D100.16", ETagtype::BOOL).is_err());
+        assert!(parse("D100", ETagtype::BOOL).is_err());
+        assert!(parse("D100.03", ETagtype::INT).is_err());
+        assert!(parse("D65535", ETagtype::INT).is_ok());
+        assert!(parse("D65535", ETagtype::DINT).is_err());
+        assert!(parse("D65534", ETagtype::REAL).is_ok());
+        assert_eq!(
+            parse("CIO12.07", ETagtype::BOOL).map(|a| (a.area, a.word, a.bit)),
+            Ok((FinsArea::CIO, 12, Some(7)))
+        );
+    }
+
+    #[test]
+    fn udp_write_then_read_back() {
+        let (host, plc) = udp_server(false, 0);
+        let client = client(&host, FinsConfig::default());
+        write_then_read_back(&client, &plc);
+    }
+
+    #[test]
+    fn stale_udp_answers_are_skipped() {
+        let (host, plc) = udp_server(true, 0);
+        let client = client(&host, FinsConfig::default());
+        plc.lock().unwrap().memory.insert((0x82, 5), 42);
+        for _ in 0..3 {
+            assert_eq!(
+                client.read_tag(&tag("D5", ETagtype::INT)),
+                Ok(ETagValue::Int(42))
+            );
+        }
+        // Each command went out once
+        assert_eq!(plc.lock().unwrap().commands, 3);
+    }
+
+    #[test]
+    fn udp_timeout_keeps_the_connection() {
+        let (host, plc) = udp_server(false, 1);
+        let mut client = client(&host, FinsConfig::default());
+        plc.lock().unwrap().memory.insert((0x82, 5), 42);
+        assert!(client
+            .read_tag(&tag("D5", ETagtype::INT))
+            .unwrap_err()
+            .starts_with("No FINS response"));
+        assert!(client.connected());
+        assert_eq!(
+            client.read_tag(&tag("D5", ETagtype::INT)),
+            Ok(ETagValue::Int(42))
+        );
+    }
+
+    #[test]
+    fn tcp_takes_the_announced_nodes() {
+        let (host, plc) = tcp_server();
+        let client = client(
+            &host,
+            FinsConfig {
+                tcp: true,
+                ..FinsConfig::default()
+            },
+        );
+        write_then_read_back(&client, &plc);
+        assert_eq!(plc.lock().unwrap().nodes, (1, 10));
+    }
+}
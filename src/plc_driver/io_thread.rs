@@ -0,0 +1,179 @@
+use super::{ETag, ETagRW, ETagRWAsync, ETagValue};
+use futures::channel::oneshot;
+use futures::future::{self, BoxFuture, FutureExt};
+use log::error;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+
+type Job<D> = Box<dyn FnOnce(&mut D) + Send>;
+
+// Owns a blocking driver on its own thread so that many devices can be
+// driven from a single executor without stalling each other.
+pub struct IoThread<D> {
+    sender: Mutex<Option<Sender<Job<D>>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<D: Send + 'static> IoThread<D> {
+    pub fn spawn(name: &str, mut driver: D) -> Result<Self, String> {
+        let (sender, receiver) = channel::<Job<D>>();
+        let thread = thread::Builder::new()
+            .name(format!("io-{}", name))
+            .spawn(move || {
+                for job in receiver {
+                    job(&mut driver);
+                }
+            })
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            sender: Mutex::new(Some(sender)),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn execute<T, F>(&self, f: F) -> BoxFuture<'static, Result<T, String>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut D) -> Result<T, String> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job<D> = Box::new(move |driver| {
+            let _ = tx.send(f(driver));
+        });
+        let sent = match self.sender.lock().unwrap().as_ref() {
+            Some(sender) => sender.send(job).is_ok(),
+            None => false,
+        };
+        if !sent {
+            return future::err(String::from("I/O thread stopped")).boxed();
+        }
+        rx.map(|r| r.unwrap_or_else(|_| Err(String::from("I/O thread stopped"))))
+            .boxed()
+    }
+}
+
+impl<D> Drop for IoThread<D> {
+    fn drop(&mut self) {
+        self.sender.lock().unwrap().take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("I/O thread panicked");
+            }
+        }
+    }
+}
+
+impl<D: ETagRW + Send + 'static> ETagRWAsync for IoThread<D> {
+    fn read_tag(&self, tag: &ETag) -> BoxFuture<'static, Result<ETagValue, String>> {
+        let tag = tag.clone();
+        self.execute(move |driver| driver.read_tag(&tag))
+    }
+
+    fn read_list(
+        &self,
+        tags: &Vec<ETag>,
+    ) -> BoxFuture<'static, Result<Vec<Result<ETagValue, String>>, String>> {
+        let tags = tags.clone();
+        self.execute(move |driver| driver.read_list(&tags))
+    }
+
+    fn write_tag(&self, tag: &ETag, write: ETagValue) -> BoxFuture<'static, Result<bool, String>> {
+        let tag = tag.clone();
+        self.execute(move |driver| driver.write_tag(&tag, write))
+    }
+
+    fn write_list(
+        &self,
+        tags: &Vec<(ETag, ETagValue)>,
+    ) -> BoxFuture<'static, Result<Vec<Result<bool, String>>, String>> {
+        let tags = tags.clone();
+        self.execute(move |driver| driver.write_list(&tags))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plc_driver::tests::{tag, Memory};
+    use crate::plc_driver::ETagtype;
+    use futures::executor::block_on;
+    use futures::future::join_all;
+
+    #[test]
+    fn jobs_run_in_submission_order() {
+        let io = IoThread::spawn("order", Vec::new()).unwrap();
+        let pending: Vec<_> = (0..50)
+            .map(|i| {
+                io.execute(move |seen: &mut Vec<i32>| {
+                    seen.push(i);
+                    Ok(seen.len())
+                })
+            })
+            .collect();
+        let results = block_on(join_all(pending));
+        assert_eq!(results, (1..=50).map(Ok).collect::<Vec<_>>());
+        let seen = block_on(io.execute(|seen: &mut Vec<i32>| Ok(seen.clone()))).unwrap();
+        assert_eq!(seen, (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn driver_results_come_back_through_the_future() {
+        let io = IoThread::spawn("memory", Memory::default()).unwrap();
+        let a = tag("a", ETagtype::INT);
+        let b = tag("b", ETagtype::INT);
+        assert_eq!(
+            block_on(ETagRWAsync::read_tag(&io, &a)),
+            Err(String::from("Not written"))
+        );
+        assert_eq!(
+            block_on(ETagRWAsync::write_tag(&io, &a, ETagValue::Int(7))),
+            Ok(true)
+        );
+        assert_eq!(
+            block_on(ETagRWAsync::write_tag(&io, &a, ETagValue::Int(70000))),
+            Err(String::from("Value Int(70000) is out of range for INT"))
+        );
+        assert_eq!(
+            block_on(ETagRWAsync::write_list(
+                &io,
+                &vec![(b.clone(), ETagValue::Int(-3))]
+            )),
+            Ok(vec![Ok(true)])
+        );
+        assert_eq!(
+            block_on(ETagRWAsync::read_list(&io, &vec![a, b])),
+            Ok(vec![Ok(ETagValue::Int(7)), Ok(ETagValue::Int(-3))])
+        );
+    }
+
+    #[test]
+    fn queued_jobs_finish_when_the_thread_is_dropped() {
+        let io = IoThread::spawn("drop", 0).unwrap();
+        let pending: Vec<_> = (0..10)
+            .map(|_| {
+                io.execute(|count: &mut i32| {
+                    thread::sleep(std::time::Duration::from_millis(5));
+                    *count += 1;
+                    Ok(*count)
+                })
+            })
+            .collect();
+        drop(io);
+        let results = block_on(join_all(pending));
+        assert_eq!(results, (1..=10).map(Ok).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn jobs_fail_once_the_thread_is_gone() {
+        let io = IoThread::spawn("panic", ()).unwrap();
+        let crashed = io.execute(|_: &mut ()| -> Result<(), String> { panic!("driver crashed") });
+        let queued = io.execute(|_: &mut ()| Ok(()));
+        assert_eq!(block_on(crashed), Err(String::from("I/O thread stopped")));
+        assert_eq!(block_on(queued), Err(String::from("I/O thread stopped")));
+        assert_eq!(
+            block_on(io.execute(|_: &mut ()| Ok(()))),
+            Err(String::from("I/O thread stopped"))
+        );
+    }
+}
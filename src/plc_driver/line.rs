@@ -0,0 +1,781 @@
+use super::registry::{self, param, Registry};
+use super::{ETag, ETagRW, ETagValue, ETagtype};
+use log::{info, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
+
+// Interval at which the reader thread checks for close
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const COMMAND_SEPARATOR: &str = " => ";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LineConfig {
+    // Ends every frame, CR LF unless the device uses something else
+    pub terminator: Vec<u8>,
+    // Strips whitespace and control characters such as STX/ETX
+    pub trim: bool,
+    // Wait for a reply in request/response mode
+    pub timeout_ms: u64,
+    // Streamed frames older than this are not used for reads
+    #[serde(default)]
+    pub max_age_ms: Option<u64>,
+    // Recent frames kept for streamed tags
+    pub history: usize,
+}
+
+impl Default for LineConfig {
+    fn default() -> Self {
+        Self {
+            terminator: b"\r\n".to_vec(),
+            trim: true,
+            timeout_ms: 1000,
+            max_age_ms: None,
+            history: 32,
+        }
+    }
+}
+
+#[cfg(feature = "line-serial")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LineSerial {
+    pub path: String,
+    pub baud: u32,
+    // Character format such as 8N1 or 7E1
+    pub format: String,
+}
+
+// A tag address: `<regex>` matched against streamed frames, or
+// `<command> => <regex>` sending the command and matching the reply.
+// In writes `{}` in the command is replaced by the value.
+#[derive(Debug, Clone)]
+pub struct LineAddress {
+    pub command: Option<Vec<u8>>,
+    pub pattern: Regex,
+    pub datatype: ETagtype,
+}
+
+impl LineAddress {
+    pub fn parse(address: &str, datatype: ETagtype) -> Result<Self, String> {
+        let (command, pattern) = match address.find(COMMAND_SEPARATOR) {
+            Some(pos) => (
+                Some(unescape(&address[..pos])?),
+                &address[pos + COMMAND_SEPARATOR.len()..],
+            ),
+            None => (None, address),
+        };
+        let pattern =
+            Regex::new(pattern).map_err(|e| format!("Invalid pattern in {}: {}", address, e))?;
+        Ok(Self {
+            command,
+            pattern,
+            datatype,
+        })
+    }
+
+    // The named group `value`, else the first group, else the whole match
+    pub fn capture<'a>(&self, frame: &'a str) -> Option<&'a str> {
+        let c = self.pattern.captures(frame)?;
+        c.name("value")
+            .or_else(|| c.get(1))
+            .or_else(|| c.get(0))
+            .map(|m| m.as_str())
+    }
+}
+
+// `\r`, `\n`, `\t`, `\\` and `\xHH` as in most device manuals. `\xHH` is
+// the raw byte, other characters are sent as UTF-8.
+pub fn unescape(s: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.extend_from_slice(c.encode_utf8(&mut [0u8; 4]).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => out.push(b'\r'),
+            Some('n') => out.push(b'\n'),
+            Some('t') => out.push(b'\t'),
+            Some('\\') => out.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let b = u8::from_str_radix(&hex, 16)
+                    .map_err(|_| format!("Invalid escape \\x{} in {}", hex, s))?;
+                out.push(b);
+            }
+            Some(c) => return Err(format!("Invalid escape \\{} in {}", c, s)),
+            None => return Err(format!("Trailing backslash in {}", s)),
+        }
+    }
+    Ok(out)
+}
+
+pub fn parse_value(text: &str, datatype: ETagtype) -> Result<ETagValue, String> {
+    let text = text.trim();
+    let number = || -> Option<f64> {
+        let t = text.trim_start_matches('+');
+        t.parse::<f64>()
+            .ok()
+            .or_else(|| t.replace(',', ".").parse().ok())
+    };
+    let invalid = || format!("Cannot convert {} to {:?}", text, datatype);
+    match datatype {
+        ETagtype::BOOL => match text.to_ascii_lowercase().as_str() {
+            "1" | "true" | "on" | "yes" | "ok" => Ok(ETagValue::Bool(true)),
+            "0" | "false" | "off" | "no" => Ok(ETagValue::Bool(false)),
+            _ => number()
+                .map(|v| ETagValue::Bool(v != 0.0))
+                .ok_or_else(invalid),
+        },
+        ETagtype::INT | ETagtype::DINT => {
+            let v = match text.trim_start_matches('+').parse::<i64>() {
+                Ok(v) => v,
+                Err(_) => number().ok_or_else(invalid)?.round() as i64,
+            };
+            let (min, max) = datatype.range().unwrap();
+            if v >= min && v <= max {
+                Ok(ETagValue::Int(v))
+            } else {
+                Err(format!("Value {} is out of range for {:?}", v, datatype))
+            }
+        }
+        ETagtype::REAL => number().map(ETagValue::Real).ok_or_else(invalid),
+    }
+}
+
+// Where a write command takes its value
+const PLACEHOLDER: &[u8] = b"{}";
+
+fn takes_value(command: &[u8]) -> bool {
+    command.windows(PLACEHOLDER.len()).any(|w| w == PLACEHOLDER)
+}
+
+fn fill(command: &[u8], value: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(command.len() + value.len());
+    let mut rest = command;
+    while !rest.is_empty() {
+        if rest.starts_with(PLACEHOLDER) {
+            out.extend_from_slice(value.as_bytes());
+            rest = &rest[PLACEHOLDER.len()..];
+        } else {
+            out.push(rest[0]);
+            rest = &rest[1..];
+        }
+    }
+    out
+}
+
+fn format_value(value: ETagValue) -> String {
+    match value {
+        ETagValue::Bool(v) => String::from(if v { "1" } else { "0" }),
+        v => v.to_string(),
+    }
+}
+
+#[derive(Debug, Default)]
+struct Frames {
+    seq: u64,
+    recent: VecDeque<(u64, Instant, String)>,
+    open: bool,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    frames: Mutex<Frames>,
+    arrived: Condvar,
+}
+
+struct Link {
+    writer: Box<dyn Write + Send>,
+    stop: Arc<AtomicBool>,
+    tcp: Option<TcpStream>,
+}
+
+impl std::fmt::Debug for Link {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Link").field("tcp", &self.tcp).finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct Client {
+    host: String,
+    config: LineConfig,
+    shared: Arc<Shared>,
+    link: Mutex<Option<Link>>,
+    addresses: Mutex<HashMap<String, LineAddress>>,
+}
+
+impl Client {
+    pub fn new(config: LineConfig) -> Self {
+        Self {
+            host: String::new(),
+            config,
+            shared: Arc::new(Shared::default()),
+            link: Mutex::new(None),
+            addresses: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Raw TCP, `host` is `address:port`
+    pub fn connect(&mut self, host: &str) {
+        self.host = host.to_owned();
+        let stream = host
+            .to_socket_addrs()
+            .map_err(|e| e.to_string())
+            .and_then(|mut a| a.next().ok_or_else(|| format!("Cannot resolve {}", host)))
+            .and_then(|addr| {
+                TcpStream::connect_timeout(&addr, Duration::from_millis(self.config.timeout_ms))
+                    .map_err(|e| e.to_string())
+            })
+            .and_then(|stream| {
+                stream
+                    .set_read_timeout(Some(POLL_INTERVAL))
+                    .and_then(|_| stream.try_clone())
+                    .map(|reader| (stream, reader))
+                    .map_err(|e| e.to_string())
+            });
+        match stream {
+            Ok((stream, reader)) => {
+                info!("Line protocol connected to {}", host);
+                let shutdown = stream.try_clone().ok();
+                self.attach(Box::new(reader), Box::new(stream));
+                if let Some(link) = self.link.lock().unwrap().as_mut() {
+                    link.tcp = shutdown;
+                }
+            }
+            Err(err) => {
+                info!("Connect to {} failed: {}", host, err);
+                self.close();
+            }
+        }
+    }
+
+    #[cfg(feature = "line-serial")]
+    pub fn open_serial(&mut self, serial: &LineSerial) -> Result<(), String> {
+        let format = serial.format.as_bytes();
+        if format.len() != 3 {
+            return Err(format!("Invalid serial format {}", serial.format));
+        }
+        let data_bits = match format[0] {
+            b'7' => serialport::DataBits::Seven,
+            b'8' => serialport::DataBits::Eight,
+            _ => return Err(format!("Invalid serial format {}", serial.format)),
+        };
+        let parity = match format[1] {
+            b'N' => serialport::Parity::None,
+            b'E' => serialport::Parity::Even,
+            b'O' => serialport::Parity::Odd,
+            _ => return Err(format!("Invalid serial format {}", serial.format)),
+        };
+        let stop_bits = match format[2] {
+            b'2' => serialport::StopBits::Two,
+            _ => serialport::StopBits::One,
+        };
+        let port = serialport::new(serial.path.as_str(), serial.baud)
+            .data_bits(data_bits)
+            .parity(parity)
+            .stop_bits(stop_bits)
+            .timeout(POLL_INTERVAL)
+            .open()
+            .map_err(|e| e.to_string())?;
+        let reader = port.try_clone().map_err(|e| e.to_string())?;
+        self.host = serial.path.clone();
+        info!("Line protocol on {} at {} baud", serial.path, serial.baud);
+        self.attach(Box::new(reader), Box::new(port));
+        Ok(())
+    }
+
+    // Any byte stream; the reader should time out now and then so that
+    // close can stop the reader thread
+    pub fn attach(&mut self, reader: Box<dyn Read + Send>, writer: Box<dyn Write + Send>) {
+        self.close();
+        let stop = Arc::new(AtomicBool::new(false));
+        // A fresh buffer, the reader of a previous link may still be running
+        self.shared = Arc::new(Shared::default());
+        self.shared.frames.lock().unwrap().open = true;
+        let shared = self.shared.clone();
+        let config = self.config.clone();
+        let thread_stop = stop.clone();
+        let host = self.host.clone();
+        thread::spawn(move || read_frames(reader, shared, config, thread_stop, host));
+        *self.link.lock().unwrap() = Some(Link {
+            writer,
+            stop,
+            tcp: None,
+        });
+    }
+
+    pub fn close(&mut self) {
+        if let Some(link) = self.link.lock().unwrap().take() {
+            link.stop.store(true, Ordering::SeqCst);
+            if let Some(tcp) = link.tcp {
+                let _ = tcp.shutdown(Shutdown::Both);
+            }
+        }
+    }
+
+    pub fn connected(&mut self) -> bool {
+        self.link.lock().unwrap().is_some() && self.shared.frames.lock().unwrap().open
+    }
+
+    // Latest frames, newest last
+    pub fn frames(&self) -> Vec<String> {
+        let frames = self.shared.frames.lock().unwrap();
+        frames.recent.iter().map(|f| f.2.clone()).collect()
+    }
+
+    pub fn conv_address(&self, address: &str, datatype: ETagtype) -> Result<LineAddress, String> {
+        let mut addresses = self.addresses.lock().unwrap();
+        if let Some(addr) = addresses.get(address) {
+            return Ok(LineAddress {
+                datatype,
+                ..addr.clone()
+            });
+        }
+        let addr = LineAddress::parse(address, datatype)?;
+        addresses.insert(address.to_owned(), addr.clone());
+        Ok(addr)
+    }
+
+    // Sends a command and waits for the first frame `pattern` matches
+    pub fn request(&self, command: &[u8], pattern: &Regex) -> Result<String, String> {
+        let mut link = self.link.lock().unwrap();
+        let link = link.as_mut().ok_or_else(|| String::from("Not connected"))?;
+        let since = self.shared.frames.lock().unwrap().seq;
+        link.writer
+            .write_all(command)
+            .and_then(|_| link.writer.flush())
+            .map_err(|e| e.to_string())?;
+        let deadline = Instant::now() + Duration::from_millis(self.config.timeout_ms);
+        let mut frames = self.shared.frames.lock().unwrap();
+        let mut seen = since;
+        loop {
+            for (seq, _, frame) in frames.recent.iter() {
+                if *seq <= seen {
+                    continue;
+                }
+                seen = *seq;
+                if pattern.is_match(frame) {
+                    return Ok(frame.clone());
+                }
+            }
+            if !frames.open {
+                return Err(String::from("Connection closed"));
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_millis(0) {
+                return Err(format!(
+                    "No reply to {:?}",
+                    String::from_utf8_lossy(command)
+                ));
+            }
+            frames = self.shared.arrived.wait_timeout(frames, left).unwrap().0;
+        }
+    }
+
+    fn latest(&self, addr: &LineAddress) -> Result<String, String> {
+        let frames = self.shared.frames.lock().unwrap();
+        let max_age = self.config.max_age_ms.map(Duration::from_millis);
+        frames
+            .recent
+            .iter()
+            .rev()
+            .take_while(|f| max_age.is_none_or(|age| f.1.elapsed() <= age))
+            .find(|f| addr.pattern.is_match(&f.2))
+            .map(|f| f.2.clone())
+            .ok_or_else(|| String::from("No matching frame"))
+    }
+
+    fn read_address(&self, addr: &LineAddress) -> Result<ETagValue, String> {
+        let frame = match &addr.command {
+            Some(command) if takes_value(command) => return Err(String::from("Tag is write only")),
+            Some(command) => self.request(command, &addr.pattern)?,
+            None => self.latest(addr)?,
+        };
+        let text = addr
+            .capture(&frame)
+            .ok_or_else(|| String::from("No matching frame"))?;
+        parse_value(text, addr.datatype)
+    }
+
+    fn write_address(
+        &self,
+        tag: &ETag,
+        addr: &LineAddress,
+        write: ETagValue,
+    ) -> Result<bool, String> {
+        let write = tag.datatype.coerce(write, tag.range)?;
+        let command = match &addr.command {
+            Some(command) if takes_value(command) => fill(command, &format_value(write)),
+            _ => return Err(String::from("Tag is read only")),
+        };
+        self.request(&command, &addr.pattern).map(|_| true)
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn read_frames(
+    mut reader: Box<dyn Read + Send>,
+    shared: Arc<Shared>,
+    config: LineConfig,
+    stop: Arc<AtomicBool>,
+    host: String,
+) {
+    let terminator = &config.terminator[..];
+    let mut pending: Vec<u8> = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !stop.load(Ordering::SeqCst) {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                continue
+            }
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                warn!("Line protocol {}: {}", host, e);
+                break;
+            }
+        };
+        pending.extend_from_slice(&buf[..len]);
+        let mut frames = Vec::new();
+        while let Some(pos) = pending
+            .windows(terminator.len().max(1))
+            .position(|w| w == terminator)
+        {
+            let frame: Vec<u8> = pending.drain(..pos + terminator.len()).take(pos).collect();
+            let frame = String::from_utf8_lossy(&frame).into_owned();
+            let frame = if config.trim {
+                frame
+                    .trim_matches(|c: char| c.is_whitespace() || c.is_control())
+                    .to_owned()
+            } else {
+                frame
+            };
+            if !frame.is_empty() {
+                frames.push(frame);
+            }
+        }
+        if frames.is_empty() {
+            continue;
+        }
+        let mut state = shared.frames.lock().unwrap();
+        for frame in frames {
+            state.seq += 1;
+            let seq = state.seq;
+            state.recent.push_back((seq, Instant::now(), frame));
+            while state.recent.len() > config.history.max(1) {
+                state.recent.pop_front();
+            }
+        }
+        shared.arrived.notify_all();
+    }
+    shared.frames.lock().unwrap().open = false;
+    shared.arrived.notify_all();
+}
+
+impl ETagRW for Client {
+    fn read_tag(&self, tag: &ETag) -> Result<ETagValue, String> {
+        let addr = self.conv_address(tag.address.as_str(), tag.datatype)?;
+        self.read_address(&addr)
+    }
+    fn read_list(&self, tags: &Vec<ETag>) -> Result<Vec<Result<ETagValue, String>>, String> {
+        let addrs = tags
+            .iter()
+            .map(|tag| self.conv_address(tag.address.as_str(), tag.datatype))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        Ok(addrs.iter().map(|addr| self.read_address(addr)).collect())
+    }
+    fn write_tag(&self, tag: &ETag, write: ETagValue) -> Result<bool, String> {
+        let addr = self.conv_address(tag.address.as_str(), tag.datatype)?;
+        self.write_address(tag, &addr, write)
+    }
+    fn write_list(
+        &self,
+        tags: &Vec<(ETag, ETagValue)>,
+    ) -> Result<Vec<Result<bool, String>>, String> {
+        let addrs = tags
+            .iter()
+            .map(|t| self.conv_address(t.0.address.as_str(), t.0.datatype))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        Ok(tags
+            .iter()
+            .zip(addrs)
+            .map(|((tag, write), addr)| self.write_address(tag, &addr, write.clone()))
+            .collect())
+    }
+}
+
+fn config_from_url(url: &Url) -> Result<LineConfig, String> {
+    let default = LineConfig::default();
+    Ok(LineConfig {
+        terminator: unescape(&param(url, "terminator", String::from("\\r\\n"))?)?,
+        trim: param(url, "trim", default.trim)?,
+        timeout_ms: param(url, "timeout_ms", default.timeout_ms)?,
+        max_age_ms: match param(url, "max_age_ms", 0u64)? {
+            0 => None,
+            ms => Some(ms),
+        },
+        history: param(url, "history", default.history)?,
+    })
+}
+
+// line+tcp://host:port?terminator=\r\n&timeout_ms=1000&max_age_ms=5000,
+// line+serial:///dev/ttyS0?baud=9600&format=8N1 with the same parameters
+pub fn register(registry: &mut Registry) {
+    registry.register_device(
+        "line+tcp",
+        |url| {
+            let host = registry::host(url)?;
+            if url.port().is_none() {
+                return Err(format!("Missing port in {}", url));
+            }
+            let mut client = Client::new(config_from_url(url)?);
+            client.connect(host.as_str());
+            if client.connected() {
+                Ok(client)
+            } else {
+                Err(format!("Connect to {} failed", host))
+            }
+        },
+        Client::connected,
+    );
+    #[cfg(feature = "line-serial")]
+    registry.register_device(
+        "line+serial",
+        |url| {
+            let mut client = Client::new(config_from_url(url)?);
+            client.open_serial(&LineSerial {
+                path: format!("{}{}", url.host_str().unwrap_or(""), url.path()),
+                baud: param(url, "baud", 9600)?,
+                format: param(url, "format", String::from("8N1"))?,
+            })?;
+            Ok(client)
+        },
+        Client::connected,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plc_driver::tests::tag;
+    use std::io;
+    use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+
+    // The device side of an in-memory link. Reads time out like a socket
+    // with a read timeout so that close can stop the reader thread.
+    struct Incoming(Receiver<Vec<u8>>, Vec<u8>);
+
+    impl Read for Incoming {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.1.is_empty() {
+                match self.0.recv_timeout(Duration::from_millis(20)) {
+                    Ok(data) => self.1 = data,
+                    Err(RecvTimeoutError::Timeout) => return Err(ErrorKind::WouldBlock.into()),
+                    Err(RecvTimeoutError::Disconnected) => return Ok(0),
+                }
+            }
+            let len = buf.len().min(self.1.len());
+            buf[..len].copy_from_slice(&self.1[..len]);
+            self.1.drain(..len);
+            Ok(len)
+        }
+    }
+
+    struct Outgoing(Sender<Vec<u8>>);
+
+    impl Write for Outgoing {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _ = self.0.send(buf.to_vec());
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Returns the client, the sender of device output and the receiver of
+    // what the client sent
+    fn link(config: LineConfig) -> (Client, Sender<Vec<u8>>, Receiver<Vec<u8>>) {
+        let (device, incoming) = channel();
+        let (outgoing, sent) = channel();
+        let mut client = Client::new(config);
+        client.attach(
+            Box::new(Incoming(incoming, Vec::new())),
+            Box::new(Outgoing(outgoing)),
+        );
+        (client, device, sent)
+    }
+
+    fn wait_for_frames(client: &Client, count: usize) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while client.frames().len() < count {
+            assert!(Instant::now() < deadline, "Frames did not arrive");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn parses_values() {
+        assert_eq!(
+            parse_value(" ON ", ETagtype::BOOL),
+            Ok(ETagValue::Bool(true))
+        );
+        assert_eq!(
+            parse_value("no", ETagtype::BOOL),
+            Ok(ETagValue::Bool(false))
+        );
+        assert_eq!(
+            parse_value("0.0", ETagtype::BOOL),
+            Ok(ETagValue::Bool(false))
+        );
+        assert_eq!(parse_value("+12", ETagtype::INT), Ok(ETagValue::Int(12)));
+        assert_eq!(parse_value("12.6", ETagtype::DINT), Ok(ETagValue::Int(13)));
+        assert_eq!(parse_value("1,5", ETagtype::REAL), Ok(ETagValue::Real(1.5)));
+        assert_eq!(
+            parse_value("-2e3", ETagtype::REAL),
+            Ok(ETagValue::Real(-2000.0))
+        );
+        assert_eq!(
+            parse_value("40000", ETagtype::INT),
+            Err(String::from("Value 40000 is out of range for INT"))
+        );
+        assert_eq!(
+            parse_value("maybe", ETagtype::BOOL),
+            Err(String::from("Cannot convert maybe to BOOL"))
+        );
+    }
+
+    #[test]
+    fn unescapes_to_raw_bytes() {
+        assert_eq!(unescape("RD\\r\\n"), Ok(b"RD\r\n".to_vec()));
+        assert_eq!(
+            unescape("\\x02A\\x03\\xFF"),
+            Ok(vec![0x02, b'A', 0x03, 0xFF])
+        );
+        assert_eq!(unescape("\\t\\\\"), Ok(b"\t\\".to_vec()));
+        assert_eq!(unescape("°C"), Ok("°C".as_bytes().to_vec()));
+        assert_eq!(
+            unescape("\\xZZ"),
+            Err(String::from("Invalid escape \\xZZ in \\xZZ"))
+        );
+        assert_eq!(
+            unescape("\\q"),
+            Err(String::from("Invalid escape \\q in \\q"))
+        );
+        assert_eq!(
+            unescape("RD\\"),
+            Err(String::from("Trailing backslash in RD\\"))
+        );
+    }
+
+    #[test]
+    fn captures_the_value_group() {
+        let capture = |address: &str, frame| {
+            LineAddress::parse(address, ETagtype::REAL)
+                .unwrap()
+                .capture(frame)
+                .map(str::to_owned)
+        };
+        let frame = "ST,GS,+0012.50kg";
+        assert_eq!(
+            capture(r"(\w+),GS,(?P<value>[-+\d.]+)", frame),
+            Some(String::from("+0012.50"))
+        );
+        assert_eq!(capture(r"ST,(\w+)", frame), Some(String::from("GS")));
+        assert_eq!(capture(r"\d+\.\d+", frame), Some(String::from("0012.50")));
+        assert_eq!(capture(r"US,", frame), None);
+
+        let addr = LineAddress::parse("\\x02W{}\\r => OK", ETagtype::INT).unwrap();
+        assert_eq!(addr.command, Some(b"\x02W{}\r".to_vec()));
+        assert_eq!(fill(&addr.command.unwrap(), "-5"), b"\x02W-5\r".to_vec());
+    }
+
+    #[test]
+    fn streamed_reads_use_the_latest_frame() {
+        let (client, device, _sent) = link(LineConfig {
+            max_age_ms: Some(200),
+            ..LineConfig::default()
+        });
+        let temperature = tag(r"T=(\S+)", ETagtype::REAL);
+        assert_eq!(
+            client.read_tag(&temperature),
+            Err(String::from("No matching frame"))
+        );
+
+        device.send(b"T=20.5\r\nP=1\r\nT=".to_vec()).unwrap();
+        device.send(b"21.5\r\n\r\n".to_vec()).unwrap();
+        wait_for_frames(&client, 3);
+        assert_eq!(client.frames(), vec!["T=20.5", "P=1", "T=21.5"]);
+        assert_eq!(client.read_tag(&temperature), Ok(ETagValue::Real(21.5)));
+        assert_eq!(
+            client.write_tag(&temperature, ETagValue::Real(1.0)),
+            Err(String::from("Tag is read only"))
+        );
+
+        // Too old once max_age_ms has passed
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(
+            client.read_tag(&temperature),
+            Err(String::from("No matching frame"))
+        );
+    }
+
+    #[test]
+    fn requests_wait_for_their_reply() {
+        let (client, device, sent) = link(LineConfig {
+            timeout_ms: 300,
+            ..LineConfig::default()
+        });
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let seen = commands.clone();
+        thread::spawn(move || {
+            for command in sent {
+                let reply: &[u8] = match &command[..] {
+                    b"\xFFMEAS?\r" => b"BUSY\r\nMEAS 12.5\r\n",
+                    c if c.starts_with(b"\x02SET ") => b"OK\r\n",
+                    _ => b"ERR\r\n",
+                };
+                seen.lock().unwrap().push(command.clone());
+                let _ = device.send(reply.to_vec());
+            }
+        });
+
+        let measure = tag(r"\xFFMEAS?\r => MEAS (?P<value>\S+)", ETagtype::REAL);
+        assert_eq!(client.read_tag(&measure), Ok(ETagValue::Real(12.5)));
+
+        let setpoint = tag(r"\x02SET {}\r => ^OK$", ETagtype::INT);
+        assert_eq!(client.write_tag(&setpoint, ETagValue::Int(-40)), Ok(true));
+        assert_eq!(
+            client.read_tag(&setpoint),
+            Err(String::from("Tag is write only"))
+        );
+        assert_eq!(
+            commands.lock().unwrap().clone(),
+            vec![b"\xFFMEAS?\r".to_vec(), b"\x02SET -40\r".to_vec()]
+        );
+
+        let ping = tag(r"PING\r => PONG", ETagtype::BOOL);
+        assert_eq!(
+            client.read_tag(&ping),
+            Err(String::from("No reply to \"PING\\r\""))
+        );
+    }
+}
@@ -0,0 +1,952 @@
+use super::registry::{self, param, Registry};
+use super::{ETag, ETagRW, ETagValue, ETagtype};
+use log::info;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const MC_DEFAULT_PORT: u16 = 5007;
+const CMD_BATCH_READ: u16 = 0x0401;
+const CMD_BATCH_WRITE: u16 = 0x1401;
+const CMD_RANDOM_READ: u16 = 0x0403;
+const CMD_RANDOM_WRITE: u16 = 0x1402;
+const SUB_WORD: u16 = 0x0000;
+const SUB_BIT: u16 = 0x0001;
+// Word plus double word points of one random read on Q/L series
+const MAX_RANDOM_POINTS: usize = 192;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum McDevice {
+    X,
+    Y,
+    M,
+    L,
+    B,
+    SM,
+    D,
+    W,
+    R,
+    SD,
+}
+
+impl McDevice {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "X" => Some(McDevice::X),
+            "Y" => Some(McDevice::Y),
+            "M" => Some(McDevice::M),
+            "L" => Some(McDevice::L),
+            "B" => Some(McDevice::B),
+            "SM" => Some(McDevice::SM),
+            "D" => Some(McDevice::D),
+            "W" => Some(McDevice::W),
+            "R" => Some(McDevice::R),
+            "SD" => Some(McDevice::SD),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            McDevice::X => 0x9C,
+            McDevice::Y => 0x9D,
+            McDevice::M => 0x90,
+            McDevice::L => 0x92,
+            McDevice::B => 0xA0,
+            McDevice::SM => 0x91,
+            McDevice::D => 0xA8,
+            McDevice::W => 0xB4,
+            McDevice::R => 0xAF,
+            McDevice::SD => 0xA9,
+        }
+    }
+
+    fn ascii_code(&self) -> &'static str {
+        match self {
+            McDevice::X => "X*",
+            McDevice::Y => "Y*",
+            McDevice::M => "M*",
+            McDevice::L => "L*",
+            McDevice::B => "B*",
+            McDevice::SM => "SM",
+            McDevice::D => "D*",
+            McDevice::W => "W*",
+            McDevice::R => "R*",
+            McDevice::SD => "SD",
+        }
+    }
+
+    // X, Y, B and W are numbered in hexadecimal
+    pub fn is_hex(&self) -> bool {
+        matches!(self, McDevice::X | McDevice::Y | McDevice::B | McDevice::W)
+    }
+
+    pub fn is_bit(&self) -> bool {
+        matches!(
+            self,
+            McDevice::X | McDevice::Y | McDevice::M | McDevice::L | McDevice::B | McDevice::SM
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct McAddress {
+    pub device: McDevice,
+    pub number: u32,
+    pub bit: Option<u8>,
+    pub datatype: ETagtype,
+}
+
+pub fn address_regex() -> Regex {
+    Regex::new(r"^(SM|SD|X|Y|M|L|B|D|W|R)([0-9A-F]{1,6})(?:\.([0-9A-F]))?$").unwrap()
+}
+
+impl McAddress {
+    // `D100`, `M20`, `X1F`, `Y10`, `W0`; `D100.F` is bit 15 of D100
+    pub fn parse(reg: &Regex, address: &str, datatype: ETagtype) -> Result<Self, String> {
+        let invalid = || format!("Invalid MC address {}", address);
+        let r = reg.captures(address).ok_or_else(invalid)?;
+        let device = McDevice::parse(r.get(1).unwrap().as_str()).unwrap();
+        let radix = if device.is_hex() { 16 } else { 10 };
+        let number =
+            u32::from_str_radix(r.get(2).unwrap().as_str(), radix).map_err(|_| invalid())?;
+        let bit = r
+            .get(3)
+            .map(|b| u8::from_str_radix(b.as_str(), 16).unwrap());
+        let valid = matches!(
+            (device.is_bit(), datatype, bit),
+            (true, ETagtype::BOOL, None)
+                | (false, ETagtype::BOOL, Some(_))
+                | (false, ETagtype::INT, None)
+                | (false, ETagtype::DINT, None)
+                | (false, ETagtype::REAL, None)
+        );
+        if valid && number <= 0xFF_FFFF {
+            Ok(Self {
+                device,
+                number,
+                bit,
+                datatype,
+            })
+        } else {
+            Err(invalid())
+        }
+    }
+
+    fn dword(&self) -> bool {
+        matches!(self.datatype, ETagtype::DINT | ETagtype::REAL)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct McConfig {
+    pub network: u8,
+    pub pc: u8,
+    pub module_io: u16,
+    pub station: u8,
+    // Monitoring timer in units of 250 ms
+    pub timer: u16,
+    pub ascii: bool,
+}
+
+impl Default for McConfig {
+    fn default() -> Self {
+        Self {
+            network: 0x00,
+            pc: 0xFF,
+            module_io: 0x03FF,
+            station: 0x00,
+            timer: 0x0010,
+            ascii: false,
+        }
+    }
+}
+
+// Request fields, laid out little endian in binary frames and as
+// upper case hex text (most significant digit first) in ASCII frames
+enum Field {
+    Byte(u8),
+    Word(u16),
+    DWord(u32),
+    Device(McDevice, u32),
+    // Copied as is, bit data is packed differently in each format
+    Raw(Vec<u8>),
+}
+
+fn encode_fields(fields: &[Field], ascii: bool) -> Vec<u8> {
+    let mut out = Vec::new();
+    for field in fields {
+        match (field, ascii) {
+            (Field::Raw(v), _) => out.extend_from_slice(v),
+            (Field::Byte(v), false) => out.push(*v),
+            (Field::Word(v), false) => out.extend_from_slice(&v.to_le_bytes()),
+            (Field::DWord(v), false) => out.extend_from_slice(&v.to_le_bytes()),
+            (Field::Device(d, n), false) => {
+                out.extend_from_slice(&n.to_le_bytes()[..3]);
+                out.push(d.code());
+            }
+            (Field::Byte(v), true) => out.extend_from_slice(format!("{:02X}", v).as_bytes()),
+            (Field::Word(v), true) => out.extend_from_slice(format!("{:04X}", v).as_bytes()),
+            (Field::DWord(v), true) => out.extend_from_slice(format!("{:08X}", v).as_bytes()),
+            (Field::Device(d, n), true) => {
+                out.extend_from_slice(d.ascii_code().as_bytes());
+                let number = if d.is_hex() {
+                    format!("{:06X}", n)
+                } else {
+                    format!("{:06}", n)
+                };
+                out.extend_from_slice(number.as_bytes());
+            }
+        }
+    }
+    out
+}
+
+// Reads the response data the same way encode_fields writes requests
+struct Response<'a> {
+    data: &'a [u8],
+    pos: usize,
+    ascii: bool,
+}
+
+impl<'a> Response<'a> {
+    fn take(&mut self, bytes: usize) -> Result<u32, String> {
+        let len = if self.ascii { bytes * 2 } else { bytes };
+        let chunk = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| String::from("Short MC response"))?;
+        self.pos += len;
+        if self.ascii {
+            let text = std::str::from_utf8(chunk).map_err(|e| e.to_string())?;
+            u32::from_str_radix(text, 16).map_err(|e| e.to_string())
+        } else {
+            Ok(chunk.iter().rev().fold(0u32, |acc, b| acc << 8 | *b as u32))
+        }
+    }
+
+    fn word(&mut self) -> Result<u16, String> {
+        self.take(2).map(|v| v as u16)
+    }
+
+    fn dword(&mut self) -> Result<u32, String> {
+        self.take(4)
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+    broken: bool,
+}
+
+#[derive(Debug)]
+pub struct Client {
+    host: String,
+    config: McConfig,
+    timeout: Duration,
+    reg: Regex,
+    conn: Mutex<Option<TcpStream>>,
+}
+
+impl Client {
+    pub fn new(config: McConfig) -> Self {
+        Self {
+            host: String::new(),
+            config,
+            timeout: Duration::from_secs(3),
+            reg: address_regex(),
+            conn: Mutex::new(None),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    // `host` is `address` or `address:port`
+    pub fn connect(&mut self, host: &str) {
+        self.host = host.to_owned();
+        match self.open(host) {
+            Ok(stream) => {
+                info!("MC protocol connected to {}", host);
+                *self.conn.lock().unwrap() = Some(stream);
+            }
+            Err(err) => {
+                info!("Connect to {} failed: {}", host, err);
+                *self.conn.lock().unwrap() = None;
+            }
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.conn.lock().unwrap().take();
+    }
+
+    pub fn connected(&mut self) -> bool {
+        self.conn.lock().unwrap().is_some()
+    }
+
+    pub fn conv_address(&self, address: &str, datatype: ETagtype) -> Result<McAddress, String> {
+        McAddress::parse(&self.reg, address, datatype)
+    }
+
+    fn open(&self, host: &str) -> Result<TcpStream, String> {
+        let addr = if host.contains(':') {
+            host.to_socket_addrs()
+        } else {
+            (host, MC_DEFAULT_PORT).to_socket_addrs()
+        }
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("Cannot resolve {}", host))?;
+        let stream = TcpStream::connect_timeout(&addr, self.timeout).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(|e| e.to_string())?;
+        stream
+            .set_write_timeout(Some(self.timeout))
+            .map_err(|e| e.to_string())?;
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        Ok(stream)
+    }
+
+    // Sends one 3E frame and returns the response data after the end code
+    fn command(&self, command: u16, subcommand: u16, fields: &[Field]) -> Result<Vec<u8>, String> {
+        let ascii = self.config.ascii;
+        let mut body = encode_fields(
+            &[
+                Field::Word(self.config.timer),
+                Field::Word(command),
+                Field::Word(subcommand),
+            ],
+            ascii,
+        );
+        body.extend(encode_fields(fields, ascii));
+        let mut frame = if ascii {
+            b"5000".to_vec()
+        } else {
+            vec![0x50, 0x00]
+        };
+        frame.extend(encode_fields(
+            &[
+                Field::Byte(self.config.network),
+                Field::Byte(self.config.pc),
+                Field::Word(self.config.module_io),
+                Field::Byte(self.config.station),
+                Field::Word(body.len() as u16),
+            ],
+            ascii,
+        ));
+        frame.extend(body);
+
+        let mut guard = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let stream = guard.take().ok_or_else(|| String::from("Not connected"))?;
+        let mut conn = Connection {
+            stream,
+            broken: false,
+        };
+        let result = conn.exchange(&frame, ascii);
+        if !conn.broken {
+            *guard = Some(conn.stream);
+        }
+        result
+    }
+
+    pub fn batch_read_words(
+        &self,
+        device: McDevice,
+        number: u32,
+        points: u16,
+    ) -> Result<Vec<u16>, String> {
+        let data = self.command(
+            CMD_BATCH_READ,
+            SUB_WORD,
+            &[Field::Device(device, number), Field::Word(points)],
+        )?;
+        let mut resp = Response {
+            data: &data,
+            pos: 0,
+            ascii: self.config.ascii,
+        };
+        (0..points).map(|_| resp.word()).collect()
+    }
+
+    pub fn batch_read_bits(
+        &self,
+        device: McDevice,
+        number: u32,
+        points: u16,
+    ) -> Result<Vec<bool>, String> {
+        let data = self.command(
+            CMD_BATCH_READ,
+            SUB_BIT,
+            &[Field::Device(device, number), Field::Word(points)],
+        )?;
+        // One character per point in ASCII, two points per byte in binary
+        (0..points as usize)
+            .map(|i| {
+                if self.config.ascii {
+                    data.get(i).map(|c| *c == b'1')
+                } else {
+                    data.get(i / 2)
+                        .map(|b| if i % 2 == 0 { b >> 4 } else { b & 0x0F } != 0)
+                }
+                .ok_or_else(|| String::from("Short MC response"))
+            })
+            .collect()
+    }
+
+    pub fn batch_write_words(
+        &self,
+        device: McDevice,
+        number: u32,
+        values: &[u16],
+    ) -> Result<(), String> {
+        let mut fields = vec![
+            Field::Device(device, number),
+            Field::Word(values.len() as u16),
+        ];
+        fields.extend(values.iter().map(|v| Field::Word(*v)));
+        self.command(CMD_BATCH_WRITE, SUB_WORD, &fields).map(|_| ())
+    }
+
+    pub fn batch_write_bits(
+        &self,
+        device: McDevice,
+        number: u32,
+        values: &[bool],
+    ) -> Result<(), String> {
+        // One character per point in ASCII, two points per byte in binary
+        let data = if self.config.ascii {
+            values
+                .iter()
+                .map(|v| if *v { b'1' } else { b'0' })
+                .collect()
+        } else {
+            values
+                .chunks(2)
+                .map(|pair| {
+                    let high = if pair[0] { 0x10 } else { 0x00 };
+                    let low = if pair.len() > 1 && pair[1] {
+                        0x01
+                    } else {
+                        0x00
+                    };
+                    high | low
+                })
+                .collect()
+        };
+        let fields = vec![
+            Field::Device(device, number),
+            Field::Word(values.len() as u16),
+            Field::Raw(data),
+        ];
+        self.command(CMD_BATCH_WRITE, SUB_BIT, &fields).map(|_| ())
+    }
+
+    // Reads word and double word points of any devices in one request.
+    // Bit devices are read as the word starting at the point.
+    pub fn random_read(
+        &self,
+        words: &[(McDevice, u32)],
+        dwords: &[(McDevice, u32)],
+    ) -> Result<(Vec<u16>, Vec<u32>), String> {
+        let mut fields = vec![
+            Field::Byte(words.len() as u8),
+            Field::Byte(dwords.len() as u8),
+        ];
+        fields.extend(words.iter().map(|(d, n)| Field::Device(*d, *n)));
+        fields.extend(dwords.iter().map(|(d, n)| Field::Device(*d, *n)));
+        let data = self.command(CMD_RANDOM_READ, SUB_WORD, &fields)?;
+        let mut resp = Response {
+            data: &data,
+            pos: 0,
+            ascii: self.config.ascii,
+        };
+        let w = words
+            .iter()
+            .map(|_| resp.word())
+            .collect::<Result<Vec<_>, String>>()?;
+        let d = dwords
+            .iter()
+            .map(|_| resp.dword())
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok((w, d))
+    }
+
+    pub fn random_write_words(
+        &self,
+        words: &[(McDevice, u32, u16)],
+        dwords: &[(McDevice, u32, u32)],
+    ) -> Result<(), String> {
+        let mut fields = vec![
+            Field::Byte(words.len() as u8),
+            Field::Byte(dwords.len() as u8),
+        ];
+        for (d, n, v) in words {
+            fields.push(Field::Device(*d, *n));
+            fields.push(Field::Word(*v));
+        }
+        for (d, n, v) in dwords {
+            fields.push(Field::Device(*d, *n));
+            fields.push(Field::DWord(*v));
+        }
+        self.command(CMD_RANDOM_WRITE, SUB_WORD, &fields)
+            .map(|_| ())
+    }
+
+    pub fn random_write_bits(&self, bits: &[(McDevice, u32, bool)]) -> Result<(), String> {
+        let mut fields = vec![Field::Byte(bits.len() as u8)];
+        for (d, n, v) in bits {
+            fields.push(Field::Device(*d, *n));
+            fields.push(Field::Byte(*v as u8));
+        }
+        self.command(CMD_RANDOM_WRITE, SUB_BIT, &fields).map(|_| ())
+    }
+
+    fn read_addresses(
+        &self,
+        addrs: &[McAddress],
+    ) -> Result<Vec<Result<ETagValue, String>>, String> {
+        let mut values = Vec::with_capacity(addrs.len());
+        for chunk in addrs.chunks(MAX_RANDOM_POINTS) {
+            let words: Vec<(McDevice, u32)> = chunk
+                .iter()
+                .filter(|a| !a.dword())
+                .map(|a| (a.device, a.number))
+                .collect();
+            let dwords: Vec<(McDevice, u32)> = chunk
+                .iter()
+                .filter(|a| a.dword())
+                .map(|a| (a.device, a.number))
+                .collect();
+            let (w, d) = self.random_read(&words, &dwords)?;
+            let (mut w, mut d) = (w.into_iter(), d.into_iter());
+            for addr in chunk {
+                values.push(if addr.dword() {
+                    let v = d.next().unwrap();
+                    Ok(match addr.datatype {
+                        ETagtype::REAL => ETagValue::Real(f32::from_bits(v) as f64),
+                        _ => ETagValue::Int(v as i32 as i64),
+                    })
+                } else {
+                    let v = w.next().unwrap();
+                    Ok(match addr.datatype {
+                        ETagtype::BOOL => ETagValue::Bool(v >> addr.bit.unwrap_or(0) & 1 != 0),
+                        _ => ETagValue::Int(v as i16 as i64),
+                    })
+                });
+            }
+        }
+        Ok(values)
+    }
+
+    fn write_address(
+        &self,
+        tag: &ETag,
+        addr: &McAddress,
+        write: ETagValue,
+    ) -> Result<bool, String> {
+        let write = tag.datatype.coerce(write, tag.range)?;
+        match (addr.bit, write) {
+            (None, ETagValue::Bool(v)) => {
+                self.random_write_bits(&[(addr.device, addr.number, v)])?
+            }
+            (Some(bit), ETagValue::Bool(v)) => {
+                // Read-modify-write of the word holding the bit
+                let word = self.random_read(&[(addr.device, addr.number)], &[])?.0[0];
+                let word = if v {
+                    word | 1 << bit
+                } else {
+                    word & !(1 << bit)
+                };
+                self.random_write_words(&[(addr.device, addr.number, word)], &[])?
+            }
+            (_, ETagValue::Int(v)) if tag.datatype == ETagtype::INT => {
+                self.random_write_words(&[(addr.device, addr.number, v as i16 as u16)], &[])?
+            }
+            (_, ETagValue::Int(v)) => {
+                self.random_write_words(&[], &[(addr.device, addr.number, v as i32 as u32)])?
+            }
+            (_, ETagValue::Real(v)) => {
+                self.random_write_words(&[], &[(addr.device, addr.number, (v as f32).to_bits())])?
+            }
+            _ => return Err(String::from("Invalid datatype for write value")),
+        }
+        Ok(true)
+    }
+}
+
+impl Connection {
+    // The stream is left in an unknown state after an I/O error
+    fn io<T>(&mut self, r: std::io::Result<T>) -> Result<T, String> {
+        r.map_err(|e| {
+            self.broken = true;
+            e.to_string()
+        })
+    }
+
+    fn exchange(&mut self, frame: &[u8], ascii: bool) -> Result<Vec<u8>, String> {
+        let r = self.stream.write_all(frame);
+        self.io(r)?;
+
+        let mut header = vec![0u8; if ascii { 18 } else { 9 }];
+        let r = self.stream.read_exact(&mut header);
+        self.io(r)?;
+        let mut resp = Response {
+            data: &header,
+            pos: if ascii { 4 } else { 2 },
+            ascii,
+        };
+        let valid = if ascii {
+            &header[0..4] == b"D000"
+        } else {
+            header[0..2] == [0xD0, 0x00]
+        };
+        // Network, PC, module I/O and station echo the request
+        for bytes in &[1, 1, 2, 1] {
+            resp.take(*bytes)?;
+        }
+        let len = resp.word()? as usize;
+        if !valid || len < if ascii { 4 } else { 2 } {
+            self.broken = true;
+            return Err(String::from("Invalid MC response header"));
+        }
+        let mut data = vec![0u8; len];
+        let r = self.stream.read_exact(&mut data);
+        self.io(r)?;
+        let mut resp = Response {
+            data: &data,
+            pos: 0,
+            ascii,
+        };
+        let end_code = resp.word()?;
+        if end_code != 0 {
+            return Err(format!("MC end code 0x{:04X}", end_code));
+        }
+        Ok(data[resp.pos..].to_vec())
+    }
+}
+
+impl ETagRW for Client {
+    fn read_tag(&self, tag: &ETag) -> Result<ETagValue, String> {
+        let addr = self.conv_address(tag.address.as_str(), tag.datatype)?;
+        self.read_addresses(&[addr])?.pop().unwrap()
+    }
+    fn read_list(&self, tags: &Vec<ETag>) -> Result<Vec<Result<ETagValue, String>>, String> {
+        let addrs = tags
+            .iter()
+            .map(|tag| self.conv_address(tag.address.as_str(), tag.datatype))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        self.read_addresses(&addrs)
+    }
+    fn write_tag(&self, tag: &ETag, write: ETagValue) -> Result<bool, String> {
+        let addr = self.conv_address(tag.address.as_str(), tag.datatype)?;
+        self.write_address(tag, &addr, write)
+    }
+    fn write_list(
+        &self,
+        tags: &Vec<(ETag, ETagValue)>,
+    ) -> Result<Vec<Result<bool, String>>, String> {
+        let addrs = tags
+            .iter()
+            .map(|t| self.conv_address(t.0.address.as_str(), t.0.datatype))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        Ok(tags
+            .iter()
+            .zip(addrs)
+            .map(|((tag, write), addr)| self.write_address(tag, &addr, write.clone()))
+            .collect())
+    }
+}
+
+// mc://host[:port]?ascii=false&network=0&pc=255&station=0
+pub fn register(registry: &mut Registry) {
+    registry.register_device(
+        "mc",
+        |url| {
+            let host = registry::host(url)?;
+            let default = McConfig::default();
+            let mut client = Client::new(McConfig {
+                network: param(url, "network", default.network)?,
+                pc: param(url, "pc", default.pc)?,
+                station: param(url, "station", default.station)?,
+                ascii: param(url, "ascii", default.ascii)?,
+                ..default
+            });
+            client.connect(host.as_str());
+            if client.connected() {
+                Ok(client)
+            } else {
+                Err(format!("Connect to {} failed", host))
+            }
+        },
+        Client::connected,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plc_driver::tests::tag;
+    use std::collections::BTreeMap;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    const DEVICES: [McDevice; 10] = [
+        McDevice::X,
+        McDevice::Y,
+        McDevice::M,
+        McDevice::L,
+        McDevice::B,
+        McDevice::SM,
+        McDevice::D,
+        McDevice::W,
+        McDevice::R,
+        McDevice::SD,
+    ];
+
+    // Word devices hold words, bit devices one bit per point
+    type Memory = BTreeMap<(McDevice, u32), u16>;
+
+    fn device(req: &mut Response) -> (McDevice, u32) {
+        if req.ascii {
+            let code = &req.data[req.pos..req.pos + 2];
+            let device = *DEVICES
+                .iter()
+                .find(|d| d.ascii_code().as_bytes() == code)
+                .unwrap();
+            let number = std::str::from_utf8(&req.data[req.pos + 2..req.pos + 8]).unwrap();
+            let radix = if device.is_hex() { 16 } else { 10 };
+            req.pos += 8;
+            (device, u32::from_str_radix(number, radix).unwrap())
+        } else {
+            let number = req.take(3).unwrap();
+            let code = req.take(1).unwrap() as u8;
+            (*DEVICES.iter().find(|d| d.code() == code).unwrap(), number)
+        }
+    }
+
+    fn read_word(memory: &Memory, (device, number): (McDevice, u32)) -> u16 {
+        if device.is_bit() {
+            (0..16).fold(0, |word, i| {
+                word | memory.get(&(device, number + i)).copied().unwrap_or(0) << i
+            })
+        } else {
+            memory.get(&(device, number)).copied().unwrap_or(0)
+        }
+    }
+
+    // Answers random reads and writes, the commands the tag interface uses
+    fn execute(req: &mut Response, memory: &mut Memory) -> Result<Vec<Field>, u16> {
+        let command = req.word().unwrap();
+        let subcommand = req.word().unwrap();
+        match (command, subcommand) {
+            (CMD_RANDOM_READ, SUB_WORD) => {
+                let words = req.take(1).unwrap();
+                let dwords = req.take(1).unwrap();
+                let mut fields = Vec::new();
+                for _ in 0..words {
+                    fields.push(Field::Word(read_word(memory, device(req))));
+                }
+                for _ in 0..dwords {
+                    let (d, n) = device(req);
+                    let low = read_word(memory, (d, n)) as u32;
+                    let high = read_word(memory, (d, n + 1)) as u32;
+                    fields.push(Field::DWord(high << 16 | low));
+                }
+                Ok(fields)
+            }
+            (CMD_RANDOM_WRITE, SUB_WORD) => {
+                let words = req.take(1).unwrap();
+                let dwords = req.take(1).unwrap();
+                for _ in 0..words {
+                    let point = device(req);
+                    memory.insert(point, req.word().unwrap());
+                }
+                for _ in 0..dwords {
+                    let (d, n) = device(req);
+                    let v = req.dword().unwrap();
+                    memory.insert((d, n), v as u16);
+                    memory.insert((d, n + 1), (v >> 16) as u16);
+                }
+                Ok(Vec::new())
+            }
+            (CMD_RANDOM_WRITE, SUB_BIT) => {
+                for _ in 0..req.take(1).unwrap() {
+                    let point = device(req);
+                    memory.insert(point, req.take(1).unwrap() as u16);
+                }
+                Ok(Vec::new())
+            }
+            _ => Err(0xC059),
+        }
+    }
+
+    fn serve(mut stream: TcpStream, memory: Arc<Mutex<Memory>>) {
+        let mut subheader = [0u8; 2];
+        while stream.read_exact(&mut subheader).is_ok() {
+            // ASCII subheaders are "5000", only "50" has been read yet
+            let ascii = subheader == *b"50";
+            let mut header = vec![0u8; if ascii { 16 } else { 7 }];
+            stream.read_exact(&mut header).unwrap();
+            let len = Response {
+                data: &header,
+                pos: header.len() - if ascii { 4 } else { 2 },
+                ascii,
+            }
+            .word()
+            .unwrap();
+            let mut body = vec![0u8; len as usize];
+            stream.read_exact(&mut body).unwrap();
+            let mut req = Response {
+                data: &body,
+                pos: if ascii { 4 } else { 2 },
+                ascii,
+            };
+            let (end_code, mut fields) = match execute(&mut req, &mut memory.lock().unwrap()) {
+                Ok(fields) => (0, fields),
+                Err(code) => (code, Vec::new()),
+            };
+            fields.insert(0, Field::Word(end_code));
+            let data = encode_fields(&fields, ascii);
+            let mut frame = if ascii {
+                b"D000".to_vec()
+            } else {
+                vec![0xD0, 0x00]
+            };
+            // Network, PC, module I/O and station, after the rest of the
+            // ASCII subheader
+            if ascii {
+                frame.extend_from_slice(&header[2..header.len() - 4]);
+            } else {
+                frame.extend_from_slice(&header[..header.len() - 2]);
+            }
+            frame.extend(encode_fields(&[Field::Word(data.len() as u16)], ascii));
+            frame.extend(data);
+            stream.write_all(&frame).unwrap();
+        }
+    }
+
+    fn server() -> (String, Arc<Mutex<Memory>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let memory = Arc::new(Mutex::new(Memory::new()));
+        let shared = memory.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                serve(stream.unwrap(), shared.clone());
+            }
+        });
+        (host, memory)
+    }
+
+    fn client(host: &str, ascii: bool) -> Client {
+        let mut client = Client::new(McConfig {
+            ascii,
+            ..McConfig::default()
+        });
+        client.connect(host);
+        assert!(client.connected());
+        client
+    }
+
+    fn read_back(ascii: bool) {
+        let (host, memory) = server();
+        {
+            let mut memory = memory.lock().unwrap();
+            memory.insert((McDevice::D, 100), (-5i16) as u16);
+            let level = 1.5f32.to_bits();
+            memory.insert((McDevice::D, 200), level as u16);
+            memory.insert((McDevice::D, 201), (level >> 16) as u16);
+            memory.insert((McDevice::D, 10), 0x0008);
+            memory.insert((McDevice::M, 20), 1);
+            memory.insert((McDevice::X, 0x1F), 1);
+            memory.insert((McDevice::W, 0x1A), 0x7FFF);
+        }
+        let client = client(&host, ascii);
+        assert_eq!(
+            client
+                .read_list(&vec![
+                    tag("D100", ETagtype::INT),
+                    tag("D200", ETagtype::REAL),
+                    tag("D10.3", ETagtype::BOOL),
+                    tag("D10.4", ETagtype::BOOL),
+                    tag("M20", ETagtype::BOOL),
+                    tag("X1F", ETagtype::BOOL),
+                    tag("Y1F", ETagtype::BOOL),
+                    tag("W1A", ETagtype::INT),
+                ])
+                .unwrap(),
+            vec![
+                Ok(ETagValue::Int(-5)),
+                Ok(ETagValue::Real(1.5)),
+                Ok(ETagValue::Bool(true)),
+                Ok(ETagValue::Bool(false)),
+                Ok(ETagValue::Bool(true)),
+                Ok(ETagValue::Bool(true)),
+                Ok(ETagValue::Bool(false)),
+                Ok(ETagValue::Int(0x7FFF)),
+            ]
+        );
+    }
+
+    fn write_then_read_back(ascii: bool) {
+        let (host, memory) = server();
+        memory.lock().unwrap().insert((McDevice::D, 10), 0x0101);
+        let client = client(&host, ascii);
+        let writes = vec![
+            (tag("D100", ETagtype::INT), ETagValue::Int(1234)),
+            (tag("D300", ETagtype::DINT), ETagValue::Int(-70_000)),
+            (tag("D10.F", ETagtype::BOOL), ETagValue::Bool(true)),
+            (tag("D10.0", ETagtype::BOOL), ETagValue::Bool(false)),
+            (tag("M5", ETagtype::BOOL), ETagValue::Bool(true)),
+            (tag("R7", ETagtype::REAL), ETagValue::Real(-0.25)),
+        ];
+        assert_eq!(client.write_list(&writes).unwrap(), vec![Ok(true); 6]);
+        // The other bits of D10 are left as they were
+        assert_eq!(memory.lock().unwrap()[&(McDevice::D, 10)], 0x8100);
+        let tags: Vec<ETag> = writes.iter().map(|w| w.0.clone()).collect();
+        let values: Vec<Result<ETagValue, String>> =
+            writes.iter().map(|w| Ok(w.1.clone())).collect();
+        assert_eq!(client.read_list(&tags).unwrap(), values);
+    }
+
+    #[test]
+    fn binary_read() {
+        read_back(false);
+    }
+
+    #[test]
+    fn ascii_read() {
+        read_back(true);
+    }
+
+    #[test]
+    fn binary_write() {
+        write_then_read_back(false);
+    }
+
+    #[test]
+    fn ascii_write() {
+        write_then_read_back(true);
+    }
+
+    #[test]
+    fn rejected_command_reports_the_end_code() {
+        let (host, _) = server();
+        let client = client(&host, false);
+        assert_eq!(
+            client.batch_read_words(McDevice::D, 0, 4),
+            Err(String::from("MC end code 0xC059"))
+        );
+        // The connection survives an error response
+        assert!(client.read_tag(&tag("D0", ETagtype::INT)).is_ok());
+    }
+}
@@ -1,6 +1,52 @@
-pub mod s7;
+pub mod io_thread;
+pub mod registry;
+pub mod s7_address;
+pub mod write_set;
 
+// Declares each driver module behind its cargo feature. A driver module that
+// is built registers its schemes through its `register` function, there is
+// no second list to keep in step.
+macro_rules! drivers {
+    ($($feature:literal => $module:ident,)*) => {
+        $(
+            #[cfg(feature = $feature)]
+            pub mod $module;
+        )*
+
+        // Every driver compiled into this build
+        #[allow(unused_variables)]
+        pub(crate) fn register_drivers(registry: &mut registry::Registry) {
+            $(
+                #[cfg(feature = $feature)]
+                $module::register(registry);
+            )*
+        }
+    };
+}
+
+drivers! {
+    "bacnet" => bacnet,
+    "cip" => cip,
+    "fins" => fins,
+    "line" => line,
+    "mc" => mc,
+    "modbus" => modbus,
+    "opcua" => opcua,
+    "snap7" => s7,
+    "s7comm" => s7comm,
+    "sim" => sim,
+}
+
+#[cfg(all(feature = "s7comm", not(feature = "snap7")))]
+pub use self::s7comm as s7;
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use log::warn;
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::Duration;
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ETagtype {
@@ -12,27 +58,413 @@ pub enum ETagtype {
 }
 impl ETagtype {
     pub fn is_bool(&self) -> bool {
+        matches!(self, ETagtype::BOOL)
+    }
+
+    pub fn range(&self) -> Option<(i64, i64)> {
         match self {
-            ETagtype::BOOL => true,
-            _ => false,
+            ETagtype::INT => Some((i16::MIN as i64, i16::MAX as i64)),
+            ETagtype::DINT => Some((i32::MIN as i64, i32::MAX as i64)),
+            _ => None,
+        }
+    }
+
+    // Brings a write value into the representation of the target type.
+    // Int to REAL is exact up to 2^24, beyond that it is out of range unless
+    // clamped, in which case it is rounded to the nearest f32 like any Real.
+    pub fn coerce(&self, value: ETagValue, policy: ERangePolicy) -> Result<ETagValue, EValueError> {
+        let out_of_range = || EValueError::OutOfRange {
+            datatype: *self,
+            value: value.clone(),
+        };
+        match (self, &value) {
+            (ETagtype::BOOL, ETagValue::Bool(_)) => Ok(value),
+            (ETagtype::INT, &ETagValue::Int(v)) | (ETagtype::DINT, &ETagValue::Int(v)) => {
+                let (min, max) = self.range().unwrap();
+                if v >= min && v <= max {
+                    Ok(value)
+                } else if policy == ERangePolicy::Clamp {
+                    warn!("{} clamped to {:?} range", v, self);
+                    Ok(ETagValue::Int(v.max(min).min(max)))
+                } else {
+                    Err(out_of_range())
+                }
+            }
+            (ETagtype::REAL, &ETagValue::Int(v)) => {
+                let exact = 1i64 << f32::MANTISSA_DIGITS;
+                if (v >= -exact && v <= exact) || policy == ERangePolicy::Clamp {
+                    Ok(ETagValue::Real(v as f32 as f64))
+                } else {
+                    Err(out_of_range())
+                }
+            }
+            (ETagtype::REAL, &ETagValue::Real(v)) => {
+                let max = f32::MAX as f64;
+                if v.is_finite() && v.abs() > max {
+                    if policy == ERangePolicy::Clamp {
+                        warn!("{} clamped to REAL range", v);
+                        Ok(ETagValue::Real(v.max(-max).min(max)))
+                    } else {
+                        Err(out_of_range())
+                    }
+                } else if v != 0.0 && v.abs() < f32::MIN_POSITIVE as f64 {
+                    if policy == ERangePolicy::Clamp {
+                        warn!("{} rounded to REAL precision", v);
+                        Ok(ETagValue::Real(v as f32 as f64))
+                    } else {
+                        Err(out_of_range())
+                    }
+                } else {
+                    Ok(ETagValue::Real(v as f32 as f64))
+                }
+            }
+            _ => Err(EValueError::Mismatch {
+                datatype: *self,
+                value,
+            }),
+        }
+    }
+
+    pub fn same_value(&self, written: &ETagValue, observed: &ETagValue) -> bool {
+        match (self, written, observed) {
+            (ETagtype::REAL, &ETagValue::Real(a), &ETagValue::Real(b)) => a as f32 == b as f32,
+            (ETagtype::REAL, &ETagValue::Int(a), &ETagValue::Real(b)) => a as f32 == b as f32,
+            _ => written == observed,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+// Serialized externally tagged: {"Bool":true}, {"Int":5}, {"Real":1.5},
+// {"String":"abc"}, {"Bytes":[1,2]}, {"Array":[{"Int":1}]},
+// {"Struct":[{"name":"a","value":{"Int":1}}]},
+// {"Timestamp":"2020-01-01T00:00:00Z"}, {"Duration":{"secs":1,"nanos":0}}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum ETagValue {
     Bool(bool),
     Int(i64),
     Real(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Array(Vec<ETagValue>),
+    Struct(Vec<EField>),
+    Timestamp(DateTime<Utc>),
+    Duration(Duration),
+}
+
+// A struct member, members keep the order of the source type
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EField {
+    pub name: String,
+    pub value: ETagValue,
+}
+
+impl EField {
+    pub fn new<V: Into<ETagValue>>(name: &str, value: V) -> Self {
+        Self {
+            name: name.to_owned(),
+            value: value.into(),
+        }
+    }
+}
+
+impl ETagValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ETagValue::Bool(_) => "Bool",
+            ETagValue::Int(_) => "Int",
+            ETagValue::Real(_) => "Real",
+            ETagValue::String(_) => "String",
+            ETagValue::Bytes(_) => "Bytes",
+            ETagValue::Array(_) => "Array",
+            ETagValue::Struct(_) => "Struct",
+            ETagValue::Timestamp(_) => "Timestamp",
+            ETagValue::Duration(_) => "Duration",
+        }
+    }
+
+    // Scalars only: booleans read as 0 and 1
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            ETagValue::Bool(v) => Some(*v as i64 as f64),
+            ETagValue::Int(v) => Some(*v as f64),
+            ETagValue::Real(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    // Reals only when they hold a whole number
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            ETagValue::Bool(v) => Some(*v as i64),
+            ETagValue::Int(v) => Some(*v),
+            ETagValue::Real(v) if v.fract() == 0.0 && v.abs() < i64::MAX as f64 => {
+                Some(*v as i64)
+            }
+            _ => None,
+        }
+    }
+
+    // Numbers are true when not zero
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            ETagValue::Bool(v) => Some(*v),
+            ETagValue::Int(v) => Some(*v != 0),
+            ETagValue::Real(v) => Some(*v != 0.0),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            ETagValue::String(v) => Some(v.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            ETagValue::Bytes(v) => Some(v.as_slice()),
+            ETagValue::String(v) => Some(v.as_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[ETagValue]> {
+        match self {
+            ETagValue::Array(v) => Some(v.as_slice()),
+            _ => None,
+        }
+    }
+
+    // A member of a Struct by name
+    pub fn field(&self, name: &str) -> Option<&ETagValue> {
+        match self {
+            ETagValue::Struct(fields) => fields.iter().find(|f| f.name == name).map(|f| &f.value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ETagValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ETagValue::Bool(v) => write!(f, "{}", v),
+            ETagValue::Int(v) => write!(f, "{}", v),
+            ETagValue::Real(v) => write!(f, "{}", v),
+            ETagValue::String(v) => write!(f, "{}", v),
+            ETagValue::Bytes(v) => {
+                for b in v {
+                    write!(f, "{:02X}", b)?;
+                }
+                Ok(())
+            }
+            ETagValue::Array(v) => {
+                write!(f, "[")?;
+                for (i, item) in v.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            ETagValue::Struct(v) => {
+                write!(f, "{{")?;
+                for (i, field) in v.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", field.name, field.value)?;
+                }
+                write!(f, "}}")
+            }
+            ETagValue::Timestamp(v) => write!(f, "{}", v.to_rfc3339()),
+            ETagValue::Duration(v) => write!(f, "{:?}", v),
+        }
+    }
+}
+
+impl From<bool> for ETagValue {
+    fn from(v: bool) -> Self {
+        ETagValue::Bool(v)
+    }
+}
+
+impl From<i16> for ETagValue {
+    fn from(v: i16) -> Self {
+        ETagValue::Int(v as i64)
+    }
+}
+
+impl From<i32> for ETagValue {
+    fn from(v: i32) -> Self {
+        ETagValue::Int(v as i64)
+    }
+}
+
+impl From<i64> for ETagValue {
+    fn from(v: i64) -> Self {
+        ETagValue::Int(v)
+    }
+}
+
+impl From<f32> for ETagValue {
+    fn from(v: f32) -> Self {
+        ETagValue::Real(v as f64)
+    }
+}
+
+impl From<f64> for ETagValue {
+    fn from(v: f64) -> Self {
+        ETagValue::Real(v)
+    }
+}
+
+impl From<String> for ETagValue {
+    fn from(v: String) -> Self {
+        ETagValue::String(v)
+    }
+}
+
+impl From<&str> for ETagValue {
+    fn from(v: &str) -> Self {
+        ETagValue::String(v.to_owned())
+    }
+}
+
+impl From<Vec<u8>> for ETagValue {
+    fn from(v: Vec<u8>) -> Self {
+        ETagValue::Bytes(v)
+    }
+}
+
+impl From<DateTime<Utc>> for ETagValue {
+    fn from(v: DateTime<Utc>) -> Self {
+        ETagValue::Timestamp(v)
+    }
+}
+
+impl From<Duration> for ETagValue {
+    fn from(v: Duration) -> Self {
+        ETagValue::Duration(v)
+    }
+}
+
+fn conversion_error(value: &ETagValue, target: &str) -> String {
+    format!("Cannot convert {} to {}", value.type_name(), target)
+}
+
+impl TryFrom<ETagValue> for bool {
+    type Error = String;
+    fn try_from(value: ETagValue) -> Result<Self, String> {
+        value.as_bool().ok_or_else(|| conversion_error(&value, "bool"))
+    }
+}
+
+impl TryFrom<ETagValue> for i64 {
+    type Error = String;
+    fn try_from(value: ETagValue) -> Result<Self, String> {
+        value.as_i64().ok_or_else(|| conversion_error(&value, "i64"))
+    }
+}
+
+impl TryFrom<ETagValue> for f64 {
+    type Error = String;
+    fn try_from(value: ETagValue) -> Result<Self, String> {
+        value.as_f64().ok_or_else(|| conversion_error(&value, "f64"))
+    }
+}
+
+impl TryFrom<ETagValue> for String {
+    type Error = String;
+    fn try_from(value: ETagValue) -> Result<Self, String> {
+        match value {
+            ETagValue::String(v) => Ok(v),
+            value => Err(conversion_error(&value, "String")),
+        }
+    }
+}
+
+impl TryFrom<ETagValue> for Vec<u8> {
+    type Error = String;
+    fn try_from(value: ETagValue) -> Result<Self, String> {
+        match value {
+            ETagValue::Bytes(v) => Ok(v),
+            ETagValue::String(v) => Ok(v.into_bytes()),
+            value => Err(conversion_error(&value, "Vec<u8>")),
+        }
+    }
+}
+
+impl TryFrom<ETagValue> for DateTime<Utc> {
+    type Error = String;
+    fn try_from(value: ETagValue) -> Result<Self, String> {
+        match value {
+            ETagValue::Timestamp(v) => Ok(v),
+            value => Err(conversion_error(&value, "timestamp")),
+        }
+    }
+}
+
+impl TryFrom<ETagValue> for Duration {
+    type Error = String;
+    fn try_from(value: ETagValue) -> Result<Self, String> {
+        match value {
+            ETagValue::Duration(v) => Ok(v),
+            value => Err(conversion_error(&value, "duration")),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ERangePolicy {
+    #[default]
+    Reject,
+    Clamp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EValueError {
+    OutOfRange { datatype: ETagtype, value: ETagValue },
+    Mismatch { datatype: ETagtype, value: ETagValue },
+}
+
+impl fmt::Display for EValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EValueError::OutOfRange { datatype, value } => {
+                write!(f, "Value {:?} is out of range for {:?}", value, datatype)
+            }
+            EValueError::Mismatch { datatype, value } => {
+                write!(f, "Invalid datatype for write value {:?} to {:?}", value, datatype)
+            }
+        }
+    }
+}
+
+impl From<EValueError> for String {
+    fn from(err: EValueError) -> Self {
+        err.to_string()
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ETag {
     pub name: String,
     pub address: String,
     pub datatype: ETagtype,
+    #[serde(default)]
+    pub range: ERangePolicy,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum EWriteCheck {
+    Verified,
+    Mismatch(ETagValue),
+}
+
+// Every driver implements these signatures, so they keep taking &Vec
+#[allow(clippy::ptr_arg)]
 pub trait ETagRW {
     fn read_tag(&self, tag: &ETag) -> Result<ETagValue, String>;
     fn read_list(&self, tags: &Vec<ETag>) -> Result<Vec<Result<ETagValue, String>>, String>;
@@ -41,4 +473,250 @@ pub trait ETagRW {
         &self,
         tags: &Vec<(ETag, ETagValue)>,
     ) -> Result<Vec<Result<bool, String>>, String>;
+
+    fn write_tag_verified(&self, tag: &ETag, write: ETagValue) -> Result<EWriteCheck, String> {
+        self.write_tag(tag, write.clone())?;
+        let observed = self.read_tag(tag)?;
+        // Compare with what the driver actually sent, clamped writes included
+        let expected = tag.datatype.coerce(write, tag.range)?;
+        if tag.datatype.same_value(&expected, &observed) {
+            Ok(EWriteCheck::Verified)
+        } else {
+            Ok(EWriteCheck::Mismatch(observed))
+        }
+    }
+
+    fn write_list_verified(
+        &self,
+        tags: &Vec<(ETag, ETagValue)>,
+    ) -> Result<Vec<Result<EWriteCheck, String>>, String> {
+        let written = self.write_list(tags)?;
+        let read_back: Vec<ETag> = tags.iter().map(|t| t.0.clone()).collect();
+        let observed = self.read_list(&read_back)?;
+        let results = written
+            .into_iter()
+            .zip(observed)
+            .zip(tags)
+            .map(|((written, observed), (tag, write))| {
+                written?;
+                let observed = observed.map_err(|err| format!("Read back failed: {}", err))?;
+                let expected = tag.datatype.coerce(write.clone(), tag.range)?;
+                if tag.datatype.same_value(&expected, &observed) {
+                    Ok(EWriteCheck::Verified)
+                } else {
+                    Ok(EWriteCheck::Mismatch(observed))
+                }
+            })
+            .collect();
+        Ok(results)
+    }
+}
+
+#[allow(clippy::ptr_arg)]
+pub trait ETagRWAsync {
+    fn read_tag(&self, tag: &ETag) -> BoxFuture<'static, Result<ETagValue, String>>;
+    fn read_list(
+        &self,
+        tags: &Vec<ETag>,
+    ) -> BoxFuture<'static, Result<Vec<Result<ETagValue, String>>, String>>;
+    fn write_tag(&self, tag: &ETag, write: ETagValue) -> BoxFuture<'static, Result<bool, String>>;
+    fn write_list(
+        &self,
+        tags: &Vec<(ETag, ETagValue)>,
+    ) -> BoxFuture<'static, Result<Vec<Result<bool, String>>, String>>;
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    // Stores what a device would: the value after range coercion. Also the
+    // device behind the server tests.
+    #[derive(Default)]
+    pub(crate) struct Memory(Mutex<HashMap<String, ETagValue>>);
+
+    impl Memory {
+        pub(crate) fn get(&self, address: &str) -> Option<ETagValue> {
+            self.0.lock().unwrap().get(address).cloned()
+        }
+
+        pub(crate) fn set(&self, address: &str, value: ETagValue) {
+            self.0.lock().unwrap().insert(address.to_owned(), value);
+        }
+    }
+
+    impl ETagRW for Memory {
+        fn read_tag(&self, tag: &ETag) -> Result<ETagValue, String> {
+            self.get(&tag.address)
+                .ok_or_else(|| String::from("Not written"))
+        }
+        fn read_list(&self, tags: &Vec<ETag>) -> Result<Vec<Result<ETagValue, String>>, String> {
+            Ok(tags.iter().map(|t| self.read_tag(t)).collect())
+        }
+        fn write_tag(&self, tag: &ETag, write: ETagValue) -> Result<bool, String> {
+            let value = tag.datatype.coerce(write, tag.range)?;
+            self.0.lock().unwrap().insert(tag.address.clone(), value);
+            Ok(true)
+        }
+        fn write_list(
+            &self,
+            tags: &Vec<(ETag, ETagValue)>,
+        ) -> Result<Vec<Result<bool, String>>, String> {
+            Ok(tags
+                .iter()
+                .map(|t| self.write_tag(&t.0, t.1.clone()))
+                .collect())
+        }
+    }
+
+    pub(crate) fn tag(address: &str, datatype: ETagtype) -> ETag {
+        ETag {
+            name: address.to_owned(),
+            address: address.to_owned(),
+            datatype,
+            range: ERangePolicy::Reject,
+        }
+    }
+
+    // Every variant with its exact JSON, as documented on ETagValue
+    fn json_samples() -> Vec<(ETagValue, &'static str)> {
+        let timestamp = Utc.with_ymd_and_hms(2020, 1, 1, 12, 30, 45).unwrap()
+            + chrono::Duration::milliseconds(250);
+        vec![
+            (ETagValue::Bool(true), r#"{"Bool":true}"#),
+            (ETagValue::Int(-5), r#"{"Int":-5}"#),
+            (ETagValue::Real(1.5), r#"{"Real":1.5}"#),
+            (ETagValue::from("a\"b"), r#"{"String":"a\"b"}"#),
+            (ETagValue::Bytes(vec![0, 255]), r#"{"Bytes":[0,255]}"#),
+            (
+                ETagValue::Array(vec![ETagValue::Int(1), ETagValue::Bool(false)]),
+                r#"{"Array":[{"Int":1},{"Bool":false}]}"#,
+            ),
+            (
+                ETagValue::Struct(vec![EField::new("z", 1i64), EField::new("a", "x")]),
+                r#"{"Struct":[{"name":"z","value":{"Int":1}},{"name":"a","value":{"String":"x"}}]}"#,
+            ),
+            (
+                ETagValue::Timestamp(timestamp),
+                r#"{"Timestamp":"2020-01-01T12:30:45.250Z"}"#,
+            ),
+            (
+                ETagValue::Duration(Duration::from_millis(1500)),
+                r#"{"Duration":{"secs":1,"nanos":500000000}}"#,
+            ),
+        ]
+    }
+
+    #[test]
+    fn values_serialize_externally_tagged() {
+        for (value, json) in json_samples() {
+            assert_eq!(serde_json::to_string(&value).unwrap(), json);
+            assert_eq!(serde_json::from_str::<ETagValue>(json).unwrap(), value);
+        }
+        // Struct members keep their order, not a sorted one
+        let value: ETagValue = serde_json::from_str(
+            r#"{"Struct":[{"name":"b","value":{"Int":2}},{"name":"a","value":{"Int":1}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            ETagValue::Struct(vec![EField::new("b", 2i64), EField::new("a", 1i64)])
+        );
+        assert!(serde_json::from_str::<ETagValue>(r#"{"Float":1.5}"#).is_err());
+    }
+
+    #[test]
+    fn conversions_reject_other_variants() {
+        let text = ETagValue::from("5");
+        assert_eq!(
+            bool::try_from(text.clone()),
+            Err(String::from("Cannot convert String to bool"))
+        );
+        assert_eq!(
+            i64::try_from(ETagValue::Real(1.5)),
+            Err(String::from("Cannot convert Real to i64"))
+        );
+        assert_eq!(i64::try_from(ETagValue::Real(-3.0)), Ok(-3));
+        assert_eq!(
+            f64::try_from(ETagValue::Bytes(vec![1])),
+            Err(String::from("Cannot convert Bytes to f64"))
+        );
+        assert_eq!(
+            String::try_from(ETagValue::Int(5)),
+            Err(String::from("Cannot convert Int to String"))
+        );
+        assert_eq!(
+            Vec::<u8>::try_from(ETagValue::Array(vec![])),
+            Err(String::from("Cannot convert Array to Vec<u8>"))
+        );
+        assert_eq!(Vec::<u8>::try_from(text.clone()), Ok(b"5".to_vec()));
+        assert_eq!(
+            DateTime::<Utc>::try_from(ETagValue::Duration(Duration::from_secs(1))),
+            Err(String::from("Cannot convert Duration to timestamp"))
+        );
+        assert_eq!(
+            Duration::try_from(text),
+            Err(String::from("Cannot convert String to duration"))
+        );
+        assert_eq!(bool::try_from(ETagValue::Int(2)), Ok(true));
+        assert_eq!(f64::try_from(ETagValue::Bool(true)), Ok(1.0));
+    }
+
+    #[test]
+    fn accessors_return_none_on_other_variants() {
+        let fields = ETagValue::Struct(vec![EField::new("a", 1i64)]);
+        for (value, _) in json_samples() {
+            let scalar = matches!(
+                value,
+                ETagValue::Bool(_) | ETagValue::Int(_) | ETagValue::Real(_)
+            );
+            assert_eq!(value.as_f64().is_some(), scalar, "{:?}", value);
+            assert_eq!(value.as_bool().is_some(), scalar, "{:?}", value);
+            assert_eq!(
+                value.as_str().is_some(),
+                matches!(value, ETagValue::String(_)),
+                "{:?}",
+                value
+            );
+            assert_eq!(
+                value.as_bytes().is_some(),
+                matches!(value, ETagValue::String(_) | ETagValue::Bytes(_)),
+                "{:?}",
+                value
+            );
+            assert_eq!(
+                value.as_array().is_some(),
+                matches!(value, ETagValue::Array(_)),
+                "{:?}",
+                value
+            );
+        }
+        assert_eq!(ETagValue::Real(1.5).as_i64(), None);
+        assert_eq!(ETagValue::Real(f64::NAN).as_i64(), None);
+        assert_eq!(ETagValue::Real(1e30).as_i64(), None);
+        assert_eq!(ETagValue::from("1").as_i64(), None);
+        assert_eq!(fields.field("a"), Some(&ETagValue::Int(1)));
+        assert_eq!(fields.field("b"), None);
+        assert_eq!(ETagValue::Int(1).field("a"), None);
+    }
+
+    #[test]
+    fn clamped_write_verifies() {
+        let memory = Memory::default();
+        let clamped = ETag {
+            range: ERangePolicy::Clamp,
+            ..tag("a", ETagtype::INT)
+        };
+        assert_eq!(
+            memory.write_tag_verified(&clamped, ETagValue::Int(100_000)),
+            Ok(EWriteCheck::Verified)
+        );
+        let results = memory
+            .write_list_verified(&vec![(clamped, ETagValue::Int(-100_000))])
+            .unwrap();
+        assert_eq!(results, vec![Ok(EWriteCheck::Verified)]);
+    }
 }
@@ -0,0 +1,726 @@
+#[cfg(feature = "modbus-rtu")]
+pub mod rtu;
+pub mod server;
+pub mod tcp;
+
+use super::registry::{param, Registry};
+use super::{ETag, ETagRW, ETagValue, ETagtype};
+use log::warn;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use url::Url;
+
+pub const FC_READ_COILS: u8 = 0x01;
+pub const FC_READ_DISCRETE_INPUTS: u8 = 0x02;
+pub const FC_READ_HOLDING_REGISTERS: u8 = 0x03;
+pub const FC_READ_INPUT_REGISTERS: u8 = 0x04;
+pub const FC_WRITE_SINGLE_COIL: u8 = 0x05;
+pub const FC_WRITE_SINGLE_REGISTER: u8 = 0x06;
+pub const FC_WRITE_MULTIPLE_COILS: u8 = 0x0F;
+pub const FC_WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+pub const MAX_READ_BITS: u16 = 2000;
+pub const MAX_READ_REGISTERS: u16 = 125;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ModbusTable {
+    Coil,
+    DiscreteInput,
+    InputRegister,
+    HoldingRegister,
+}
+
+impl ModbusTable {
+    pub fn is_bit(&self) -> bool {
+        matches!(self, ModbusTable::Coil | ModbusTable::DiscreteInput)
+    }
+
+    pub fn read_function(&self) -> u8 {
+        match self {
+            ModbusTable::Coil => FC_READ_COILS,
+            ModbusTable::DiscreteInput => FC_READ_DISCRETE_INPUTS,
+            ModbusTable::InputRegister => FC_READ_INPUT_REGISTERS,
+            ModbusTable::HoldingRegister => FC_READ_HOLDING_REGISTERS,
+        }
+    }
+}
+
+// Order of the bytes of a 32-bit value on the wire, ABCD being big endian.
+// For 16-bit values only the byte swap (BADC, DCBA) matters.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ModbusByteOrder {
+    #[default]
+    ABCD,
+    BADC,
+    CDAB,
+    DCBA,
+}
+
+impl ModbusByteOrder {
+    fn byte_swap(&self) -> bool {
+        matches!(self, ModbusByteOrder::BADC | ModbusByteOrder::DCBA)
+    }
+
+    fn word_swap(&self) -> bool {
+        matches!(self, ModbusByteOrder::CDAB | ModbusByteOrder::DCBA)
+    }
+
+    // Converts between wire registers and big endian value bytes, both ways
+    fn reorder(&self, bytes: &mut [u8]) {
+        if self.byte_swap() {
+            for pair in bytes.chunks_mut(2) {
+                pair.swap(0, 1);
+            }
+        }
+        if self.word_swap() && bytes.len() == 4 {
+            bytes.rotate_left(2);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModbusConfig {
+    pub unit_id: u8,
+    #[serde(default)]
+    pub order: ModbusByteOrder,
+    // Unused registers a coalesced read may span to join two tags
+    pub max_gap: u16,
+    pub max_registers: u16,
+    pub max_bits: u16,
+    pub retries: u8,
+}
+
+impl ModbusConfig {
+    // Query parameters of a device URL: `unit`, `order`, `max_gap`, `retries`
+    pub fn from_url(url: &Url) -> Result<Self, String> {
+        let default = Self::default();
+        let order = match param(url, "order", String::from("ABCD"))?.as_str() {
+            "ABCD" => ModbusByteOrder::ABCD,
+            "BADC" => ModbusByteOrder::BADC,
+            "CDAB" => ModbusByteOrder::CDAB,
+            "DCBA" => ModbusByteOrder::DCBA,
+            order => return Err(format!("Invalid byte order {} in {}", order, url)),
+        };
+        Ok(Self {
+            unit_id: param(url, "unit", default.unit_id)?,
+            order,
+            max_gap: param(url, "max_gap", default.max_gap)?,
+            retries: param(url, "retries", default.retries)?,
+            ..default
+        })
+    }
+}
+
+impl Default for ModbusConfig {
+    fn default() -> Self {
+        Self {
+            unit_id: 1,
+            order: ModbusByteOrder::ABCD,
+            max_gap: 8,
+            max_registers: MAX_READ_REGISTERS,
+            max_bits: MAX_READ_BITS,
+            retries: 0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ModbusAddress {
+    pub unit: Option<u8>,
+    pub table: ModbusTable,
+    pub index: u16,
+    pub bit: Option<u8>,
+    pub datatype: ETagtype,
+}
+
+pub fn address_regex() -> Regex {
+    Regex::new(r"^(?:(\d{1,3}):)?(?:(HR|IR|C|DI)(\d{1,5})|([0134])(\d{4,5}))(?:\.(\d{1,2}))?$")
+        .unwrap()
+}
+
+impl ModbusAddress {
+    // `HR100`, `IR5`, `C12`, `DI7` are protocol (0 based) addresses,
+    // `40001` and `400001` are the 1 based reference notation. `.n` selects
+    // a bit of a register and `u:` a unit other than the configured one.
+    pub fn parse(reg: &Regex, address: &str, datatype: ETagtype) -> Result<Self, String> {
+        let invalid = || format!("Invalid Modbus address {}", address);
+        let r = reg.captures(address).ok_or_else(invalid)?;
+        let unit = match r.get(1) {
+            Some(u) => Some(u.as_str().parse::<u8>().map_err(|_| invalid())?),
+            None => None,
+        };
+        let (table, index) = if let Some(prefix) = r.get(2) {
+            let table = match prefix.as_str() {
+                "HR" => ModbusTable::HoldingRegister,
+                "IR" => ModbusTable::InputRegister,
+                "C" => ModbusTable::Coil,
+                _ => ModbusTable::DiscreteInput,
+            };
+            let index: u16 = r.get(3).unwrap().as_str().parse().map_err(|_| invalid())?;
+            (table, index)
+        } else {
+            let table = match r.get(4).unwrap().as_str() {
+                "0" => ModbusTable::Coil,
+                "1" => ModbusTable::DiscreteInput,
+                "3" => ModbusTable::InputRegister,
+                _ => ModbusTable::HoldingRegister,
+            };
+            let number: u32 = r.get(5).unwrap().as_str().parse().unwrap();
+            if number == 0 || number > 0x10000 {
+                return Err(invalid());
+            }
+            (table, (number - 1) as u16)
+        };
+        let bit = r.get(6).map(|b| b.as_str().parse::<u8>().unwrap());
+        let addr = Self {
+            unit,
+            table,
+            index,
+            bit,
+            datatype,
+        };
+        let valid = match (table.is_bit(), datatype, bit) {
+            (true, ETagtype::BOOL, None) => true,
+            (false, ETagtype::BOOL, Some(b)) => b < 16,
+            (false, ETagtype::BOOL, None) => false,
+            (false, _, None) => addr.end() <= 0x10000,
+            _ => false,
+        };
+        if valid {
+            Ok(addr)
+        } else {
+            Err(invalid())
+        }
+    }
+
+    // Number of coils or registers the value occupies
+    pub fn count(&self) -> u16 {
+        match self.datatype {
+            ETagtype::DINT | ETagtype::REAL if !self.table.is_bit() => 2,
+            _ => 1,
+        }
+    }
+
+    fn end(&self) -> u32 {
+        self.index as u32 + self.count() as u32
+    }
+
+    pub fn writable(&self) -> bool {
+        matches!(self.table, ModbusTable::Coil | ModbusTable::HoldingRegister)
+    }
+}
+
+pub fn decode_registers(
+    regs: &[u16],
+    addr: &ModbusAddress,
+    order: ModbusByteOrder,
+) -> Result<ETagValue, String> {
+    if regs.len() < addr.count() as usize {
+        return Err(String::from("Short register data"));
+    }
+    if let Some(bit) = addr.bit {
+        return Ok(ETagValue::Bool(regs[0] >> bit & 1 != 0));
+    }
+    let mut bytes: Vec<u8> = regs[..addr.count() as usize]
+        .iter()
+        .flat_map(|r| r.to_be_bytes().to_vec())
+        .collect();
+    order.reorder(&mut bytes);
+    match addr.datatype {
+        ETagtype::INT => Ok(ETagValue::Int(
+            i16::from_be_bytes([bytes[0], bytes[1]]) as i64
+        )),
+        ETagtype::DINT => {
+            Ok(ETagValue::Int(
+                i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i64,
+            ))
+        }
+        ETagtype::REAL => {
+            Ok(ETagValue::Real(
+                f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ))
+        }
+        ETagtype::BOOL => Err(String::from("BOOL register tags need a bit number")),
+    }
+}
+
+pub fn encode_registers(
+    value: ETagValue,
+    addr: &ModbusAddress,
+    order: ModbusByteOrder,
+) -> Result<Vec<u16>, String> {
+    let mut bytes = match (addr.datatype, value) {
+        (ETagtype::INT, ETagValue::Int(v)) => (v as i16).to_be_bytes().to_vec(),
+        (ETagtype::DINT, ETagValue::Int(v)) => (v as i32).to_be_bytes().to_vec(),
+        (ETagtype::REAL, ETagValue::Real(v)) => (v as f32).to_be_bytes().to_vec(),
+        _ => return Err(String::from("Invalid datatype for write value")),
+    };
+    order.reorder(&mut bytes);
+    Ok(bytes
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+        .collect())
+}
+
+pub fn exception_text(code: u8) -> String {
+    match code {
+        0x01 => String::from("Illegal function"),
+        0x02 => String::from("Illegal data address"),
+        0x03 => String::from("Illegal data value"),
+        0x04 => String::from("Server device failure"),
+        0x05 => String::from("Acknowledge"),
+        0x06 => String::from("Server device busy"),
+        0x08 => String::from("Memory parity error"),
+        0x0A => String::from("Gateway path unavailable"),
+        0x0B => String::from("Gateway target device failed to respond"),
+        c => format!("Modbus exception 0x{:02X}", c),
+    }
+}
+
+// Carries one request PDU (function code and data) to a unit and returns
+// the response PDU. Exception responses are handled by the caller.
+pub trait Transport: Send {
+    fn transact(&mut self, unit: u8, pdu: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+struct ReadRange {
+    unit: u8,
+    table: ModbusTable,
+    start: u16,
+    count: u16,
+    items: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub struct Client<T: Transport> {
+    config: ModbusConfig,
+    reg: Regex,
+    transport: Mutex<T>,
+}
+
+impl<T: Transport> Client<T> {
+    pub fn with_transport(transport: T, config: ModbusConfig) -> Self {
+        Self {
+            config,
+            reg: address_regex(),
+            transport: Mutex::new(transport),
+        }
+    }
+
+    pub fn config(&self) -> &ModbusConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: ModbusConfig) {
+        self.config = config;
+    }
+
+    pub fn transport(&self) -> std::sync::MutexGuard<'_, T> {
+        self.transport.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn conv_address(&self, address: &str, datatype: ETagtype) -> Result<ModbusAddress, String> {
+        ModbusAddress::parse(&self.reg, address, datatype)
+    }
+
+    pub fn request(&self, unit: u8, pdu: &[u8]) -> Result<Vec<u8>, String> {
+        let mut transport = self.transport();
+        let mut attempt = 0;
+        let resp = loop {
+            match transport.transact(unit, pdu) {
+                Ok(resp) => break resp,
+                Err(err) if attempt < self.config.retries => {
+                    warn!("Modbus unit {} request failed, retrying: {}", unit, err);
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        };
+        match resp.first() {
+            Some(fc) if *fc == pdu[0] => Ok(resp),
+            Some(fc) if *fc == pdu[0] | 0x80 => {
+                Err(exception_text(resp.get(1).copied().unwrap_or(0)))
+            }
+            _ => Err(String::from("Unexpected Modbus response")),
+        }
+    }
+
+    pub fn read_bits(
+        &self,
+        unit: u8,
+        table: ModbusTable,
+        start: u16,
+        count: u16,
+    ) -> Result<Vec<bool>, String> {
+        let resp = self.request(unit, &read_pdu(table.read_function(), start, count))?;
+        let bytes = (count as usize).div_ceil(8);
+        if resp.len() < 2 + bytes || resp[1] as usize != bytes {
+            return Err(String::from("Invalid Modbus bit response length"));
+        }
+        Ok((0..count as usize)
+            .map(|i| resp[2 + i / 8] >> (i % 8) & 1 != 0)
+            .collect())
+    }
+
+    pub fn read_registers(
+        &self,
+        unit: u8,
+        table: ModbusTable,
+        start: u16,
+        count: u16,
+    ) -> Result<Vec<u16>, String> {
+        let resp = self.request(unit, &read_pdu(table.read_function(), start, count))?;
+        let bytes = count as usize * 2;
+        if resp.len() < 2 + bytes || resp[1] as usize != bytes {
+            return Err(String::from("Invalid Modbus register response length"));
+        }
+        Ok(resp[2..2 + bytes]
+            .chunks(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect())
+    }
+
+    pub fn write_coil(&self, unit: u8, index: u16, value: bool) -> Result<(), String> {
+        let mut pdu = vec![FC_WRITE_SINGLE_COIL];
+        pdu.extend_from_slice(&index.to_be_bytes());
+        pdu.extend_from_slice(if value { &[0xFF, 0x00] } else { &[0x00, 0x00] });
+        self.request(unit, &pdu).map(|_| ())
+    }
+
+    pub fn write_registers(&self, unit: u8, index: u16, regs: &[u16]) -> Result<(), String> {
+        let mut pdu;
+        if regs.len() == 1 {
+            pdu = vec![FC_WRITE_SINGLE_REGISTER];
+            pdu.extend_from_slice(&index.to_be_bytes());
+            pdu.extend_from_slice(&regs[0].to_be_bytes());
+        } else {
+            pdu = vec![FC_WRITE_MULTIPLE_REGISTERS];
+            pdu.extend_from_slice(&index.to_be_bytes());
+            pdu.extend_from_slice(&(regs.len() as u16).to_be_bytes());
+            pdu.push((regs.len() * 2) as u8);
+            for r in regs {
+                pdu.extend_from_slice(&r.to_be_bytes());
+            }
+        }
+        self.request(unit, &pdu).map(|_| ())
+    }
+
+    fn unit(&self, addr: &ModbusAddress) -> u8 {
+        addr.unit.unwrap_or(self.config.unit_id)
+    }
+
+    // Joins tags of the same unit and table into as few range reads as the
+    // gap and size limits allow
+    fn plan_reads(&self, addrs: &[ModbusAddress]) -> Vec<ReadRange> {
+        let mut order: Vec<usize> = (0..addrs.len()).collect();
+        order.sort_by_key(|i| (self.unit(&addrs[*i]), addrs[*i].table, addrs[*i].index));
+        let mut ranges: Vec<ReadRange> = Vec::new();
+        for i in order {
+            let addr = &addrs[i];
+            let unit = self.unit(addr);
+            let max = if addr.table.is_bit() {
+                self.config.max_bits
+            } else {
+                self.config.max_registers
+            } as u32;
+            if let Some(range) = ranges.last_mut() {
+                let end = range.start as u32 + range.count as u32;
+                if range.unit == unit
+                    && range.table == addr.table
+                    && addr.index as u32 <= end + self.config.max_gap as u32
+                    && addr.end().max(end) - range.start as u32 <= max
+                {
+                    range.count = (addr.end().max(end) - range.start as u32) as u16;
+                    range.items.push(i);
+                    continue;
+                }
+            }
+            ranges.push(ReadRange {
+                unit,
+                table: addr.table,
+                start: addr.index,
+                count: addr.count(),
+                items: vec![i],
+            });
+        }
+        ranges
+    }
+
+    fn read_addresses(&self, addrs: &[ModbusAddress]) -> Vec<Result<ETagValue, String>> {
+        let mut values: Vec<Result<ETagValue, String>> =
+            vec![Err(String::from("Not read")); addrs.len()];
+        for range in self.plan_reads(addrs) {
+            if range.table.is_bit() {
+                match self.read_bits(range.unit, range.table, range.start, range.count) {
+                    Ok(bits) => {
+                        for i in range.items {
+                            let offset = (addrs[i].index - range.start) as usize;
+                            values[i] = Ok(ETagValue::Bool(bits[offset]));
+                        }
+                    }
+                    Err(err) => {
+                        for i in range.items {
+                            values[i] = Err(err.clone());
+                        }
+                    }
+                }
+            } else {
+                match self.read_registers(range.unit, range.table, range.start, range.count) {
+                    Ok(regs) => {
+                        for i in range.items {
+                            let offset = (addrs[i].index - range.start) as usize;
+                            values[i] =
+                                decode_registers(&regs[offset..], &addrs[i], self.config.order);
+                        }
+                    }
+                    Err(err) => {
+                        for i in range.items {
+                            values[i] = Err(err.clone());
+                        }
+                    }
+                }
+            }
+        }
+        values
+    }
+
+    fn write_address(
+        &self,
+        tag: &ETag,
+        addr: &ModbusAddress,
+        write: ETagValue,
+    ) -> Result<bool, String> {
+        if !addr.writable() {
+            return Err(format!("{:?} is read only", addr.table));
+        }
+        let unit = self.unit(addr);
+        let write = tag.datatype.coerce(write, tag.range)?;
+        match (addr.table, addr.bit, &write) {
+            (ModbusTable::Coil, _, &ETagValue::Bool(v)) => self.write_coil(unit, addr.index, v)?,
+            (_, Some(bit), &ETagValue::Bool(v)) => {
+                // Read-modify-write of the register holding the bit
+                let reg = self.read_registers(unit, addr.table, addr.index, 1)?[0];
+                let reg = if v { reg | 1 << bit } else { reg & !(1 << bit) };
+                self.write_registers(unit, addr.index, &[reg])?
+            }
+            _ => {
+                let regs = encode_registers(write, addr, self.config.order)?;
+                self.write_registers(unit, addr.index, &regs)?
+            }
+        }
+        Ok(true)
+    }
+}
+
+fn read_pdu(function: u8, start: u16, count: u16) -> Vec<u8> {
+    let mut pdu = vec![function];
+    pdu.extend_from_slice(&start.to_be_bytes());
+    pdu.extend_from_slice(&count.to_be_bytes());
+    pdu
+}
+
+impl<T: Transport> ETagRW for Client<T> {
+    fn read_tag(&self, tag: &ETag) -> Result<ETagValue, String> {
+        let addr = self.conv_address(tag.address.as_str(), tag.datatype)?;
+        self.read_addresses(&[addr]).pop().unwrap()
+    }
+    fn read_list(&self, tags: &Vec<ETag>) -> Result<Vec<Result<ETagValue, String>>, String> {
+        let addrs = tags
+            .iter()
+            .map(|tag| self.conv_address(tag.address.as_str(), tag.datatype))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        Ok(self.read_addresses(&addrs))
+    }
+    fn write_tag(&self, tag: &ETag, write: ETagValue) -> Result<bool, String> {
+        let addr = self.conv_address(tag.address.as_str(), tag.datatype)?;
+        self.write_address(tag, &addr, write)
+    }
+    fn write_list(
+        &self,
+        tags: &Vec<(ETag, ETagValue)>,
+    ) -> Result<Vec<Result<bool, String>>, String> {
+        let addrs = tags
+            .iter()
+            .map(|t| self.conv_address(t.0.address.as_str(), t.0.datatype))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        Ok(tags
+            .iter()
+            .zip(addrs)
+            .map(|((tag, write), addr)| self.write_address(tag, &addr, write.clone()))
+            .collect())
+    }
+}
+
+// modbus+tcp://, and modbus+rtu:// when built with modbus-rtu
+pub fn register(registry: &mut Registry) {
+    tcp::register(registry);
+    #[cfg(feature = "modbus-rtu")]
+    rtu::register(registry);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::server::{RegisterMapping, Server};
+    use super::tcp::TcpClient;
+    use super::*;
+    use crate::plc_driver::tests::{tag, Memory};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    fn parse(address: &str, datatype: ETagtype) -> Result<ModbusAddress, String> {
+        ModbusAddress::parse(&address_regex(), address, datatype)
+    }
+
+    #[test]
+    fn parses_addresses() {
+        let a = parse("40001", ETagtype::INT).unwrap();
+        assert_eq!((a.table, a.index), (ModbusTable::HoldingRegister, 0));
+        let a = parse("300010", ETagtype::DINT).unwrap();
+        assert_eq!((a.table, a.index), (ModbusTable::InputRegister, 9));
+        let a = parse("2:HR100.15", ETagtype::BOOL).unwrap();
+        assert_eq!((a.unit, a.index, a.bit), (Some(2), 100, Some(15)));
+        assert_eq!(
+            parse("C12", ETagtype::BOOL).unwrap().table,
+            ModbusTable::Coil
+        );
+        assert!(parse("HR5", ETagtype::BOOL).is_err());
+        assert!(parse("C5", ETagtype::INT).is_err());
+        assert!(parse("HR1.16", ETagtype::BOOL).is_err());
+        assert!(parse("HR65535", ETagtype::DINT).is_err());
+        assert!(parse("HR65534", ETagtype::REAL).is_ok());
+    }
+
+    #[test]
+    fn byte_orders_round_trip() {
+        let addr = parse("HR0", ETagtype::DINT).unwrap();
+        let value = ETagValue::Int(0x1122_3344);
+        let cases = [
+            (ModbusByteOrder::ABCD, [0x1122, 0x3344]),
+            (ModbusByteOrder::BADC, [0x2211, 0x4433]),
+            (ModbusByteOrder::CDAB, [0x3344, 0x1122]),
+            (ModbusByteOrder::DCBA, [0x4433, 0x2211]),
+        ];
+        for (order, regs) in cases.iter() {
+            assert_eq!(
+                encode_registers(value.clone(), &addr, *order).unwrap(),
+                regs
+            );
+            assert_eq!(decode_registers(regs, &addr, *order).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn coalesces_reads() {
+        let client = Client::with_transport(Unused, ModbusConfig::default());
+        let addrs: Vec<ModbusAddress> = ["HR0", "HR1", "HR12", "HR30", "IR1", "2:HR2"]
+            .iter()
+            .map(|a| parse(a, ETagtype::INT).unwrap())
+            .collect();
+        let ranges: Vec<(u8, ModbusTable, u16, u16)> = client
+            .plan_reads(&addrs)
+            .iter()
+            .map(|r| (r.unit, r.table, r.start, r.count))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                (1, ModbusTable::InputRegister, 1, 1),
+                (1, ModbusTable::HoldingRegister, 0, 2),
+                (1, ModbusTable::HoldingRegister, 12, 1),
+                (1, ModbusTable::HoldingRegister, 30, 1),
+                (2, ModbusTable::HoldingRegister, 2, 1),
+            ]
+        );
+    }
+
+    struct Unused;
+
+    impl Transport for Unused {
+        fn transact(&mut self, _unit: u8, _pdu: &[u8]) -> Result<Vec<u8>, String> {
+            Err(String::from("No transport"))
+        }
+    }
+
+    fn mapping(address: &str, tag: ETag) -> RegisterMapping {
+        RegisterMapping {
+            address: address.to_owned(),
+            tag,
+        }
+    }
+
+    #[test]
+    fn tcp_client_against_server() {
+        let device = Arc::new(Memory::default());
+        device.set("speed", ETagValue::Int(-12));
+        device.set("count", ETagValue::Int(70_000));
+        device.set("level", ETagValue::Real(2.5));
+        device.set("run", ETagValue::Bool(true));
+        device.set("alarm", ETagValue::Bool(false));
+        let config = ModbusConfig {
+            order: ModbusByteOrder::CDAB,
+            ..ModbusConfig::default()
+        };
+        let server = Server::new(
+            vec![
+                mapping("HR0", tag("speed", ETagtype::INT)),
+                mapping("HR2", tag("count", ETagtype::DINT)),
+                mapping("HR4", tag("level", ETagtype::REAL)),
+                mapping("C0", tag("run", ETagtype::BOOL)),
+                mapping("HR10.3", tag("alarm", ETagtype::BOOL)),
+            ],
+            device.clone(),
+            config.clone(),
+        )
+        .unwrap();
+        server.refresh().unwrap();
+        let host = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        Arc::new(server).serve(&host).unwrap();
+
+        let client = TcpClient::connect(&host, config);
+        assert!(client.connected());
+        let tags = vec![
+            tag("HR0", ETagtype::INT),
+            tag("HR2", ETagtype::DINT),
+            tag("HR4", ETagtype::REAL),
+            tag("C0", ETagtype::BOOL),
+            tag("HR10.3", ETagtype::BOOL),
+        ];
+        let values: Vec<ETagValue> = client
+            .read_list(&tags)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                ETagValue::Int(-12),
+                ETagValue::Int(70_000),
+                ETagValue::Real(2.5),
+                ETagValue::Bool(true),
+                ETagValue::Bool(false),
+            ]
+        );
+
+        let written = client
+            .write_list(&vec![
+                (tags[1].clone(), ETagValue::Int(-5)),
+                (tags[3].clone(), ETagValue::Bool(false)),
+                (tags[4].clone(), ETagValue::Bool(true)),
+            ])
+            .unwrap();
+        assert_eq!(written, vec![Ok(true), Ok(true), Ok(true)]);
+        assert_eq!(device.get("count"), Some(ETagValue::Int(-5)));
+        assert_eq!(device.get("run"), Some(ETagValue::Bool(false)));
+        assert_eq!(device.get("alarm"), Some(ETagValue::Bool(true)));
+        assert!(client
+            .write_tag(&tag("IR0", ETagtype::INT), ETagValue::Int(1))
+            .is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use super::{Client, ModbusConfig, Transport};
+use crate::plc_driver::registry::{param, Registry};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serialport::SerialPort;
+use std::io::{Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub type RtuClient = Client<RtuTransport<Box<dyn SerialPort>>>;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum SerialParity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SerialConfig {
+    pub path: String,
+    pub baud: u32,
+    pub parity: SerialParity,
+    pub stop_bits: u8,
+    pub timeout_ms: u64,
+    // Silence between frames, 3.5 characters when not set
+    #[serde(default)]
+    pub frame_gap_us: Option<u64>,
+}
+
+impl SerialConfig {
+    pub fn new(path: &str, baud: u32) -> Self {
+        Self {
+            path: path.to_owned(),
+            baud,
+            parity: SerialParity::Even,
+            stop_bits: 1,
+            timeout_ms: 500,
+            frame_gap_us: None,
+        }
+    }
+
+    // The spec fixes the gap to 1.75 ms above 19200 baud
+    pub fn frame_gap(&self) -> Duration {
+        match self.frame_gap_us {
+            Some(us) => Duration::from_micros(us),
+            None if self.baud > 19200 => Duration::from_micros(1750),
+            None => Duration::from_micros(3_500_000 * 11 / self.baud.max(1) as u64),
+        }
+    }
+}
+
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for b in data {
+        crc ^= *b as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+// RTU framing over any byte stream, a serial port or one side of a pty
+pub struct RtuTransport<P: Read + Write + Send> {
+    port: P,
+    frame_gap: Duration,
+    last: Instant,
+}
+
+impl<P: Read + Write + Send> std::fmt::Debug for RtuTransport<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RtuTransport")
+            .field("frame_gap", &self.frame_gap)
+            .finish()
+    }
+}
+
+impl<P: Read + Write + Send> RtuTransport<P> {
+    pub fn new(port: P, frame_gap: Duration) -> Self {
+        Self {
+            port,
+            frame_gap,
+            last: Instant::now(),
+        }
+    }
+
+    pub fn port(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    fn read_bytes(&mut self, len: usize, frame: &mut Vec<u8>) -> Result<(), String> {
+        let start = frame.len();
+        frame.resize(start + len, 0);
+        self.port
+            .read_exact(&mut frame[start..])
+            .map_err(|e| e.to_string())
+    }
+
+    fn recv_frame(&mut self, unit: u8) -> Result<Vec<u8>, String> {
+        let mut frame = Vec::new();
+        self.read_bytes(2, &mut frame)?;
+        let fc = frame[1];
+        match fc {
+            f if f & 0x80 != 0 => self.read_bytes(1, &mut frame)?,
+            0x01..=0x04 => {
+                self.read_bytes(1, &mut frame)?;
+                let count = frame[2] as usize;
+                self.read_bytes(count, &mut frame)?;
+            }
+            0x05 | 0x06 | 0x0F | 0x10 => self.read_bytes(4, &mut frame)?,
+            f => return Err(format!("Unsupported RTU function 0x{:02X}", f)),
+        }
+        self.read_bytes(2, &mut frame)?;
+        let (body, crc) = frame.split_at(frame.len() - 2);
+        if crc16(body).to_le_bytes() != [crc[0], crc[1]] {
+            return Err(String::from("RTU CRC error"));
+        }
+        if body[0] != unit {
+            return Err(format!(
+                "RTU response from unit {} instead of {}",
+                body[0], unit
+            ));
+        }
+        Ok(body[1..].to_vec())
+    }
+}
+
+impl<P: Read + Write + Send> Transport for RtuTransport<P> {
+    fn transact(&mut self, unit: u8, pdu: &[u8]) -> Result<Vec<u8>, String> {
+        let mut frame = Vec::with_capacity(pdu.len() + 3);
+        frame.push(unit);
+        frame.extend_from_slice(pdu);
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_le_bytes());
+
+        let idle = self.last.elapsed();
+        if idle < self.frame_gap {
+            thread::sleep(self.frame_gap - idle);
+        }
+        let result = self
+            .port
+            .write_all(&frame)
+            .and_then(|_| self.port.flush())
+            .map_err(|e| e.to_string());
+        let result = match result {
+            // Broadcast requests are never answered
+            Ok(_) if unit == 0 => Ok(pdu.to_vec()),
+            Ok(_) => self.recv_frame(unit),
+            Err(err) => Err(err),
+        };
+        self.last = Instant::now();
+        if let Err(err) = &result {
+            warn!("Modbus RTU unit {}: {}", unit, err);
+            // Let a late or partial answer pass before the next request
+            thread::sleep(self.frame_gap);
+        }
+        result
+    }
+}
+
+impl RtuClient {
+    pub fn open(serial: &SerialConfig, config: ModbusConfig) -> Result<Self, String> {
+        let parity = match serial.parity {
+            SerialParity::None => serialport::Parity::None,
+            SerialParity::Even => serialport::Parity::Even,
+            SerialParity::Odd => serialport::Parity::Odd,
+        };
+        let stop_bits = match serial.stop_bits {
+            2 => serialport::StopBits::Two,
+            _ => serialport::StopBits::One,
+        };
+        let port = serialport::new(serial.path.as_str(), serial.baud)
+            .data_bits(serialport::DataBits::Eight)
+            .parity(parity)
+            .stop_bits(stop_bits)
+            .timeout(Duration::from_millis(serial.timeout_ms))
+            .open()
+            .map_err(|e| e.to_string())?;
+        info!("Modbus RTU on {} at {} baud", serial.path, serial.baud);
+        Ok(Client::with_transport(
+            RtuTransport::new(port, serial.frame_gap()),
+            config,
+        ))
+    }
+}
+
+// modbus+rtu:///dev/ttyUSB0?baud=19200&parity=even&unit=1 or modbus+rtu://COM3
+pub fn register(registry: &mut Registry) {
+    registry.register_device(
+        "modbus+rtu",
+        |url| {
+            let path = format!("{}{}", url.host_str().unwrap_or(""), url.path());
+            let mut serial = SerialConfig::new(path.as_str(), param(url, "baud", 9600)?);
+            serial.parity = match param(url, "parity", String::from("even"))?.as_str() {
+                "none" => SerialParity::None,
+                "even" => SerialParity::Even,
+                "odd" => SerialParity::Odd,
+                parity => return Err(format!("Invalid parity {} in {}", parity, url)),
+            };
+            serial.stop_bits = param(url, "stop_bits", serial.stop_bits)?;
+            serial.timeout_ms = param(url, "timeout_ms", serial.timeout_ms)?;
+            RtuClient::open(&serial, ModbusConfig::from_url(url)?)
+        },
+        // The port stays open until the driver is closed
+        |_| true,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::server::{RegisterMapping, Server};
+    use super::*;
+    use crate::plc_driver::tests::{tag, Memory};
+    use crate::plc_driver::{ETagRW, ETagValue, ETagtype};
+    use serialport::TTYPort;
+    use std::sync::Arc;
+
+    fn slave(unit: u8, address: &str) -> (Server, Arc<Memory>) {
+        let device = Arc::new(Memory::default());
+        device.set(address, ETagValue::Int(unit as i64 * 100));
+        let config = ModbusConfig {
+            unit_id: unit,
+            ..ModbusConfig::default()
+        };
+        let mapping = RegisterMapping {
+            address: String::from("HR0"),
+            tag: tag(address, ETagtype::INT),
+        };
+        let server = Server::new(vec![mapping], device.clone(), config).unwrap();
+        server.refresh().unwrap();
+        (server, device)
+    }
+
+    fn read_request(port: &mut TTYPort) -> std::io::Result<Vec<u8>> {
+        let mut frame = vec![0u8; 8];
+        port.read_exact(&mut frame)?;
+        if frame[1] == 0x0F || frame[1] == 0x10 {
+            // Byte count, data and CRC follow the fixed part
+            let mut rest = vec![0u8; frame[6] as usize + 1];
+            port.read_exact(&mut rest)?;
+            frame.extend_from_slice(&rest);
+        }
+        Ok(frame)
+    }
+
+    // Simulated bus on the master side of a pty: units 1 and 2 answer,
+    // unit 3 has a corrupted CRC and any other unit is silent
+    fn bus(mut port: TTYPort, slaves: Vec<Server>) {
+        port.set_timeout(Duration::from_secs(60)).unwrap();
+        thread::spawn(move || {
+            while let Ok(frame) = read_request(&mut port) {
+                let (body, crc) = frame.split_at(frame.len() - 2);
+                assert_eq!(crc16(body).to_le_bytes(), [crc[0], crc[1]]);
+                let unit = body[0];
+                let pdu = match unit as usize {
+                    3 => vec![0x03, 0x02, 0x00, 0x00],
+                    u if u >= 1 && u <= slaves.len() => slaves[u - 1].handle_pdu(unit, &body[1..]),
+                    _ => continue,
+                };
+                let mut resp = vec![unit];
+                resp.extend_from_slice(&pdu);
+                let mut crc = crc16(&resp);
+                if unit == 3 {
+                    crc ^= 0xFFFF;
+                }
+                resp.extend_from_slice(&crc.to_le_bytes());
+                port.write_all(&resp).unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn several_units_on_a_pty() {
+        let (master, slave_port) = TTYPort::pair().unwrap();
+        let path = slave_port.name().unwrap();
+        let (one, device) = slave(1, "one");
+        let (two, _) = slave(2, "two");
+        bus(master, vec![one, two]);
+
+        let mut serial = SerialConfig::new(&path, 115_200);
+        serial.timeout_ms = 200;
+        let client = RtuClient::open(&serial, ModbusConfig::default()).unwrap();
+        drop(slave_port);
+        let values = client
+            .read_list(&vec![
+                tag("HR0", ETagtype::INT),
+                tag("2:HR0", ETagtype::INT),
+            ])
+            .unwrap();
+        assert_eq!(
+            values,
+            vec![Ok(ETagValue::Int(100)), Ok(ETagValue::Int(200))]
+        );
+        assert_eq!(
+            client.write_tag(&tag("HR0", ETagtype::INT), ETagValue::Int(-7)),
+            Ok(true)
+        );
+        assert_eq!(device.get("one"), Some(ETagValue::Int(-7)));
+
+        let crc = client.read_tag(&tag("3:HR0", ETagtype::INT));
+        assert_eq!(crc, Err(String::from("RTU CRC error")));
+        assert!(client.read_tag(&tag("9:HR0", ETagtype::INT)).is_err());
+        // The bus recovers after an error
+        assert_eq!(
+            client.read_tag(&tag("2:HR0", ETagtype::INT)),
+            Ok(ETagValue::Int(200))
+        );
+    }
+}
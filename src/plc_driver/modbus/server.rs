@@ -0,0 +1,449 @@
+use super::{
+    address_regex, decode_registers, encode_registers, ModbusAddress, ModbusConfig, ModbusTable,
+    FC_READ_COILS, FC_READ_DISCRETE_INPUTS, FC_READ_HOLDING_REGISTERS, FC_READ_INPUT_REGISTERS,
+    FC_WRITE_MULTIPLE_COILS, FC_WRITE_MULTIPLE_REGISTERS, FC_WRITE_SINGLE_COIL,
+    FC_WRITE_SINGLE_REGISTER, MAX_READ_BITS, MAX_READ_REGISTERS,
+};
+use crate::plc_driver::{ERangePolicy, ETag, ETagRW, ETagValue};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const EX_ILLEGAL_FUNCTION: u8 = 0x01;
+const EX_ILLEGAL_ADDRESS: u8 = 0x02;
+const EX_ILLEGAL_VALUE: u8 = 0x03;
+const EX_DEVICE_FAILURE: u8 = 0x04;
+const EX_GATEWAY_PATH: u8 = 0x0A;
+
+// A device tag exposed at `address` of the register map
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegisterMapping {
+    pub address: String,
+    pub tag: ETag,
+}
+
+#[derive(Default)]
+struct Image {
+    registers: BTreeMap<(ModbusTable, u16), u16>,
+    bits: BTreeMap<(ModbusTable, u16), bool>,
+}
+
+// Serves the register map from an image refreshed from the device;
+// writes from Modbus masters go through to the mapped device tags.
+pub struct Server {
+    config: ModbusConfig,
+    device: Arc<dyn ETagRW + Send + Sync>,
+    mappings: Vec<(RegisterMapping, ModbusAddress)>,
+    image: Mutex<Image>,
+}
+
+impl Server {
+    pub fn new(
+        mappings: Vec<RegisterMapping>,
+        device: Arc<dyn ETagRW + Send + Sync>,
+        config: ModbusConfig,
+    ) -> Result<Self, String> {
+        let reg = address_regex();
+        let mappings = mappings
+            .into_iter()
+            .map(
+                |m| match ModbusAddress::parse(&reg, m.address.as_str(), m.tag.datatype) {
+                    Ok(addr) if addr.unit.is_some() => {
+                        Err(format!("Unit not allowed in map: {}", m.address))
+                    }
+                    Ok(addr) if addr.end() > 0x10000 => {
+                        Err(format!("{} runs past register 65535", m.address))
+                    }
+                    Ok(addr) => Ok((m, addr)),
+                    Err(err) => Err(err),
+                },
+            )
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let mut image = Image::default();
+        for (_, addr) in &mappings {
+            if addr.table.is_bit() {
+                image.bits.insert((addr.table, addr.index), false);
+            } else {
+                for i in addr.index as u32..addr.end() {
+                    image.registers.insert((addr.table, i as u16), 0);
+                }
+            }
+        }
+        Ok(Self {
+            config,
+            device,
+            mappings,
+            image: Mutex::new(image),
+        })
+    }
+
+    pub fn mappings(&self) -> Vec<&RegisterMapping> {
+        self.mappings.iter().map(|m| &m.0).collect()
+    }
+
+    // Reads every mapped tag from the device into the register image
+    pub fn refresh(&self) -> Result<(), String> {
+        let tags: Vec<ETag> = self.mappings.iter().map(|m| m.0.tag.clone()).collect();
+        let values = self.device.read_list(&tags)?;
+        let mut image = self.image.lock().unwrap();
+        for ((mapping, addr), value) in self.mappings.iter().zip(values) {
+            match value {
+                Ok(value) => {
+                    if let Err(err) = self.store(&mut image, addr, value) {
+                        warn!("{}: {}", mapping.tag.name, err);
+                    }
+                }
+                Err(err) => warn!("{}: {}", mapping.tag.name, err),
+            }
+        }
+        Ok(())
+    }
+
+    fn store(
+        &self,
+        image: &mut Image,
+        addr: &ModbusAddress,
+        value: ETagValue,
+    ) -> Result<(), String> {
+        match (addr.table.is_bit(), addr.bit, &value) {
+            (true, _, &ETagValue::Bool(v)) => {
+                image.bits.insert((addr.table, addr.index), v);
+            }
+            (false, Some(bit), &ETagValue::Bool(v)) => {
+                let reg = image.registers.entry((addr.table, addr.index)).or_insert(0);
+                *reg = if v {
+                    *reg | 1 << bit
+                } else {
+                    *reg & !(1 << bit)
+                };
+            }
+            _ => {
+                let value = addr.datatype.coerce(value, ERangePolicy::Clamp)?;
+                let regs = encode_registers(value, addr, self.config.order)?;
+                for (i, r) in regs.into_iter().enumerate() {
+                    image
+                        .registers
+                        .insert((addr.table, addr.index + i as u16), r);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn load(&self, image: &Image, addr: &ModbusAddress) -> Result<ETagValue, String> {
+        if addr.table.is_bit() {
+            return Ok(ETagValue::Bool(
+                *image.bits.get(&(addr.table, addr.index)).unwrap_or(&false),
+            ));
+        }
+        let regs: Vec<u16> = (addr.index as u32..addr.end())
+            .map(|i| *image.registers.get(&(addr.table, i as u16)).unwrap_or(&0))
+            .collect();
+        decode_registers(&regs, addr, self.config.order)
+    }
+
+    // Answers one request PDU, used by the TCP listener and usable by any
+    // other framing (an RTU slave on a pty for instance)
+    pub fn handle_pdu(&self, unit: u8, pdu: &[u8]) -> Vec<u8> {
+        let fc = pdu.first().copied().unwrap_or(0);
+        let result = if unit != self.config.unit_id && unit != 0 && unit != 0xFF {
+            Err(EX_GATEWAY_PATH)
+        } else {
+            self.dispatch(fc, &pdu[pdu.len().min(1)..])
+        };
+        match result {
+            Ok(data) => {
+                let mut resp = vec![fc];
+                resp.extend_from_slice(&data);
+                resp
+            }
+            Err(code) => vec![fc | 0x80, code],
+        }
+    }
+
+    fn dispatch(&self, fc: u8, data: &[u8]) -> Result<Vec<u8>, u8> {
+        let word = |i: usize| -> Result<u16, u8> {
+            data.get(i..i + 2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .ok_or(EX_ILLEGAL_VALUE)
+        };
+        match fc {
+            FC_READ_COILS | FC_READ_DISCRETE_INPUTS => {
+                let table = if fc == FC_READ_COILS {
+                    ModbusTable::Coil
+                } else {
+                    ModbusTable::DiscreteInput
+                };
+                let (start, count) = (word(0)?, word(2)?);
+                if count == 0 || count > MAX_READ_BITS {
+                    return Err(EX_ILLEGAL_VALUE);
+                }
+                check_range(start, count)?;
+                let image = self.image.lock().unwrap();
+                let mut resp = vec![0u8; 1 + (count as usize).div_ceil(8)];
+                resp[0] = (resp.len() - 1) as u8;
+                for i in 0..count {
+                    if *image.bits.get(&(table, start + i)).unwrap_or(&false) {
+                        resp[1 + i as usize / 8] |= 1 << (i % 8);
+                    }
+                }
+                Ok(resp)
+            }
+            FC_READ_HOLDING_REGISTERS | FC_READ_INPUT_REGISTERS => {
+                let table = if fc == FC_READ_HOLDING_REGISTERS {
+                    ModbusTable::HoldingRegister
+                } else {
+                    ModbusTable::InputRegister
+                };
+                let (start, count) = (word(0)?, word(2)?);
+                if count == 0 || count > MAX_READ_REGISTERS {
+                    return Err(EX_ILLEGAL_VALUE);
+                }
+                check_range(start, count)?;
+                let image = self.image.lock().unwrap();
+                let mut resp = vec![(count * 2) as u8];
+                for i in 0..count {
+                    let r = *image.registers.get(&(table, start + i)).unwrap_or(&0);
+                    resp.extend_from_slice(&r.to_be_bytes());
+                }
+                Ok(resp)
+            }
+            FC_WRITE_SINGLE_COIL => {
+                let value = match word(2)? {
+                    0xFF00 => true,
+                    0x0000 => false,
+                    _ => return Err(EX_ILLEGAL_VALUE),
+                };
+                self.write(ModbusTable::Coil, word(0)?, &[], &[value])?;
+                Ok(data[..4].to_vec())
+            }
+            FC_WRITE_MULTIPLE_COILS => {
+                let (start, count) = (word(0)?, word(2)?);
+                check_range(start, count)?;
+                let bytes = data.get(5..).ok_or(EX_ILLEGAL_VALUE)?;
+                if count == 0 || bytes.len() < (count as usize).div_ceil(8) {
+                    return Err(EX_ILLEGAL_VALUE);
+                }
+                let values: Vec<bool> = (0..count as usize)
+                    .map(|i| bytes[i / 8] >> (i % 8) & 1 != 0)
+                    .collect();
+                self.write(ModbusTable::Coil, start, &[], &values)?;
+                Ok(data[..4].to_vec())
+            }
+            FC_WRITE_SINGLE_REGISTER => {
+                self.write(ModbusTable::HoldingRegister, word(0)?, &[word(2)?], &[])?;
+                Ok(data[..4].to_vec())
+            }
+            FC_WRITE_MULTIPLE_REGISTERS => {
+                let (start, count) = (word(0)?, word(2)?);
+                check_range(start, count)?;
+                if count == 0 || data.len() < 5 + count as usize * 2 {
+                    return Err(EX_ILLEGAL_VALUE);
+                }
+                let regs = (0..count as usize)
+                    .map(|i| word(5 + i * 2))
+                    .collect::<Result<Vec<u16>, u8>>()?;
+                self.write(ModbusTable::HoldingRegister, start, &regs, &[])?;
+                Ok(data[..4].to_vec())
+            }
+            _ => Err(EX_ILLEGAL_FUNCTION),
+        }
+    }
+
+    // Applies a master write to the image and forwards the affected tags.
+    // Bits sharing a register with the written ones are only forwarded when
+    // their value changed.
+    fn write(&self, table: ModbusTable, start: u16, regs: &[u16], bits: &[bool]) -> Result<(), u8> {
+        let count = regs.len().max(bits.len()) as u32;
+        let end = start as u32 + count;
+        let mut image = self.image.lock().unwrap();
+        let cells = (start as u32..end).all(|i| {
+            if table.is_bit() {
+                image.bits.contains_key(&(table, i as u16))
+            } else {
+                image.registers.contains_key(&(table, i as u16))
+            }
+        });
+        if !cells {
+            return Err(EX_ILLEGAL_ADDRESS);
+        }
+
+        let affected: Vec<&(RegisterMapping, ModbusAddress)> = self
+            .mappings
+            .iter()
+            .filter(|(_, addr)| {
+                addr.table == table && (addr.index as u32) < end && (start as u32) < addr.end()
+            })
+            .collect();
+        let before: Vec<Option<ETagValue>> = affected
+            .iter()
+            .map(|(_, addr)| self.load(&image, addr).ok())
+            .collect();
+        for (i, r) in regs.iter().enumerate() {
+            image.registers.insert((table, start + i as u16), *r);
+        }
+        for (i, b) in bits.iter().enumerate() {
+            image.bits.insert((table, start + i as u16), *b);
+        }
+        let mut writes: Vec<(ETag, ETagValue)> = Vec::new();
+        for ((mapping, addr), before) in affected.into_iter().zip(before) {
+            match self.load(&image, addr) {
+                Ok(value) if addr.bit.is_some() && before.as_ref() == Some(&value) => {}
+                Ok(value) => writes.push((mapping.tag.clone(), value)),
+                Err(err) => {
+                    warn!("{}: {}", mapping.tag.name, err);
+                    return Err(EX_ILLEGAL_VALUE);
+                }
+            }
+        }
+        drop(image);
+
+        if writes.is_empty() {
+            return Ok(());
+        }
+        match self.device.write_list(&writes) {
+            Ok(results) => {
+                let mut failed = false;
+                for ((tag, _), r) in writes.iter().zip(results) {
+                    if let Err(err) = r {
+                        warn!("Modbus write to {} failed: {}", tag.name, err);
+                        failed = true;
+                    }
+                }
+                if failed {
+                    Err(EX_DEVICE_FAILURE)
+                } else {
+                    Ok(())
+                }
+            }
+            Err(err) => {
+                warn!("Modbus write forwarding failed: {}", err);
+                Err(EX_DEVICE_FAILURE)
+            }
+        }
+    }
+
+    pub fn serve(self: &Arc<Self>, listen: &str) -> Result<thread::JoinHandle<()>, String> {
+        let listener = TcpListener::bind(listen).map_err(|e| e.to_string())?;
+        info!("Modbus TCP server listening on {}", listen);
+        let server = self.clone();
+        thread::Builder::new()
+            .name(String::from("modbus-server"))
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            let server = server.clone();
+                            thread::spawn(move || server.session(stream));
+                        }
+                        Err(err) => warn!("Modbus accept failed: {}", err),
+                    }
+                }
+            })
+            .map_err(|e| e.to_string())
+    }
+
+    fn session(&self, mut stream: TcpStream) {
+        let peer = stream.peer_addr().ok();
+        info!("Modbus master connected: {:?}", peer);
+        let _ = stream.set_nodelay(true);
+        loop {
+            let mut header = [0u8; 7];
+            if stream.read_exact(&mut header).is_err() {
+                break;
+            }
+            let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+            if header[2..4] != [0x00, 0x00] || len < 2 {
+                break;
+            }
+            let mut pdu = vec![0u8; len - 1];
+            if stream.read_exact(&mut pdu).is_err() {
+                break;
+            }
+            let resp = self.handle_pdu(header[6], &pdu);
+            let mut frame = header[..4].to_vec();
+            frame.extend_from_slice(&((resp.len() + 1) as u16).to_be_bytes());
+            frame.push(header[6]);
+            frame.extend_from_slice(&resp);
+            if stream.write_all(&frame).is_err() {
+                break;
+            }
+        }
+        info!("Modbus master disconnected: {:?}", peer);
+    }
+}
+
+fn check_range(start: u16, count: u16) -> Result<(), u8> {
+    if start as u32 + count as u32 > 0x10000 {
+        Err(EX_ILLEGAL_ADDRESS)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plc_driver::tests::{tag, Memory};
+    use crate::plc_driver::ETagtype;
+
+    fn server(mappings: &[(&str, &str, ETagtype)]) -> Result<(Server, Arc<Memory>), String> {
+        let device = Arc::new(Memory::default());
+        let mappings = mappings
+            .iter()
+            .map(|(address, name, datatype)| RegisterMapping {
+                address: address.to_string(),
+                tag: tag(name, *datatype),
+            })
+            .collect();
+        let server = Server::new(mappings, device.clone(), ModbusConfig::default())?;
+        Ok((server, device))
+    }
+
+    #[test]
+    fn maps_up_to_the_last_register() {
+        assert!(server(&[("HR65535", "a", ETagtype::DINT)]).is_err());
+        let (server, device) = server(&[("HR65534", "a", ETagtype::REAL)]).unwrap();
+        device.set("a", ETagValue::Real(1.5));
+        server.refresh().unwrap();
+        assert_eq!(
+            server.handle_pdu(1, &[FC_READ_HOLDING_REGISTERS, 0xFF, 0xFE, 0x00, 0x02]),
+            vec![FC_READ_HOLDING_REGISTERS, 4, 0x3F, 0xC0, 0x00, 0x00]
+        );
+        assert_eq!(
+            server.handle_pdu(1, &[FC_READ_HOLDING_REGISTERS, 0xFF, 0xFF, 0x00, 0x02]),
+            vec![FC_READ_HOLDING_REGISTERS | 0x80, EX_ILLEGAL_ADDRESS]
+        );
+    }
+
+    #[test]
+    fn forwards_only_changed_bits() {
+        let (server, device) = server(&[
+            ("HR0.0", "low", ETagtype::BOOL),
+            ("HR0.1", "high", ETagtype::BOOL),
+        ])
+        .unwrap();
+        let resp = server.handle_pdu(1, &[FC_WRITE_SINGLE_REGISTER, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(resp[0], FC_WRITE_SINGLE_REGISTER);
+        assert_eq!(device.get("low"), Some(ETagValue::Bool(true)));
+        assert_eq!(device.get("high"), None);
+    }
+
+    #[test]
+    fn rejects_unmapped_writes_and_other_units() {
+        let (server, _) = server(&[("HR0", "a", ETagtype::INT)]).unwrap();
+        assert_eq!(
+            server.handle_pdu(1, &[FC_WRITE_SINGLE_REGISTER, 0x00, 0x01, 0x00, 0x01]),
+            vec![FC_WRITE_SINGLE_REGISTER | 0x80, EX_ILLEGAL_ADDRESS]
+        );
+        assert_eq!(
+            server.handle_pdu(7, &[FC_READ_HOLDING_REGISTERS, 0x00, 0x00, 0x00, 0x01]),
+            vec![FC_READ_HOLDING_REGISTERS | 0x80, EX_GATEWAY_PATH]
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use super::{Client, ModbusConfig, Transport};
+use crate::plc_driver::registry::{self, Registry};
+use log::info;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+pub const MODBUS_TCP_PORT: u16 = 502;
+const MBAP_HEADER_LEN: usize = 7;
+
+pub type TcpClient = Client<TcpTransport>;
+
+// Reconnects lazily: a failed exchange drops the stream and the next
+// request opens a new one.
+#[derive(Debug)]
+pub struct TcpTransport {
+    host: String,
+    timeout: Duration,
+    stream: Option<TcpStream>,
+    transaction: u16,
+}
+
+impl TcpTransport {
+    pub fn new(host: &str, timeout: Duration) -> Self {
+        Self {
+            host: host.to_owned(),
+            timeout,
+            stream: None,
+            transaction: 0,
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    pub fn close(&mut self) {
+        self.stream.take();
+    }
+
+    pub fn open(&mut self) -> Result<(), String> {
+        let addr = if self.host.contains(':') {
+            self.host.to_socket_addrs()
+        } else {
+            (self.host.as_str(), MODBUS_TCP_PORT).to_socket_addrs()
+        }
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("Cannot resolve {}", self.host))?;
+        let stream = TcpStream::connect_timeout(&addr, self.timeout).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(|e| e.to_string())?;
+        stream
+            .set_write_timeout(Some(self.timeout))
+            .map_err(|e| e.to_string())?;
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        info!("Modbus TCP connected to {}", addr);
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn exchange(&mut self, unit: u8, pdu: &[u8]) -> Result<Vec<u8>, String> {
+        self.transaction = self.transaction.wrapping_add(1);
+        let stream = self.stream.as_mut().unwrap();
+        let mut frame = Vec::with_capacity(MBAP_HEADER_LEN + pdu.len());
+        frame.extend_from_slice(&self.transaction.to_be_bytes());
+        frame.extend_from_slice(&[0x00, 0x00]);
+        frame.extend_from_slice(&((pdu.len() + 1) as u16).to_be_bytes());
+        frame.push(unit);
+        frame.extend_from_slice(pdu);
+        stream.write_all(&frame).map_err(|e| e.to_string())?;
+
+        let mut header = [0u8; MBAP_HEADER_LEN];
+        stream.read_exact(&mut header).map_err(|e| e.to_string())?;
+        let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+        if header[2..4] != [0x00, 0x00] || len < 2 {
+            return Err(String::from("Invalid MBAP header"));
+        }
+        let mut resp = vec![0u8; len - 1];
+        stream.read_exact(&mut resp).map_err(|e| e.to_string())?;
+        if header[0..2] != self.transaction.to_be_bytes() || header[6] != unit {
+            return Err(String::from("Modbus TCP response does not match request"));
+        }
+        Ok(resp)
+    }
+}
+
+impl Transport for TcpTransport {
+    fn transact(&mut self, unit: u8, pdu: &[u8]) -> Result<Vec<u8>, String> {
+        if self.stream.is_none() {
+            self.open()?;
+        }
+        let result = self.exchange(unit, pdu);
+        if result.is_err() {
+            self.close();
+        }
+        result
+    }
+}
+
+impl Client<TcpTransport> {
+    // `host` is `address` or `address:port`
+    pub fn connect(host: &str, config: ModbusConfig) -> Self {
+        let mut transport = TcpTransport::new(host, Duration::from_secs(3));
+        if let Err(err) = transport.open() {
+            info!("Connect to {} failed: {}", host, err);
+        }
+        Client::with_transport(transport, config)
+    }
+
+    pub fn set_timeout(&self, timeout: Duration) {
+        let mut transport = self.transport();
+        transport.timeout = timeout;
+        transport.close();
+    }
+
+    pub fn connected(&self) -> bool {
+        self.transport().connected()
+    }
+
+    pub fn close(&self) {
+        self.transport().close();
+    }
+}
+
+// modbus+tcp://host[:port]?unit=1&order=ABCD
+pub fn register(registry: &mut Registry) {
+    registry.register_device(
+        "modbus+tcp",
+        |url| {
+            let host = registry::host(url)?;
+            let client = TcpClient::connect(host.as_str(), ModbusConfig::from_url(url)?);
+            if client.connected() {
+                Ok(client)
+            } else {
+                Err(format!("Connect to {} failed", host))
+            }
+        },
+        |client| client.connected(),
+    );
+}
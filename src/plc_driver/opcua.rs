@@ -0,0 +1,619 @@
+#[cfg(feature = "opcua-server")]
+pub mod server;
+
+use super::registry::{param, Registry};
+use super::{ETag, ETagRW, ETagValue, ETagtype};
+use ::opcua::client::prelude::*;
+use ::opcua::sync::RwLock;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OpcUaConfig {
+    pub endpoint: String,
+    // None, Basic128Rsa15, Basic256, Basic256Sha256, Aes128-Sha256-RsaOaep...
+    pub security_policy: String,
+    // None, Sign or SignAndEncrypt
+    pub security_mode: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    pub pki_dir: String,
+    pub trust_server_certs: bool,
+    // Upper bound of nodes in one Read or Write service call
+    pub max_nodes_per_call: usize,
+}
+
+impl OpcUaConfig {
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            endpoint: endpoint.to_owned(),
+            security_policy: String::from("None"),
+            security_mode: String::from("None"),
+            username: None,
+            password: None,
+            pki_dir: String::from("./pki"),
+            trust_server_certs: false,
+            max_nodes_per_call: 500,
+        }
+    }
+}
+
+pub type SubscriptionEvent = (ETag, Result<ETagValue, String>);
+
+pub struct Client {
+    config: OpcUaConfig,
+    client: Mutex<::opcua::client::prelude::Client>,
+    session: Mutex<Option<Arc<RwLock<Session>>>>,
+    stop: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+    // Variant type of each node's value, learned from the first read of it
+    types: Mutex<HashMap<NodeId, VariantTypeId>>,
+}
+
+impl Client {
+    pub fn new(config: OpcUaConfig) -> Result<Self, String> {
+        let client = ClientBuilder::new()
+            .application_name("box-edge")
+            .application_uri("urn:box-edge")
+            .product_uri("urn:box-edge")
+            .pki_dir(config.pki_dir.as_str())
+            .create_sample_keypair(true)
+            .trust_server_certs(config.trust_server_certs)
+            .session_retry_limit(3)
+            .client()
+            .ok_or_else(|| String::from("Invalid OPC UA client configuration"))?;
+        Ok(Self {
+            config,
+            client: Mutex::new(client),
+            session: Mutex::new(None),
+            stop: Mutex::new(None),
+            types: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn connect(&self) -> Result<(), String> {
+        let policy = SecurityPolicy::from_str(&self.config.security_policy)
+            .map_err(|_| format!("Unknown security policy {}", self.config.security_policy))?;
+        let mode = match self.config.security_mode.as_str() {
+            "None" => MessageSecurityMode::None,
+            "Sign" => MessageSecurityMode::Sign,
+            "SignAndEncrypt" => MessageSecurityMode::SignAndEncrypt,
+            m => return Err(format!("Unknown security mode {}", m)),
+        };
+        let (token_policy, identity) = match &self.config.username {
+            Some(user) => (
+                UserTokenPolicy {
+                    token_type: UserTokenType::UserName,
+                    ..UserTokenPolicy::anonymous()
+                },
+                IdentityToken::UserName(
+                    user.clone(),
+                    self.config.password.clone().unwrap_or_default(),
+                ),
+            ),
+            None => (UserTokenPolicy::anonymous(), IdentityToken::Anonymous),
+        };
+        let endpoint: EndpointDescription = (
+            self.config.endpoint.as_str(),
+            policy.to_str(),
+            mode,
+            token_policy,
+        )
+            .into();
+        self.close();
+        self.types.lock().unwrap().clear();
+        let session = self
+            .client
+            .lock()
+            .unwrap()
+            .connect_to_endpoint(endpoint, identity)
+            .map_err(|s| s.to_string())?;
+        info!("OPC UA session to {}", self.config.endpoint);
+
+        // Services subscriptions and keep alive in the background
+        let tx = Session::run_async(session.clone());
+        *self.stop.lock().unwrap() = Some(Box::new(move || {
+            let _ = tx.send(SessionCommand::Stop);
+        }));
+        *self.session.lock().unwrap() = Some(session);
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        if let Some(stop) = self.stop.lock().unwrap().take() {
+            stop();
+        }
+        if let Some(session) = self.session.lock().unwrap().take() {
+            session.write().disconnect();
+        }
+    }
+
+    pub fn connected(&self) -> bool {
+        match self.session.lock().unwrap().as_ref() {
+            Some(session) => session.read().is_connected(),
+            None => false,
+        }
+    }
+
+    fn session(&self) -> Result<Arc<RwLock<Session>>, String> {
+        self.session
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| String::from("Not connected"))
+    }
+
+    pub fn conv_address(&self, address: &str) -> Result<NodeId, String> {
+        NodeId::from_str(address).map_err(|_| format!("Invalid NodeId {}", address))
+    }
+
+    // Pushes value changes of `tags` instead of polling them. Events arrive
+    // on the returned channel until the subscription is deleted.
+    pub fn subscribe(
+        &self,
+        tags: &[ETag],
+        interval_ms: f64,
+    ) -> Result<(u32, Receiver<SubscriptionEvent>), String> {
+        let mut nodes: HashMap<NodeId, Vec<ETag>> = HashMap::new();
+        for tag in tags {
+            let node = self.conv_address(&tag.address)?;
+            nodes.entry(node).or_default().push(tag.clone());
+        }
+        let requests: Vec<MonitoredItemCreateRequest> =
+            nodes.keys().map(|n| n.clone().into()).collect();
+
+        let (sender, receiver) = channel();
+        let sender = Mutex::new(sender);
+        let session = self.session()?;
+        let session = session.read();
+        let id = session
+            .create_subscription(
+                interval_ms,
+                10,
+                30,
+                0,
+                0,
+                true,
+                DataChangeCallback::new(move |items| {
+                    let sender = sender.lock().unwrap();
+                    for item in items {
+                        let node = &item.item_to_monitor().node_id;
+                        for tag in nodes.get(node).into_iter().flatten() {
+                            let value = data_value(item.last_value(), tag.datatype);
+                            let _ = sender.send((tag.clone(), value));
+                        }
+                    }
+                }),
+            )
+            .map_err(|s| s.to_string())?;
+        let results = session
+            .create_monitored_items(id, TimestampsToReturn::Neither, &requests)
+            .map_err(|s| s.to_string())?;
+        for (request, result) in requests.iter().zip(results) {
+            if !result.status_code.is_good() {
+                warn!(
+                    "Monitoring {} failed: {}",
+                    request.item_to_monitor.node_id, result.status_code
+                );
+            }
+        }
+        Ok((id, receiver))
+    }
+
+    pub fn unsubscribe(&self, id: u32) -> Result<(), String> {
+        let session = self.session()?;
+        let session = session.read();
+        session
+            .delete_subscription(id)
+            .map(|_| ())
+            .map_err(|s| s.to_string())
+    }
+
+    fn read_nodes(&self, nodes: &[NodeId]) -> Result<Vec<DataValue>, String> {
+        let session = self.session()?;
+        let session = session.read();
+        let mut values = Vec::with_capacity(nodes.len());
+        for chunk in nodes.chunks(self.config.max_nodes_per_call.max(1)) {
+            let ids: Vec<ReadValueId> = chunk.iter().map(|n| n.clone().into()).collect();
+            values.extend(
+                session
+                    .read(&ids, TimestampsToReturn::Neither, 0.0)
+                    .map_err(|s| s.to_string())?,
+            );
+        }
+        let mut types = self.types.lock().unwrap();
+        for (node, value) in nodes.iter().zip(&values) {
+            if let Some(v) = &value.value {
+                if v.type_id() != VariantTypeId::Empty {
+                    types.insert(node.clone(), v.type_id());
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    // Writes have to carry the node's own type, servers reject anything else
+    // with BadTypeMismatch. Nodes that were never read are read once here.
+    fn value_types(&self, nodes: &[NodeId]) -> Result<Vec<Result<VariantTypeId, String>>, String> {
+        let unknown: Vec<NodeId> = {
+            let types = self.types.lock().unwrap();
+            nodes
+                .iter()
+                .filter(|n| !types.contains_key(n))
+                .cloned()
+                .collect()
+        };
+        if !unknown.is_empty() {
+            self.read_nodes(&unknown)?;
+        }
+        let types = self.types.lock().unwrap();
+        Ok(nodes
+            .iter()
+            .map(|n| {
+                types
+                    .get(n)
+                    .copied()
+                    .ok_or_else(|| format!("Type of {} is unknown", n))
+            })
+            .collect())
+    }
+
+    fn write_nodes(&self, writes: Vec<WriteValue>) -> Result<Vec<StatusCode>, String> {
+        let session = self.session()?;
+        let session = session.read();
+        let mut results = Vec::with_capacity(writes.len());
+        for chunk in writes.chunks(self.config.max_nodes_per_call.max(1)) {
+            results.extend(session.write(chunk).map_err(|s| s.to_string())?);
+        }
+        Ok(results)
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn data_value(value: &DataValue, datatype: ETagtype) -> Result<ETagValue, String> {
+    if let Some(status) = value.status {
+        if !status.is_good() {
+            return Err(status.to_string());
+        }
+    }
+    match &value.value {
+        Some(v) => from_variant(v, datatype),
+        None => Err(String::from("No value")),
+    }
+}
+
+pub fn from_variant(value: &Variant, datatype: ETagtype) -> Result<ETagValue, String> {
+    let value = match value {
+        Variant::Boolean(v) => ETagValue::Bool(*v),
+        Variant::SByte(v) => ETagValue::Int(*v as i64),
+        Variant::Byte(v) => ETagValue::Int(*v as i64),
+        Variant::Int16(v) => ETagValue::Int(*v as i64),
+        Variant::UInt16(v) => ETagValue::Int(*v as i64),
+        Variant::Int32(v) => ETagValue::Int(*v as i64),
+        Variant::UInt32(v) => ETagValue::Int(*v as i64),
+        Variant::Int64(v) => ETagValue::Int(*v),
+        Variant::UInt64(v) => {
+            ETagValue::Int(i64::try_from(*v).map_err(|_| format!("{} is out of range", v))?)
+        }
+        Variant::Float(v) => ETagValue::Real(*v as f64),
+        Variant::Double(v) => ETagValue::Real(*v),
+        Variant::String(v) => ETagValue::String(v.as_ref().to_owned()),
+        v => return Err(format!("Unsupported OPC UA value {:?}", v)),
+    };
+    match (datatype, &value) {
+        // There is no STRING tag type, text is passed on as read
+        (_, ETagValue::String(_)) => Ok(value),
+        (ETagtype::BOOL, ETagValue::Bool(_)) => Ok(value),
+        (ETagtype::INT, ETagValue::Int(_)) | (ETagtype::DINT, ETagValue::Int(_)) => Ok(value),
+        (ETagtype::REAL, ETagValue::Real(_)) => Ok(value),
+        (ETagtype::REAL, &ETagValue::Int(v)) => Ok(ETagValue::Real(v as f64)),
+        _ => Err(format!("{:?} does not match {:?}", value, datatype)),
+    }
+}
+
+// Converts to the node's own type, e.g. Double, UInt16 or Byte, with the
+// range checks of Variant::cast
+pub fn to_variant(value: ETagValue, target: VariantTypeId) -> Result<Variant, String> {
+    let variant = match &value {
+        ETagValue::Bool(v) => Variant::Boolean(*v),
+        ETagValue::Int(v) => Variant::Int64(*v),
+        ETagValue::Real(v) => Variant::Double(*v),
+        ETagValue::String(v) => Variant::String(UAString::from(v.as_str())),
+        v => return Err(format!("Unsupported OPC UA value {:?}", v)),
+    };
+    match variant.cast(target) {
+        Variant::Empty => Err(format!("{:?} cannot be written as {:?}", value, target)),
+        v => Ok(v),
+    }
+}
+
+impl ETagRW for Client {
+    fn read_tag(&self, tag: &ETag) -> Result<ETagValue, String> {
+        let node = self.conv_address(&tag.address)?;
+        let value = self.read_nodes(&[node])?;
+        match value.first() {
+            Some(value) => data_value(value, tag.datatype),
+            None => Err(String::from("No value")),
+        }
+    }
+    fn read_list(&self, tags: &Vec<ETag>) -> Result<Vec<Result<ETagValue, String>>, String> {
+        let nodes = tags
+            .iter()
+            .map(|tag| self.conv_address(&tag.address))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        let values = self.read_nodes(&nodes)?;
+        if values.len() != tags.len() {
+            return Err(String::from("Read returned a wrong number of values"));
+        }
+        Ok(values
+            .iter()
+            .zip(tags)
+            .map(|(value, tag)| data_value(value, tag.datatype))
+            .collect())
+    }
+    fn write_tag(&self, tag: &ETag, write: ETagValue) -> Result<bool, String> {
+        self.write_list(&vec![(tag.clone(), write)])?.pop().unwrap()
+    }
+    fn write_list(
+        &self,
+        tags: &Vec<(ETag, ETagValue)>,
+    ) -> Result<Vec<Result<bool, String>>, String> {
+        let nodes = tags
+            .iter()
+            .map(|t| self.conv_address(&t.0.address))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        let types = self.value_types(&nodes)?;
+        let converted: Vec<Result<Variant, String>> = tags
+            .iter()
+            .zip(types)
+            .map(|((tag, value), target)| {
+                let value = tag.datatype.coerce(value.clone(), tag.range)?;
+                to_variant(value, target?)
+            })
+            .collect();
+        let writes: Vec<WriteValue> = nodes
+            .into_iter()
+            .zip(converted.iter())
+            .filter_map(|(node, value)| {
+                value.as_ref().ok().map(|value| WriteValue {
+                    node_id: node,
+                    attribute_id: AttributeId::Value as u32,
+                    index_range: UAString::null(),
+                    value: DataValue::value_only(value.clone()),
+                })
+            })
+            .collect();
+        let mut written = self.write_nodes(writes)?.into_iter();
+        Ok(converted
+            .into_iter()
+            .map(|value| match value {
+                Ok(_) => match written.next() {
+                    Some(status) if status.is_good() => Ok(true),
+                    Some(status) => Err(status.to_string()),
+                    None => Err(String::from("Not written")),
+                },
+                Err(err) => Err(err),
+            })
+            .collect())
+    }
+}
+
+// opc.tcp://[user:password@]host:4840/path?security_policy=Basic256Sha256&security_mode=Sign
+pub fn register(registry: &mut Registry) {
+    registry.register_device(
+        "opc.tcp",
+        |url| {
+            let mut endpoint = url.clone();
+            endpoint.set_query(None);
+            let _ = endpoint.set_username("");
+            let _ = endpoint.set_password(None);
+            let mut config = OpcUaConfig::new(endpoint.as_str());
+            config.security_policy = param(url, "security_policy", config.security_policy)?;
+            config.security_mode = param(url, "security_mode", config.security_mode)?;
+            config.pki_dir = param(url, "pki_dir", config.pki_dir)?;
+            config.trust_server_certs = param(url, "trust_server_certs", false)?;
+            if !url.username().is_empty() {
+                config.username = Some(url.username().to_owned());
+                config.password = url.password().map(|p| p.to_owned());
+            }
+            let client = Client::new(config)?;
+            client.connect()?;
+            Ok(client)
+        },
+        |client| client.connected(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "opcua-server")]
+    use super::server::{OpcUaServerConfig, Server, ServerDevice, TagGroup};
+    use super::*;
+    #[cfg(feature = "opcua-server")]
+    use crate::plc_driver::tests::{tag, Memory};
+    #[cfg(feature = "opcua-server")]
+    use std::net::TcpListener;
+    #[cfg(feature = "opcua-server")]
+    use std::thread;
+    #[cfg(feature = "opcua-server")]
+    use std::time::Duration;
+
+    #[test]
+    fn writes_in_the_node_type() {
+        assert_eq!(
+            to_variant(ETagValue::Int(12), VariantTypeId::UInt16),
+            Ok(Variant::UInt16(12))
+        );
+        assert_eq!(
+            to_variant(ETagValue::Int(200), VariantTypeId::Byte),
+            Ok(Variant::Byte(200))
+        );
+        assert_eq!(
+            to_variant(ETagValue::Int(-3), VariantTypeId::Int64),
+            Ok(Variant::Int64(-3))
+        );
+        assert_eq!(
+            to_variant(ETagValue::Real(2.5), VariantTypeId::Double),
+            Ok(Variant::Double(2.5))
+        );
+        assert_eq!(
+            to_variant(ETagValue::Real(2.5), VariantTypeId::Float),
+            Ok(Variant::Float(2.5))
+        );
+        assert_eq!(
+            to_variant(ETagValue::Bool(true), VariantTypeId::Boolean),
+            Ok(Variant::Boolean(true))
+        );
+        assert_eq!(
+            to_variant(ETagValue::from("on"), VariantTypeId::String),
+            Ok(Variant::String(UAString::from("on")))
+        );
+    }
+
+    #[test]
+    fn rejects_values_out_of_the_node_range() {
+        assert_eq!(
+            to_variant(ETagValue::Int(-1), VariantTypeId::UInt16),
+            Err(String::from("Int(-1) cannot be written as UInt16"))
+        );
+        assert_eq!(
+            to_variant(ETagValue::Int(256), VariantTypeId::Byte),
+            Err(String::from("Int(256) cannot be written as Byte"))
+        );
+        assert!(to_variant(ETagValue::Bytes(vec![1]), VariantTypeId::ByteString).is_err());
+    }
+
+    #[test]
+    fn reads_any_numeric_variant() {
+        assert_eq!(
+            from_variant(&Variant::UInt32(70_000), ETagtype::DINT),
+            Ok(ETagValue::Int(70_000))
+        );
+        assert_eq!(
+            from_variant(&Variant::UInt64(5), ETagtype::DINT),
+            Ok(ETagValue::Int(5))
+        );
+        assert_eq!(
+            from_variant(&Variant::UInt64(u64::MAX), ETagtype::DINT),
+            Err(format!("{} is out of range", u64::MAX))
+        );
+        assert_eq!(
+            from_variant(&Variant::Double(0.25), ETagtype::REAL),
+            Ok(ETagValue::Real(0.25))
+        );
+        assert_eq!(
+            from_variant(&Variant::Byte(7), ETagtype::REAL),
+            Ok(ETagValue::Real(7.0))
+        );
+        assert_eq!(
+            from_variant(&Variant::String(UAString::from("ready")), ETagtype::INT),
+            Ok(ETagValue::from("ready"))
+        );
+        assert!(from_variant(&Variant::Double(0.25), ETagtype::INT).is_err());
+    }
+
+    #[cfg(feature = "opcua-server")]
+    #[test]
+    fn client_against_local_server() {
+        let device = Arc::new(Memory::default());
+        device.set("speed", ETagValue::Int(-7));
+        device.set("level", ETagValue::Real(2.5));
+        device.set("run", ETagValue::Bool(true));
+        let pki = std::env::temp_dir().join(format!("box-edge-opcua-{}", std::process::id()));
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = OpcUaServerConfig {
+            host: String::from("127.0.0.1"),
+            port,
+            pki_dir: pki.join("server").to_string_lossy().into_owned(),
+            ..OpcUaServerConfig::default()
+        };
+        let server = Arc::new(
+            Server::new(
+                &config,
+                vec![ServerDevice {
+                    name: String::from("line"),
+                    driver: device.clone(),
+                    groups: vec![TagGroup {
+                        name: String::from("g"),
+                        tags: vec![
+                            tag("speed", ETagtype::INT),
+                            tag("level", ETagtype::REAL),
+                            tag("run", ETagtype::BOOL),
+                        ],
+                    }],
+                }],
+            )
+            .unwrap(),
+        );
+        server.poll();
+        let running = server.clone();
+        thread::spawn(move || running.run());
+
+        // Client tags address the server nodes by NodeId
+        let tags: Vec<ETag> = server
+            .node_ids()
+            .into_iter()
+            .map(|(_, t, node)| ETag {
+                address: node.to_string(),
+                ..t.clone()
+            })
+            .collect();
+        let mut client_config = OpcUaConfig::new(&format!("opc.tcp://127.0.0.1:{}/", port));
+        client_config.pki_dir = pki.join("client").to_string_lossy().into_owned();
+        client_config.max_nodes_per_call = 2;
+        let client = Client::new(client_config).unwrap();
+        let mut attempts = 0;
+        while client.connect().is_err() {
+            attempts += 1;
+            assert!(attempts < 50, "OPC UA server did not start");
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        assert_eq!(
+            client.read_list(&tags).unwrap(),
+            vec![
+                Ok(ETagValue::Int(-7)),
+                Ok(ETagValue::Real(2.5)),
+                Ok(ETagValue::Bool(true)),
+            ]
+        );
+        assert_eq!(
+            client.write_list(&vec![
+                (tags[0].clone(), ETagValue::Int(12)),
+                (tags[2].clone(), ETagValue::Bool(false)),
+            ]),
+            Ok(vec![Ok(true), Ok(true)])
+        );
+        assert_eq!(device.get("speed"), Some(ETagValue::Int(12)));
+        assert_eq!(device.get("run"), Some(ETagValue::Bool(false)));
+
+        let (id, events) = client.subscribe(&tags[1..2], 100.0).unwrap();
+        let (tag, value) = events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            (tag.name.as_str(), value),
+            ("level", Ok(ETagValue::Real(2.5)))
+        );
+        client.unsubscribe(id).unwrap();
+
+        client.close();
+        server.abort();
+        let _ = std::fs::remove_dir_all(pki);
+    }
+}
@@ -0,0 +1,193 @@
+use super::{from_variant, to_variant};
+use crate::plc_driver::{ETag, ETagRW, ETagtype};
+use ::opcua::server::prelude::*;
+use ::opcua::sync::{Mutex, RwLock};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const NAMESPACE_URI: &str = "urn:box-edge";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OpcUaServerConfig {
+    pub application_name: String,
+    pub host: String,
+    pub port: u16,
+    pub pki_dir: String,
+}
+
+impl Default for OpcUaServerConfig {
+    fn default() -> Self {
+        Self {
+            application_name: String::from("box-edge"),
+            host: String::from("0.0.0.0"),
+            port: 4840,
+            pki_dir: String::from("./pki-server"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TagGroup {
+    pub name: String,
+    pub tags: Vec<ETag>,
+}
+
+// A configured device: its tags show up under Objects/<name>/<group>/
+pub struct ServerDevice {
+    pub name: String,
+    pub driver: Arc<dyn ETagRW + Send + Sync>,
+    pub groups: Vec<TagGroup>,
+}
+
+pub struct Server {
+    server: Arc<RwLock<::opcua::server::prelude::Server>>,
+    devices: Vec<(ServerDevice, Vec<(ETag, NodeId)>)>,
+}
+
+fn data_type(datatype: ETagtype) -> (DataTypeId, Variant) {
+    match datatype {
+        ETagtype::BOOL => (DataTypeId::Boolean, Variant::Boolean(false)),
+        ETagtype::INT => (DataTypeId::Int16, Variant::Int16(0)),
+        ETagtype::DINT => (DataTypeId::Int32, Variant::Int32(0)),
+        ETagtype::REAL => (DataTypeId::Float, Variant::Float(0.0)),
+    }
+}
+
+impl Server {
+    pub fn new(config: &OpcUaServerConfig, devices: Vec<ServerDevice>) -> Result<Self, String> {
+        let url = format!("opc.tcp://{}:{}/", config.host, config.port);
+        let server = ServerBuilder::new_anonymous(config.application_name.as_str())
+            .application_uri(NAMESPACE_URI)
+            .product_uri(NAMESPACE_URI)
+            .host_and_port(config.host.as_str(), config.port)
+            .discovery_urls(vec![url])
+            .pki_dir(config.pki_dir.as_str())
+            .create_sample_keypair(true)
+            .server()
+            .ok_or_else(|| String::from("Invalid OPC UA server configuration"))?;
+
+        let mut nodes = Vec::with_capacity(devices.len());
+        {
+            let address_space = server.address_space();
+            let mut address_space = address_space.write();
+            let ns = address_space
+                .register_namespace(NAMESPACE_URI)
+                .map_err(|_| String::from("Cannot register namespace"))?;
+            for device in &devices {
+                let device_id = NodeId::new(ns, device.name.clone());
+                if !address_space.add_folder_with_id(
+                    &device_id,
+                    device.name.as_str(),
+                    device.name.as_str(),
+                    &NodeId::objects_folder_id(),
+                ) {
+                    return Err(format!("Cannot add folder {}", device.name));
+                }
+                let mut tags = Vec::new();
+                for group in &device.groups {
+                    let group_id = NodeId::new(ns, format!("{}.{}", device.name, group.name));
+                    if !address_space.add_folder_with_id(
+                        &group_id,
+                        group.name.as_str(),
+                        group.name.as_str(),
+                        &device_id,
+                    ) {
+                        return Err(format!("Cannot add folder {}", group.name));
+                    }
+                    for tag in &group.tags {
+                        let node =
+                            NodeId::new(ns, format!("{}.{}.{}", device.name, group.name, tag.name));
+                        let (type_id, initial) = data_type(tag.datatype);
+                        VariableBuilder::new(&node, tag.name.as_str(), tag.name.as_str())
+                            .data_type(type_id)
+                            .value(initial)
+                            .writable()
+                            .value_setter(write_setter(device.driver.clone(), tag.clone()))
+                            .organized_by(&group_id)
+                            .insert(&mut address_space);
+                        tags.push((tag.clone(), node));
+                    }
+                }
+                info!("OPC UA device {} with {} tags", device.name, tags.len());
+                nodes.push(tags);
+            }
+        }
+
+        Ok(Self {
+            server: Arc::new(RwLock::new(server)),
+            devices: devices.into_iter().zip(nodes).collect(),
+        })
+    }
+
+    // Called by the poller: new values reach monitored items of clients
+    // through the sampling of the address space.
+    pub fn poll(&self) {
+        let address_space = self.server.read().address_space();
+        for (device, nodes) in &self.devices {
+            let tags: Vec<ETag> = nodes.iter().map(|n| n.0.clone()).collect();
+            let values = match device.driver.read_list(&tags) {
+                Ok(values) => values,
+                Err(err) => {
+                    warn!("{}: {}", device.name, err);
+                    continue;
+                }
+            };
+            let now = DateTime::now();
+            let mut address_space = address_space.write();
+            for ((tag, node), value) in nodes.iter().zip(values) {
+                let target = data_type(tag.datatype).1.type_id();
+                match value.and_then(|v| to_variant(v, target)) {
+                    Ok(value) => {
+                        address_space.set_variable_value(node.clone(), value, &now, &now);
+                    }
+                    Err(err) => warn!("{}.{}: {}", device.name, tag.name, err),
+                }
+            }
+        }
+    }
+
+    pub fn node_ids(&self) -> Vec<(String, &ETag, &NodeId)> {
+        self.devices
+            .iter()
+            .flat_map(|(device, nodes)| {
+                nodes
+                    .iter()
+                    .map(move |(tag, node)| (device.name.clone(), tag, node))
+            })
+            .collect()
+    }
+
+    // Blocks until the server is aborted
+    pub fn run(&self) {
+        ::opcua::server::prelude::Server::run_server(self.server.clone());
+    }
+
+    pub fn abort(&self) {
+        self.server.write().abort();
+    }
+}
+
+fn write_setter(
+    driver: Arc<dyn ETagRW + Send + Sync>,
+    tag: ETag,
+) -> Arc<Mutex<dyn AttributeSetter + Send>> {
+    Arc::new(Mutex::new(AttrFnSetter::new(
+        move |_node: &NodeId, _attribute, _range, value: DataValue| {
+            let value = value
+                .value
+                .as_ref()
+                .ok_or(StatusCode::BadTypeMismatch)
+                .and_then(|v| {
+                    from_variant(v, tag.datatype).map_err(|_| StatusCode::BadTypeMismatch)
+                })?;
+            match driver.write_tag(&tag, value) {
+                Ok(_) => Ok(()),
+                Err(err) => {
+                    warn!("OPC UA write to {} failed: {}", tag.name, err);
+                    Err(StatusCode::BadCommunicationError)
+                }
+            }
+        },
+    )))
+}
@@ -0,0 +1,317 @@
+use super::{ETag, ETagRW, ETagValue};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use url::Url;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DriverStatus {
+    Disconnected,
+    Connected,
+    Failed(String),
+}
+
+pub trait Driver: ETagRW + Send + Sync {
+    fn url(&self) -> &Url;
+    fn connect(&mut self) -> Result<(), String>;
+    fn close(&mut self);
+    fn status(&mut self) -> DriverStatus;
+}
+
+pub type DriverFactory = Box<dyn Fn(&Url) -> Result<Box<dyn Driver>, String> + Send + Sync>;
+
+// A protocol client behind a URL: `open` builds and connects a new client,
+// closing drops it
+pub struct Device<C> {
+    url: Url,
+    open: fn(&Url) -> Result<C, String>,
+    connected: fn(&mut C) -> bool,
+    client: Option<C>,
+    error: Option<String>,
+}
+
+impl<C> Device<C> {
+    pub fn new(
+        url: &Url,
+        open: fn(&Url) -> Result<C, String>,
+        connected: fn(&mut C) -> bool,
+    ) -> Self {
+        Self {
+            url: url.clone(),
+            open,
+            connected,
+            client: None,
+            error: None,
+        }
+    }
+
+    fn client(&self) -> Result<&C, String> {
+        self.client
+            .as_ref()
+            .ok_or_else(|| String::from("Not connected"))
+    }
+}
+
+impl<C: ETagRW + Send + Sync> Driver for Device<C> {
+    fn url(&self) -> &Url {
+        &self.url
+    }
+
+    fn connect(&mut self) -> Result<(), String> {
+        self.client = None;
+        match (self.open)(&self.url) {
+            Ok(client) => {
+                info!("Driver {} connected", self.url);
+                self.client = Some(client);
+                self.error = None;
+                Ok(())
+            }
+            Err(err) => {
+                self.error = Some(err.clone());
+                Err(err)
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        self.client = None;
+        self.error = None;
+    }
+
+    fn status(&mut self) -> DriverStatus {
+        match (self.client.as_mut().map(self.connected), &self.error) {
+            (Some(true), _) => DriverStatus::Connected,
+            (None, Some(err)) => DriverStatus::Failed(err.clone()),
+            _ => DriverStatus::Disconnected,
+        }
+    }
+}
+
+impl<C: ETagRW> ETagRW for Device<C> {
+    fn read_tag(&self, tag: &ETag) -> Result<ETagValue, String> {
+        self.client()?.read_tag(tag)
+    }
+    fn read_list(&self, tags: &Vec<ETag>) -> Result<Vec<Result<ETagValue, String>>, String> {
+        self.client()?.read_list(tags)
+    }
+    fn write_tag(&self, tag: &ETag, write: ETagValue) -> Result<bool, String> {
+        self.client()?.write_tag(tag, write)
+    }
+    fn write_list(
+        &self,
+        tags: &Vec<(ETag, ETagValue)>,
+    ) -> Result<Vec<Result<bool, String>>, String> {
+        self.client()?.write_list(tags)
+    }
+}
+
+// Query parameter `key` of a device URL, `default` when absent
+pub fn param<T: FromStr>(url: &Url, key: &str, default: T) -> Result<T, String> {
+    match url.query_pairs().find(|(k, _)| k == key) {
+        Some((_, v)) => v
+            .parse()
+            .map_err(|_| format!("Invalid value {} for {} in {}", v, key, url)),
+        None => Ok(default),
+    }
+}
+
+// `host:port` of a device URL, `host` alone leaves the port to the driver
+pub fn host(url: &Url) -> Result<String, String> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| format!("Missing host in {}", url))?;
+    Ok(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_owned(),
+    })
+}
+
+pub struct Registry {
+    factories: BTreeMap<String, DriverFactory>,
+}
+
+impl Default for Registry {
+    // Every driver compiled into this build
+    fn default() -> Self {
+        let mut registry = Registry::new();
+        super::register_drivers(&mut registry);
+        registry
+    }
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self {
+            factories: BTreeMap::new(),
+        }
+    }
+
+    // A scheme belongs to exactly one driver, registering it twice is a bug
+    pub fn register<F>(&mut self, scheme: &str, factory: F)
+    where
+        F: Fn(&Url) -> Result<Box<dyn Driver>, String> + Send + Sync + 'static,
+    {
+        let previous = self.factories.insert(scheme.to_owned(), Box::new(factory));
+        assert!(previous.is_none(), "{}:// is registered twice", scheme);
+    }
+
+    // Registers a client type wrapped in a Device
+    pub fn register_device<C>(
+        &mut self,
+        scheme: &str,
+        open: fn(&Url) -> Result<C, String>,
+        connected: fn(&mut C) -> bool,
+    ) where
+        C: ETagRW + Send + Sync + 'static,
+    {
+        self.register(scheme, move |url| {
+            Ok(Box::new(Device::new(url, open, connected)) as Box<dyn Driver>)
+        });
+    }
+
+    pub fn schemes(&self) -> Vec<&str> {
+        self.factories.keys().map(|k| k.as_str()).collect()
+    }
+
+    // The driver is returned unconnected
+    pub fn open(&self, url: &str) -> Result<Box<dyn Driver>, String> {
+        let url = Url::parse(url).map_err(|e| format!("Invalid device URL {}: {}", url, e))?;
+        let factory = self
+            .factories
+            .get(url.scheme())
+            .ok_or_else(|| format!("No driver for {}:// in this build", url.scheme()))?;
+        factory(&url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plc_driver::tests::{tag, Memory};
+    use crate::plc_driver::ETagtype;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    // `?fail=true` refuses the connection, writing `up` false drops it
+    fn registry() -> Registry {
+        let mut registry = Registry::new();
+        registry.register_device(
+            "mem",
+            |url| {
+                if param(url, "fail", false)? {
+                    Err(format!("{} refused", host(url)?))
+                } else {
+                    let memory = Memory::default();
+                    memory.set("up", ETagValue::Bool(true));
+                    Ok(memory)
+                }
+            },
+            |memory| memory.get("up") == Some(ETagValue::Bool(true)),
+        );
+        registry
+    }
+
+    #[test]
+    fn reads_url_params() {
+        let u = url("sim://plc?rack=2&name=line%201&flag=true");
+        assert_eq!(param(&u, "rack", 0), Ok(2));
+        assert_eq!(param(&u, "slot", 1), Ok(1));
+        assert_eq!(param(&u, "name", String::new()), Ok(String::from("line 1")));
+        assert_eq!(param(&u, "flag", false), Ok(true));
+        assert_eq!(
+            param(&u, "flag", 0u8),
+            Err(format!("Invalid value true for flag in {}", u))
+        );
+    }
+
+    #[test]
+    fn keeps_the_url_port() {
+        assert_eq!(host(&url("s7://10.0.0.1")), Ok(String::from("10.0.0.1")));
+        assert_eq!(
+            host(&url("modbus+tcp://plc:1502/")),
+            Ok(String::from("plc:1502"))
+        );
+        assert_eq!(
+            host(&url("sim://")),
+            Err(String::from("Missing host in sim://"))
+        );
+    }
+
+    #[test]
+    fn opens_registered_schemes_only() {
+        let registry = registry();
+        assert_eq!(registry.schemes(), vec!["mem"]);
+        let driver = registry.open("mem://plc").unwrap();
+        assert_eq!(driver.url().as_str(), "mem://plc");
+        assert_eq!(
+            registry.open("nope://plc").err(),
+            Some(String::from("No driver for nope:// in this build"))
+        );
+        assert!(registry
+            .open("plc 1")
+            .err()
+            .unwrap()
+            .starts_with("Invalid device URL plc 1"));
+    }
+
+    #[test]
+    #[should_panic(expected = "mem:// is registered twice")]
+    fn rejects_a_scheme_registered_twice() {
+        let mut registry = registry();
+        registry.register_device("mem", |_| Ok(Memory::default()), |_| true);
+    }
+
+    #[test]
+    fn default_registry_has_every_built_driver() {
+        let registry = Registry::default();
+        #[cfg(any(feature = "snap7", feature = "s7comm"))]
+        assert!(registry.schemes().contains(&"s7"));
+        #[cfg(feature = "sim")]
+        assert!(registry.open("sim://").is_ok());
+        #[cfg(not(feature = "sim"))]
+        assert!(registry.open("sim://").is_err());
+    }
+
+    #[test]
+    fn device_status_follows_the_connection() {
+        let registry = registry();
+        let point = tag("DB1W0", ETagtype::INT);
+        let mut driver = registry.open("mem://plc").unwrap();
+        assert_eq!(driver.status(), DriverStatus::Disconnected);
+        assert_eq!(driver.read_tag(&point), Err(String::from("Not connected")));
+
+        driver.connect().unwrap();
+        assert_eq!(driver.status(), DriverStatus::Connected);
+        assert_eq!(driver.write_tag(&point, ETagValue::Int(5)), Ok(true));
+        assert_eq!(driver.read_tag(&point), Ok(ETagValue::Int(5)));
+
+        // The client reports its link lost
+        let up = tag("up", ETagtype::BOOL);
+        driver.write_tag(&up, ETagValue::Bool(false)).unwrap();
+        assert_eq!(driver.status(), DriverStatus::Disconnected);
+
+        // Reconnecting starts a new client
+        driver.connect().unwrap();
+        assert_eq!(driver.status(), DriverStatus::Connected);
+        assert_eq!(driver.read_tag(&point), Err(String::from("Not written")));
+
+        driver.close();
+        assert_eq!(driver.status(), DriverStatus::Disconnected);
+    }
+
+    #[test]
+    fn failed_connect_is_reported_until_closed() {
+        let registry = registry();
+        let mut driver = registry.open("mem://plc?fail=true").unwrap();
+        assert_eq!(driver.connect(), Err(String::from("plc refused")));
+        assert_eq!(
+            driver.status(),
+            DriverStatus::Failed(String::from("plc refused"))
+        );
+        driver.close();
+        assert_eq!(driver.status(), DriverStatus::Disconnected);
+    }
+}
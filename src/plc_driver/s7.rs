@@ -1,65 +1,245 @@
-use super::{ETag, ETagRW, ETagValue, ETagtype};
+mod ffi;
+pub mod snapshot;
+pub mod pool;
+pub mod szl;
+pub mod partner;
+pub mod server;
+
+pub use super::s7_address::{S7Address, S7Area, S7WL};
+
+use self::ffi::*;
+use super::registry::{param, Registry};
+use super::s7_address::{address_regex, decode_value, encode_value};
+use super::{ERangePolicy, ETag, ETagRW, ETagValue, ETagtype};
 use bit_vec::BitVec;
+use chrono::NaiveDate;
 use itertools::Itertools;
-use log::{error, info, warn, LevelFilter};
-use regex::Regex;
-use snap7_sys::*;
-use std::cmp::Ordering;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::ffi::CString;
 use std::os::raw::{c_char, c_int, c_void};
-use url::Url;
+use std::sync::{Mutex, MutexGuard};
 
 #[derive(Debug)]
 pub struct Client {
     handle: S7Object,
+    host: String,
     req_len: usize,
     neg_len: usize,
     reg: regex::Regex,
+    password: Option<String>,
+    protection: Option<S7Protection>,
+    lock: Mutex<()>,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub enum S7Area {
-    PE = 0x81,
-    PA = 0x82,
-    MK = 0x83,
-    DB = 0x84,
+// snap7 client objects are not reentrant, every call on the handle goes
+// through `lock` so a Client can be shared between threads.
+unsafe impl Send for Client {}
+unsafe impl Sync for Client {}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum S7BlockType {
+    OB = 0x38,
+    DB = 0x41,
+    SDB = 0x42,
+    FC = 0x43,
+    SFC = 0x44,
+    FB = 0x45,
+    SFB = 0x46,
 }
 
-#[derive(Debug)]
-pub enum S7WL {
-    S7WLBit = 0x01,
-    S7WLByte = 0x02,
-    S7WLWord = 0x04,
-    S7WLDWord = 0x06,
-    S7WLReal = 0x08,
+impl S7BlockType {
+    // Block info reports the sub block type, not the one used to request it
+    fn from_sub_block(code: i32) -> Option<Self> {
+        match code {
+            0x08 => Some(S7BlockType::OB),
+            0x0A => Some(S7BlockType::DB),
+            0x0B => Some(S7BlockType::SDB),
+            0x0C => Some(S7BlockType::FC),
+            0x0D => Some(S7BlockType::SFC),
+            0x0E => Some(S7BlockType::FB),
+            0x0F => Some(S7BlockType::SFB),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum S7BlockLang {
+    AWL = 0x01,
+    KOP = 0x02,
+    FUP = 0x03,
+    SCL = 0x04,
+    DB = 0x05,
+    GRAPH = 0x06,
+}
+
+impl S7BlockLang {
+    fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0x01 => Some(S7BlockLang::AWL),
+            0x02 => Some(S7BlockLang::KOP),
+            0x03 => Some(S7BlockLang::FUP),
+            0x04 => Some(S7BlockLang::SCL),
+            0x05 => Some(S7BlockLang::DB),
+            0x06 => Some(S7BlockLang::GRAPH),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default)]
+pub struct S7BlocksList {
+    pub ob: usize,
+    pub fb: usize,
+    pub fc: usize,
+    pub sfb: usize,
+    pub sfc: usize,
+    pub db: usize,
+    pub sdb: usize,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub struct S7Address {
-    area: S7Area,
-    dbnb: u8,
-    bit: u8,
-    start: u8,
-    size: u8,
-    datatype: ETagtype,
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct S7BlockInfo {
+    pub block_type: S7BlockType,
+    pub number: u16,
+    pub language: Option<S7BlockLang>,
+    pub flags: u8,
+    pub mc7_size: usize,
+    pub load_size: usize,
+    pub local_data: usize,
+    pub sbb_length: usize,
+    pub checksum: u16,
+    pub version: u8,
+    pub code_date: Option<NaiveDate>,
+    pub intf_date: Option<NaiveDate>,
+    pub author: String,
+    pub family: String,
+    pub header: String,
+}
+
+impl S7BlockInfo {
+    fn from_ts7(info: &TS7BlockInfo) -> Result<Self, String> {
+        let blk_type = info.BlkType;
+        let block_type = S7BlockType::from_sub_block(blk_type)
+            .ok_or_else(|| format!("Unknown S7 block type: {:#x}", blk_type))?;
+        Ok(Self {
+            block_type,
+            number: info.BlkNumber as u16,
+            language: S7BlockLang::from_code(info.BlkLang),
+            flags: info.BlkFlags as u8,
+            mc7_size: info.MC7Size as usize,
+            load_size: info.LoadSize as usize,
+            local_data: info.LocalData as usize,
+            sbb_length: info.SBBLength as usize,
+            checksum: info.CheckSum as u16,
+            version: info.Version as u8,
+            code_date: NaiveDate::parse_from_str(&c_chars_text(&info.CodeDate), "%Y/%m/%d").ok(),
+            intf_date: NaiveDate::parse_from_str(&c_chars_text(&info.IntfDate), "%Y/%m/%d").ok(),
+            author: c_chars_text(&info.Author),
+            family: c_chars_text(&info.Family),
+            header: c_chars_text(&info.Header),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum S7ProtectionLevel {
+    NoProtection,
+    WriteProtected,
+    ReadWriteProtected,
+    Unknown(u16),
+}
+
+impl S7ProtectionLevel {
+    fn from_code(code: u16) -> Self {
+        match code {
+            0 | 1 => S7ProtectionLevel::NoProtection,
+            2 => S7ProtectionLevel::WriteProtected,
+            3 => S7ProtectionLevel::ReadWriteProtected,
+            _ => S7ProtectionLevel::Unknown(code),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum S7ModeSwitch {
+    Undefined,
+    Run,
+    RunP,
+    Stop,
+    MRes,
+}
+
+impl S7ModeSwitch {
+    fn from_code(code: u16) -> Self {
+        match code {
+            1 => S7ModeSwitch::Run,
+            2 => S7ModeSwitch::RunP,
+            3 => S7ModeSwitch::Stop,
+            4 => S7ModeSwitch::MRes,
+            _ => S7ModeSwitch::Undefined,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct S7Protection {
+    pub switch_level: S7ProtectionLevel,
+    pub password_level: S7ProtectionLevel,
+    pub level: S7ProtectionLevel,
+    pub mode_switch: S7ModeSwitch,
+    pub startup_switch: u16,
+}
+
+impl S7Protection {
+    pub fn can_read(&self) -> bool {
+        matches!(
+            self.level,
+            S7ProtectionLevel::NoProtection | S7ProtectionLevel::WriteProtected
+        )
+    }
+
+    pub fn can_write(&self) -> bool {
+        self.level == S7ProtectionLevel::NoProtection
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct S7CpInfo {
+    pub max_pdu_length: usize,
+    pub max_connections: usize,
+    pub max_mpi_rate: usize,
+    pub max_bus_rate: usize,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Client {
     pub fn new() -> Self {
         Self {
             handle: unsafe { Cli_Create() },
+            host: String::new(),
             req_len: 0,
             neg_len: 0,
-            reg: Regex::new(r"^(M|I|Q|(?:DB(\d+)))(W|D|X)(\d+)(?:\.([0-7]))?$").unwrap(),
+            reg: address_regex(),
+            password: None,
+            protection: None,
+            lock: Mutex::new(()),
         }
     }
 
     pub fn connect(&mut self, host: &str, rack: i32, slot: i32) {
         let mut req: c_int = 0;
         let mut neg: c_int = 0;
-        let mut buf = Vec::<u8>::new();
-        buf.resize(4, 0);
+        let mut buf = vec![0u8; 4];
+        self.host = host.to_owned();
         unsafe {
             Cli_ConnectTo(
                 self.handle,
@@ -81,6 +261,125 @@ impl Client {
                 i32::from_le_bytes(buf[0..4].try_into().unwrap())
             );
         }
+
+        if let Some(password) = self.password.clone() {
+            if let Err(err) = self.send_session_password(&password) {
+                error!("Set session password: {}", err);
+            }
+        }
+        self.protection = self.get_protection().ok();
+        info!("Protection: {:?}", self.protection);
+    }
+
+    pub fn get_protection(&self) -> Result<S7Protection, String> {
+        let _guard = self.guard();
+        let mut p = TS7Protection {
+            sch_schal: 0,
+            sch_par: 0,
+            sch_rel: 0,
+            bart_sch: 0,
+            anl_sch: 0,
+        };
+        let res;
+        unsafe {
+            res = Cli_GetProtection(self.handle, &mut p) as i32;
+        }
+        if res == 0 {
+            Ok(S7Protection {
+                switch_level: S7ProtectionLevel::from_code(p.sch_schal),
+                password_level: S7ProtectionLevel::from_code(p.sch_par),
+                level: S7ProtectionLevel::from_code(p.sch_rel),
+                mode_switch: S7ModeSwitch::from_code(p.bart_sch),
+                startup_switch: p.anl_sch,
+            })
+        } else {
+            Err(error_text(res))
+        }
+    }
+
+    pub fn set_session_password(&mut self, password: &str) -> Result<(), String> {
+        if password.len() > 8 {
+            return Err(String::from("S7 password is limited to 8 characters"));
+        }
+        self.send_session_password(password)?;
+        self.password = Some(password.to_owned());
+        self.protection = self.get_protection().ok();
+        Ok(())
+    }
+
+    pub fn clear_session_password(&mut self) -> Result<(), String> {
+        let res;
+        unsafe {
+            res = Cli_ClearSessionPassword(self.handle) as i32;
+        }
+        self.password = None;
+        self.protection = self.get_protection().ok();
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(error_text(res))
+        }
+    }
+
+    fn send_session_password(&self, password: &str) -> Result<(), String> {
+        let password = CString::new(password).map_err(|e| e.to_string())?;
+        let res;
+        unsafe {
+            res = Cli_SetSessionPassword(self.handle, password.as_ptr() as *mut c_char) as i32;
+        }
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(error_text(res))
+        }
+    }
+
+    pub fn get_cp_info(&self) -> Result<S7CpInfo, String> {
+        let _guard = self.guard();
+        let mut info = TS7CpInfo {
+            MaxPduLengt: 0,
+            MaxConnections: 0,
+            MaxMpiRate: 0,
+            MaxBusRate: 0,
+        };
+        let res;
+        unsafe {
+            res = Cli_GetCpInfo(self.handle, &mut info) as i32;
+        }
+        if res == 0 {
+            Ok(S7CpInfo {
+                max_pdu_length: info.MaxPduLengt as usize,
+                max_connections: info.MaxConnections as usize,
+                max_mpi_rate: info.MaxMpiRate as usize,
+                max_bus_rate: info.MaxBusRate as usize,
+            })
+        } else {
+            Err(error_text(res))
+        }
+    }
+
+    fn guard(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn is_busy(&self) -> bool {
+        self.lock.try_lock().is_err()
+    }
+
+    // The protection level is read back after every password change, so a
+    // rejected password still shows up as an insufficient level here
+    fn check_write_access(&self) -> Result<(), String> {
+        match (self.protection, &self.password) {
+            (Some(p), None) if !p.can_write() => Err(format!(
+                "Protection level insufficient: CPU is {:?}, set a session password to write",
+                p.level
+            )),
+            (Some(p), Some(_)) if !p.can_write() => Err(format!(
+                "Protection level insufficient: CPU is {:?}, session password was not accepted",
+                p.level
+            )),
+            _ => Ok(()),
+        }
     }
 
     pub fn close(&mut self) {
@@ -99,61 +398,22 @@ impl Client {
         res == 0 && r == 1
     }
 
-    fn conv_value(&self, buf: &Vec<u8>, datatype: &ETagtype, bit: u8) -> Result<ETagValue, String> {
-        match datatype {
-            ETagtype::INT => Ok(ETagValue::Int(
-                i16::from_be_bytes(buf[0..2].try_into().unwrap()) as i64,
-            )),
-
-            ETagtype::DINT => Ok(ETagValue::Int(
-                i32::from_be_bytes(buf[0..4].try_into().unwrap()) as i64,
-            )),
-
-            ETagtype::REAL => Ok(ETagValue::Real(f32::from_bits(u32::from_be_bytes(
-                buf[0..4].try_into().unwrap(),
-            )) as f64)),
-            ETagtype::BOOL => {
-                let bv = BitVec::from_bytes(&buf);
-                Ok(ETagValue::Bool(bv.get((7 - bit) as usize).unwrap()))
-            }
-        }
+    fn conv_value(&self, buf: &[u8], datatype: &ETagtype, bit: u8) -> Result<ETagValue, String> {
+        decode_value(buf, datatype, bit)
     }
 
     fn conv_buf(
         &self,
         write: ETagValue,
         addr: &S7Address,
+        range: ERangePolicy,
         prefetch_bool_byte: bool,
     ) -> Result<Vec<u8>, String> {
+        let write = addr.datatype.coerce(write, range)?;
         match addr.datatype {
-            ETagtype::INT => {
-                if let ETagValue::Int(v) = write {
-                    let bytes = (v as i16).to_be_bytes();
-                    Ok(vec![bytes[0], bytes[1]])
-                } else {
-                    Err(String::from("Invalid datatype for write value"))
-                }
-            }
-            ETagtype::DINT => {
-                if let ETagValue::Int(v) = write {
-                    let bytes = (v as i32).to_be_bytes();
-                    Ok(vec![bytes[0], bytes[1], bytes[2], bytes[3]])
-                } else {
-                    Err(String::from("Invalid datatype for write value"))
-                }
-            }
-            ETagtype::REAL => {
-                if let ETagValue::Real(v) = write {
-                    let bytes = ((v as f32).to_bits() as u32).to_be_bytes();
-                    Ok(vec![bytes[0], bytes[1], bytes[2], bytes[3]])
-                } else {
-                    Err(String::from("Invalid datatype for write value"))
-                }
-            }
             ETagtype::BOOL => {
                 if let ETagValue::Bool(v) = write {
-                    let mut buf = Vec::<u8>::new();
-                    buf.resize(addr.size as usize, 0);
+                    let mut buf = vec![0u8; addr.size as usize];
                     if prefetch_bool_byte {
                         let res;
                         unsafe {
@@ -172,7 +432,7 @@ impl Client {
                             bv.set((7 - addr.bit) as usize, v);
                             Ok(bv.to_bytes())
                         } else {
-                            Err(String::from(error_text(res)))
+                            Err(error_text(res))
                         }
                     } else {
                         Ok(buf)
@@ -181,52 +441,108 @@ impl Client {
                     Err(String::from("Invalid datatype for write value"))
                 }
             }
+            datatype => encode_value(write, datatype, range),
         }
     }
 
     pub fn conv_address(&self, address: &str, datatype: ETagtype) -> Result<S7Address, String> {
-        if let Some(r) = &self.reg.captures(address) {
-            let area: S7Area = match r.get(1).unwrap().as_str() {
-                "M" => S7Area::MK,
-                "I" => S7Area::PE,
-                "Q" => S7Area::PA,
-                _ => S7Area::DB,
-            };
-            let dbnb: u8 = match area {
-                S7Area::DB => r.get(2).unwrap().as_str().parse().unwrap(),
-                _ => 0,
-            };
-            let dd = r.get(3).unwrap().as_str();
-            let size: u8 = match dd {
-                "W" => 2,
-                "D" => 4,
-                _ => 1,
-            };
-            let start: u8 = r.get(4).unwrap().as_str().parse().unwrap();
-            let bit: u8 = if r.get(5).is_none() {
-                0
-            } else {
-                r.get(5).unwrap().as_str().parse().unwrap()
-            };
-            let addr = S7Address {
-                area,
-                dbnb,
-                size,
-                start,
-                bit,
-                datatype,
-            };
-            match datatype {
-                ETagtype::BOOL if dd == "X" => Ok(addr),
-                ETagtype::INT if dd == "W" => Ok(addr),
-                _ if dd == "D" => Ok(addr),
-                _ => Err(String::from("Invalid S7 addree")),
-            }
+        S7Address::parse(&self.reg, address, datatype)
+    }
+
+    pub fn list_blocks(&self) -> Result<S7BlocksList, String> {
+        let _guard = self.guard();
+        let mut list = TS7BlocksList {
+            OBCount: 0,
+            FBCount: 0,
+            FCCount: 0,
+            SFBCount: 0,
+            SFCCount: 0,
+            DBCount: 0,
+            SDBCount: 0,
+        };
+        let res;
+        unsafe {
+            res = Cli_ListBlocks(self.handle, &mut list) as i32;
+        }
+        if res == 0 {
+            Ok(S7BlocksList {
+                ob: list.OBCount as usize,
+                fb: list.FBCount as usize,
+                fc: list.FCCount as usize,
+                sfb: list.SFBCount as usize,
+                sfc: list.SFCCount as usize,
+                db: list.DBCount as usize,
+                sdb: list.SDBCount as usize,
+            })
         } else {
-            Err(String::from("Invalid S7 addree"))
+            Err(error_text(res))
         }
     }
 
+    pub fn list_blocks_of_type(&self, block_type: S7BlockType) -> Result<Vec<u16>, String> {
+        let _guard = self.guard();
+        let mut list: Box<TS7BlocksOfType> = Box::new([0; 0x2000]);
+        let mut count = list.len() as c_int;
+        let res;
+        unsafe {
+            res = Cli_ListBlocksOfType(self.handle, block_type as c_int, &mut *list, &mut count)
+                as i32;
+        }
+        if res == 0 {
+            Ok(list[0..count as usize].to_vec())
+        } else {
+            Err(error_text(res))
+        }
+    }
+
+    pub fn block_info(&self, block_type: S7BlockType, number: u16) -> Result<S7BlockInfo, String> {
+        let _guard = self.guard();
+        let mut info: TS7BlockInfo = unsafe { std::mem::zeroed() };
+        let res;
+        unsafe {
+            res = Cli_GetAgBlockInfo(self.handle, block_type as c_int, number as c_int, &mut info)
+                as i32;
+        }
+        if res == 0 {
+            S7BlockInfo::from_ts7(&info)
+        } else {
+            Err(error_text(res))
+        }
+    }
+
+    pub fn browse_blocks(&self, block_type: S7BlockType) -> Result<Vec<S7BlockInfo>, String> {
+        self.list_blocks_of_type(block_type)?
+            .iter()
+            .map(|number| self.block_info(block_type, *number))
+            .collect()
+    }
+
+    pub fn validate_tags(&self, tags: &[ETag]) -> Result<Vec<Result<(), String>>, String> {
+        let dbs = self.list_blocks_of_type(S7BlockType::DB)?;
+        let mut db_sizes: HashMap<u16, usize> = HashMap::new();
+        let results = tags
+            .iter()
+            .map(|tag| {
+                let addr = self.conv_address(tag.address.as_str(), tag.datatype)?;
+                if addr.area != S7Area::DB {
+                    return Ok(());
+                }
+                let dbnb = addr.dbnb;
+                let size = if !dbs.contains(&dbnb) {
+                    None
+                } else if let Some(size) = db_sizes.get(&dbnb) {
+                    Some(*size)
+                } else {
+                    let size = self.block_info(S7BlockType::DB, dbnb)?.mc7_size;
+                    db_sizes.insert(dbnb, size);
+                    Some(size)
+                };
+                check_db_range(&tag.address, &addr, size)
+            })
+            .collect();
+        Ok(results)
+    }
+
     fn get_s7data_item(&self, addr: &S7Address, buf: &mut Vec<u8>) -> TS7DataItem {
         TS7DataItem {
             Area: addr.area as c_int,
@@ -252,10 +568,10 @@ impl Drop for Client {
 
 impl ETagRW for Client {
     fn read_tag(&self, tag: &ETag) -> Result<ETagValue, String> {
+        let _guard = self.guard();
         match self.conv_address(tag.address.as_str(), tag.datatype) {
             Ok(addr) => {
-                let mut buf = Vec::<u8>::new();
-                buf.resize(addr.size as usize, 0);
+                let mut buf = vec![0u8; addr.size as usize];
                 let res;
                 unsafe {
                     res = Cli_ReadArea(
@@ -272,13 +588,14 @@ impl ETagRW for Client {
                 if res == 0 {
                     self.conv_value(&buf, &tag.datatype, addr.bit)
                 } else {
-                    Err(String::from(error_text(res)))
+                    Err(error_text(res))
                 }
             }
             Err(err) => Err(err),
         }
     }
     fn read_list(&self, tags: &Vec<ETag>) -> Result<Vec<Result<ETagValue, String>>, String> {
+        let _guard = self.guard();
         let addrs: Vec<_> = tags
             .iter()
             .map(|tag| self.conv_address(tag.address.as_str(), tag.datatype))
@@ -290,8 +607,7 @@ impl ETagRW for Client {
                 .iter()
                 .map(|addr| {
                     let addr_ = addr.as_ref().unwrap();
-                    let mut buf = Vec::<u8>::new();
-                    buf.resize(addr_.size as usize, 0);
+                    let mut buf = vec![0u8; addr_.size as usize];
                     (self.get_s7data_item(addr_, &mut buf), buf, addr_)
                 })
                 .collect();
@@ -309,13 +625,13 @@ impl ETagRW for Client {
             //             if p.Result == 0 {
             //                 self.conv_value(&t.1, &t.2.datatype, t.2.bit)
             //             } else {
-            //                 Err(String::from(error_text(res)))
+            //                 Err(error_text(res))
             //             }
             //         })
             //         .collect();
             //     Ok(results)
             // } else {
-            //     Err(String::from(error_text(res)))
+            //     Err(error_text(res))
             // }
             let cli_results: Vec<_> = ts7_items
                 .chunks_mut(20)
@@ -333,7 +649,7 @@ impl ETagRW for Client {
                 })
                 .collect();
             match cli_results.into_iter().find(|cli_r| cli_r.is_err()) {
-                Some(Err(res)) => Err(String::from(error_text(res))),
+                Some(Err(res)) => Err(error_text(res)),
                 _ => {
                     let results: Vec<_> = items
                         .iter()
@@ -342,7 +658,7 @@ impl ETagRW for Client {
                             if p.Result == 0 {
                                 self.conv_value(&t.1, &t.2.datatype, t.2.bit)
                             } else {
-                                Err(String::from(error_text(p.Result)))
+                                Err(error_text(p.Result))
                             }
                         })
                         .collect();
@@ -352,8 +668,10 @@ impl ETagRW for Client {
         }
     }
     fn write_tag(&self, tag: &ETag, write: ETagValue) -> Result<bool, String> {
+        let _guard = self.guard();
+        self.check_write_access()?;
         match self.conv_address(tag.address.as_str(), tag.datatype) {
-            Ok(addr) => match self.conv_buf(write, &addr, true) {
+            Ok(addr) => match self.conv_buf(write, &addr, tag.range, true) {
                 Ok(buf) => {
                     let res;
                     unsafe {
@@ -371,7 +689,7 @@ impl ETagRW for Client {
                     if res == 0 {
                         Ok(true)
                     } else {
-                        Err(String::from(error_text(res)))
+                        Err(error_text(res))
                     }
                 }
                 Err(err) => Err(err),
@@ -383,6 +701,8 @@ impl ETagRW for Client {
         &self,
         tags: &Vec<(ETag, ETagValue)>,
     ) -> Result<Vec<Result<bool, String>>, String> {
+        let _guard = self.guard();
+        self.check_write_access()?;
         let addrs: Vec<_> = tags
             .iter()
             .map(|t| self.conv_address(t.0.address.as_str(), t.0.datatype))
@@ -397,13 +717,18 @@ impl ETagRW for Client {
                         .unwrap()
                 })
                 .collect();
+            let converted: Vec<_> = addrs
+                .iter()
+                .enumerate()
+                .map(|(i, addr)| self.conv_buf(tags[i].1.clone(), addr, tags[i].0.range, false))
+                .collect();
             let mut items: Vec<_> = addrs
                 .iter()
                 .enumerate()
                 .map(|(i, addr)| {
-                    let mut buf_ = Vec::<u8>::new();
-                    buf_.resize(addr.size as usize, 0);
-                    let mut buf = self.conv_buf(tags[i].1, addr, false).unwrap_or(buf_);
+                    let mut buf = converted[i]
+                        .clone()
+                        .unwrap_or_else(|_| vec![0u8; addr.size as usize]);
                     (self.get_s7data_item(addr, &mut buf), buf)
                 })
                 .collect();
@@ -417,13 +742,12 @@ impl ETagRW for Client {
             for (area_key, area_group) in &addrs
                 .iter()
                 .filter(|addr| addr.datatype.is_bool())
-                .sorted_by(|a, b| Ord::cmp(a, b))
-                .group_by(|t| t.area)
+                .sorted()
+                .chunk_by(|t| t.area)
             {
-                for (dbnb_key, dbnb_group) in &area_group.into_iter().group_by(|t| t.dbnb) {
-                    for (start_key, start_group) in &dbnb_group.into_iter().group_by(|t| t.start) {
-                        let mut buf = Vec::<u8>::new();
-                        buf.resize(1, 0);
+                for (dbnb_key, dbnb_group) in &area_group.into_iter().chunk_by(|t| t.dbnb) {
+                    for (start_key, start_group) in &dbnb_group.into_iter().chunk_by(|t| t.start) {
+                        let mut buf = vec![0u8; 1];
                         let res;
                         unsafe {
                             res = Cli_ReadArea(
@@ -450,12 +774,17 @@ impl ETagRW for Client {
                                 items[index].1[0] = bv.to_bytes()[0];
                             }
                         } else {
-                            return Err(String::from(error_text(res)));
+                            return Err(error_text(res));
                         }
                     }
                 }
             }
-            let mut ts7_items: Vec<TS7DataItem> = items.iter().map(|t| t.0).collect();
+            let mut ts7_items: Vec<TS7DataItem> = items
+                .iter()
+                .zip(converted.iter())
+                .filter(|(_, buf)| buf.is_ok())
+                .map(|(t, _)| t.0)
+                .collect();
             // let res;
             // unsafe {
             //     res = Cli_WriteMultiVars(self.handle, &mut ts7_items[0], ts7_items.len() as c_int)
@@ -469,13 +798,13 @@ impl ETagRW for Client {
             //             if p.Result == 0 {
             //                 Ok(true)
             //             } else {
-            //                 Err(String::from(error_text(res)))
+            //                 Err(error_text(res))
             //             }
             //         })
             //         .collect();
             //     Ok(results)
             // } else {
-            //     Err(String::from(error_text(res)))
+            //     Err(error_text(res))
             // }
             let cli_results: Vec<_> = ts7_items
                 .chunks_mut(20)
@@ -493,17 +822,21 @@ impl ETagRW for Client {
                 })
                 .collect();
             match cli_results.into_iter().find(|cli_r| cli_r.is_err()) {
-                Some(Err(res)) => Err(String::from(error_text(res))),
+                Some(Err(res)) => Err(error_text(res)),
                 _ => {
-                    let results: Vec<_> = items
-                        .iter()
-                        .map(|t| {
-                            let p = t.0;
-                            if p.Result == 0 {
-                                Ok(true)
-                            } else {
-                                Err(String::from(error_text(p.Result)))
+                    let mut written = ts7_items.iter();
+                    let results: Vec<_> = converted
+                        .into_iter()
+                        .map(|buf| match buf {
+                            Ok(_) => {
+                                let p = written.next().unwrap();
+                                if p.Result == 0 {
+                                    Ok(true)
+                                } else {
+                                    Err(error_text(p.Result))
+                                }
                             }
+                            Err(err) => Err(err),
                         })
                         .collect();
                     Ok(results)
@@ -519,9 +852,7 @@ impl ETagRW for Client {
 // }
 
 pub fn error_text(code: i32) -> String {
-    let mut err = Vec::<u8>::new();
-
-    err.resize(1024, 0);
+    let mut err = vec![0u8; 1024];
 
     unsafe {
         Cli_ErrorText(
@@ -539,3 +870,86 @@ pub fn error_text(code: i32) -> String {
 
     err.to_owned()
 }
+
+// `db_size` is None when the DB is not in the PLC's block list
+fn check_db_range(address: &str, addr: &S7Address, db_size: Option<usize>) -> Result<(), String> {
+    if addr.area != S7Area::DB {
+        return Ok(());
+    }
+    match db_size {
+        None => Err(format!("DB{} does not exist", addr.dbnb)),
+        Some(size) if addr.start as usize + addr.size as usize > size => Err(format!(
+            "{} is out of range, DB{} is {} bytes",
+            address, addr.dbnb, size
+        )),
+        Some(_) => Ok(()),
+    }
+}
+
+fn c_chars_text(chars: &[c_char]) -> String {
+    let bytes: Vec<u8> = chars
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).trim().to_owned()
+}
+
+// s7://host?rack=0&slot=1
+pub fn register(registry: &mut Registry) {
+    registry.register_device(
+        "s7",
+        |url| {
+            let host = url
+                .host_str()
+                .ok_or_else(|| format!("Missing host in {}", url))?;
+            let mut client = Client::new();
+            client.connect(host, param(url, "rack", 0)?, param(url, "slot", 1)?);
+            if client.connected() {
+                Ok(client)
+            } else {
+                Err(format!("Connect to {} failed", host))
+            }
+        },
+        Client::connected,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(address: &str, datatype: ETagtype, db_size: Option<usize>) -> Result<(), String> {
+        let addr = S7Address::parse(&address_regex(), address, datatype)?;
+        check_db_range(address, &addr, db_size)
+    }
+
+    #[test]
+    fn checks_db_addresses_against_the_db_size() {
+        assert_eq!(check("DB1D0", ETagtype::REAL, Some(4)), Ok(()));
+        assert_eq!(check("DB1W2", ETagtype::INT, Some(4)), Ok(()));
+        assert_eq!(check("DB1X3.7", ETagtype::BOOL, Some(4)), Ok(()));
+        assert_eq!(
+            check("DB1W3", ETagtype::INT, Some(4)),
+            Err(String::from("DB1W3 is out of range, DB1 is 4 bytes"))
+        );
+        assert_eq!(
+            check("DB1X4.0", ETagtype::BOOL, Some(4)),
+            Err(String::from("DB1X4.0 is out of range, DB1 is 4 bytes"))
+        );
+    }
+
+    #[test]
+    fn missing_dbs_are_reported() {
+        assert_eq!(
+            check("DB7W0", ETagtype::INT, None),
+            Err(String::from("DB7 does not exist"))
+        );
+    }
+
+    #[test]
+    fn other_areas_are_not_checked() {
+        assert_eq!(check("MW100", ETagtype::INT, None), Ok(()));
+        assert_eq!(check("IX0.1", ETagtype::BOOL, Some(0)), Ok(()));
+    }
+}
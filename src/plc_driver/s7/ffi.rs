@@ -0,0 +1,252 @@
+// Declarations for the parts of snap7.h the S7 drivers use. Names follow the
+// header; structs are byte packed like the header (#pragma pack(1)) and
+// S7API is __stdcall on Windows, hence extern "system".
+#![allow(non_camel_case_types, non_snake_case)]
+
+use std::os::raw::{c_char, c_int, c_void};
+
+pub type S7Object = usize;
+pub type byte = u8;
+pub type word = u16;
+pub type longword = u32;
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct TS7DataItem {
+    pub Area: c_int,
+    pub WordLen: c_int,
+    pub Result: c_int,
+    pub DBNumber: c_int,
+    pub Start: c_int,
+    pub Amount: c_int,
+    pub pdata: *mut c_void,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct TS7BlocksList {
+    pub OBCount: c_int,
+    pub FBCount: c_int,
+    pub FCCount: c_int,
+    pub SFBCount: c_int,
+    pub SFCCount: c_int,
+    pub DBCount: c_int,
+    pub SDBCount: c_int,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct TS7BlockInfo {
+    pub BlkType: c_int,
+    pub BlkNumber: c_int,
+    pub BlkLang: c_int,
+    pub BlkFlags: c_int,
+    pub MC7Size: c_int,
+    pub LoadSize: c_int,
+    pub LocalData: c_int,
+    pub SBBLength: c_int,
+    pub CheckSum: c_int,
+    pub Version: c_int,
+    pub CodeDate: [c_char; 11],
+    pub IntfDate: [c_char; 11],
+    pub Author: [c_char; 9],
+    pub Family: [c_char; 9],
+    pub Header: [c_char; 9],
+}
+
+pub type TS7BlocksOfType = [word; 0x2000];
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct TS7CpInfo {
+    pub MaxPduLengt: c_int,
+    pub MaxConnections: c_int,
+    pub MaxMpiRate: c_int,
+    pub MaxBusRate: c_int,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct SZL_HEADER {
+    pub LENTHDR: word,
+    pub N_DR: word,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct TS7SZL {
+    pub Header: SZL_HEADER,
+    pub Data: [byte; 0x4000 - 4],
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct TS7SZLList {
+    pub Header: SZL_HEADER,
+    pub List: [word; 0x2000 - 2],
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct TS7Protection {
+    pub sch_schal: word,
+    pub sch_par: word,
+    pub sch_rel: word,
+    pub bart_sch: word,
+    pub anl_sch: word,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct TSrvEvent {
+    pub EvtTime: i64,
+    pub EvtSender: c_int,
+    pub EvtCode: longword,
+    pub EvtRetCode: word,
+    pub EvtParam1: word,
+    pub EvtParam2: word,
+    pub EvtParam3: word,
+    pub EvtParam4: word,
+}
+
+pub type PSrvEvent = *mut TSrvEvent;
+
+pub type pfn_SrvCallBack =
+    Option<unsafe extern "system" fn(usrPtr: *mut c_void, PEvent: PSrvEvent, Size: c_int)>;
+
+#[link(name = "snap7")]
+extern "system" {
+    pub fn Cli_Create() -> S7Object;
+    pub fn Cli_Destroy(Client: *mut S7Object);
+    pub fn Cli_ConnectTo(
+        Client: S7Object,
+        Address: *const c_char,
+        Rack: c_int,
+        Slot: c_int,
+    ) -> c_int;
+    pub fn Cli_Disconnect(Client: S7Object) -> c_int;
+    pub fn Cli_GetParam(Client: S7Object, ParamNumber: c_int, pValue: *mut c_void) -> c_int;
+    pub fn Cli_ReadArea(
+        Client: S7Object,
+        Area: c_int,
+        DBNumber: c_int,
+        Start: c_int,
+        Amount: c_int,
+        WordLen: c_int,
+        pUsrData: *mut c_void,
+    ) -> c_int;
+    pub fn Cli_WriteArea(
+        Client: S7Object,
+        Area: c_int,
+        DBNumber: c_int,
+        Start: c_int,
+        Amount: c_int,
+        WordLen: c_int,
+        pUsrData: *mut c_void,
+    ) -> c_int;
+    pub fn Cli_ReadMultiVars(Client: S7Object, Item: *mut TS7DataItem, ItemsCount: c_int) -> c_int;
+    pub fn Cli_WriteMultiVars(Client: S7Object, Item: *mut TS7DataItem, ItemsCount: c_int)
+        -> c_int;
+    pub fn Cli_ListBlocks(Client: S7Object, pUsrData: *mut TS7BlocksList) -> c_int;
+    pub fn Cli_GetAgBlockInfo(
+        Client: S7Object,
+        BlockType: c_int,
+        BlockNum: c_int,
+        pUsrData: *mut TS7BlockInfo,
+    ) -> c_int;
+    pub fn Cli_ListBlocksOfType(
+        Client: S7Object,
+        BlockType: c_int,
+        pUsrData: *mut TS7BlocksOfType,
+        ItemsCount: *mut c_int,
+    ) -> c_int;
+    pub fn Cli_DBGet(
+        Client: S7Object,
+        DBNumber: c_int,
+        pUsrData: *mut c_void,
+        Size: *mut c_int,
+    ) -> c_int;
+    pub fn Cli_GetCpInfo(Client: S7Object, pUsrData: *mut TS7CpInfo) -> c_int;
+    pub fn Cli_ReadSZL(
+        Client: S7Object,
+        ID: c_int,
+        Index: c_int,
+        pUsrData: *mut TS7SZL,
+        Size: *mut c_int,
+    ) -> c_int;
+    pub fn Cli_ReadSZLList(
+        Client: S7Object,
+        pUsrData: *mut TS7SZLList,
+        ItemsCount: *mut c_int,
+    ) -> c_int;
+    pub fn Cli_GetProtection(Client: S7Object, pUsrData: *mut TS7Protection) -> c_int;
+    pub fn Cli_SetSessionPassword(Client: S7Object, Password: *mut c_char) -> c_int;
+    pub fn Cli_ClearSessionPassword(Client: S7Object) -> c_int;
+    pub fn Cli_GetPduLength(
+        Client: S7Object,
+        Requested: *mut c_int,
+        Negotiated: *mut c_int,
+    ) -> c_int;
+    pub fn Cli_ErrorText(Error: c_int, Text: *mut c_char, TextLen: c_int) -> c_int;
+    pub fn Cli_GetConnected(Client: S7Object, Connected: *mut c_int) -> c_int;
+
+    pub fn Srv_Create() -> S7Object;
+    pub fn Srv_Destroy(Server: *mut S7Object);
+    pub fn Srv_StartTo(Server: S7Object, Address: *const c_char) -> c_int;
+    pub fn Srv_Stop(Server: S7Object) -> c_int;
+    pub fn Srv_RegisterArea(
+        Server: S7Object,
+        AreaCode: c_int,
+        Index: word,
+        pUsrData: *mut c_void,
+        Size: c_int,
+    ) -> c_int;
+    pub fn Srv_LockArea(Server: S7Object, AreaCode: c_int, Index: word) -> c_int;
+    pub fn Srv_UnlockArea(Server: S7Object, AreaCode: c_int, Index: word) -> c_int;
+    pub fn Srv_GetStatus(
+        Server: S7Object,
+        ServerStatus: *mut c_int,
+        CpuStatus: *mut c_int,
+        ClientsCount: *mut c_int,
+    ) -> c_int;
+    pub fn Srv_SetCpuStatus(Server: S7Object, CpuStatus: c_int) -> c_int;
+    pub fn Srv_SetEventsCallback(
+        Server: S7Object,
+        pCallback: pfn_SrvCallBack,
+        usrPtr: *mut c_void,
+    ) -> c_int;
+    pub fn Srv_ErrorText(Error: c_int, Text: *mut c_char, TextLen: c_int) -> c_int;
+
+    pub fn Par_Create(Active: c_int) -> S7Object;
+    pub fn Par_Destroy(Partner: *mut S7Object);
+    pub fn Par_StartTo(
+        Partner: S7Object,
+        LocalAddress: *const c_char,
+        RemoteAddress: *const c_char,
+        LocTsap: word,
+        RemTsap: word,
+    ) -> c_int;
+    pub fn Par_Stop(Partner: S7Object) -> c_int;
+    pub fn Par_BSend(
+        Partner: S7Object,
+        R_ID: longword,
+        pUsrData: *mut c_void,
+        Size: c_int,
+    ) -> c_int;
+    pub fn Par_BRecv(
+        Partner: S7Object,
+        R_ID: *mut longword,
+        pData: *mut c_void,
+        Size: *mut c_int,
+        Timeout: longword,
+    ) -> c_int;
+    pub fn Par_GetStats(
+        Partner: S7Object,
+        BytesSent: *mut longword,
+        BytesRecv: *mut longword,
+        SendErrors: *mut longword,
+        RecvErrors: *mut longword,
+    ) -> c_int;
+    pub fn Par_GetStatus(Partner: S7Object, Status: *mut c_int) -> c_int;
+    pub fn Par_ErrorText(Error: c_int, Text: *mut c_char, TextLen: c_int) -> c_int;
+}
@@ -0,0 +1,402 @@
+use super::ffi::*;
+use crate::plc_driver::s7_address::{decode_value, encode_value};
+use crate::plc_driver::{EField, ERangePolicy, ETagValue, ETagtype};
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::os::raw::{c_int, c_void};
+use std::time::Duration;
+
+const MAX_BSEND_SIZE: usize = 0x10000;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum S7PartnerStatus {
+    Stopped,
+    Connecting,
+    Waiting,
+    Linked,
+    Sending,
+    Receiving,
+    BindError,
+    Unknown(i32),
+}
+
+impl S7PartnerStatus {
+    fn from_code(code: c_int) -> Self {
+        match code {
+            0 => S7PartnerStatus::Stopped,
+            1 => S7PartnerStatus::Connecting,
+            2 => S7PartnerStatus::Waiting,
+            3 => S7PartnerStatus::Linked,
+            4 => S7PartnerStatus::Sending,
+            5 => S7PartnerStatus::Receiving,
+            6 => S7PartnerStatus::BindError,
+            c => S7PartnerStatus::Unknown(c),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct S7PartnerStats {
+    pub bytes_sent: u32,
+    pub bytes_recv: u32,
+    pub send_errors: u32,
+    pub recv_errors: u32,
+}
+
+// One BSEND/BRECV record as received from the PLC
+#[derive(Debug, Clone)]
+pub struct S7Record {
+    pub r_id: u32,
+    pub data: Vec<u8>,
+}
+
+// Field of a UDT laid out like the PLC program declares it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UdtField {
+    pub name: String,
+    pub offset: usize,
+    #[serde(default)]
+    pub bit: u8,
+    pub datatype: ETagtype,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Udt {
+    pub name: String,
+    pub fields: Vec<UdtField>,
+}
+
+impl Udt {
+    pub fn size(&self) -> usize {
+        self.fields
+            .iter()
+            .map(|f| f.offset + field_size(&f.datatype))
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn decode(&self, data: &[u8]) -> Result<ETagValue, String> {
+        self.fields
+            .iter()
+            .map(|f| {
+                let end = f.offset + field_size(&f.datatype);
+                if end > data.len() {
+                    return Err(format!(
+                        "{}.{}: record too short ({} bytes)",
+                        self.name,
+                        f.name,
+                        data.len()
+                    ));
+                }
+                decode_value(&data[f.offset..end], &f.datatype, f.bit).map(|value| EField {
+                    name: f.name.clone(),
+                    value,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .map(ETagValue::Struct)
+    }
+
+    // Fields missing from `value` and padding are sent as zero
+    pub fn encode(&self, value: &ETagValue) -> Result<Vec<u8>, String> {
+        let fields = match value {
+            ETagValue::Struct(fields) => fields,
+            other => {
+                return Err(format!(
+                    "{} needs a Struct, got {}",
+                    self.name,
+                    other.type_name()
+                ))
+            }
+        };
+        let mut data = vec![0u8; self.size()];
+        for EField { name, value } in fields {
+            let f = self
+                .fields
+                .iter()
+                .find(|f| &f.name == name)
+                .ok_or_else(|| format!("{} has no field {}", self.name, name))?;
+            let buf = encode_value(value.clone(), f.datatype, ERangePolicy::Reject)
+                .map_err(|err| format!("{}.{}: {}", self.name, f.name, err))?;
+            if f.datatype.is_bool() {
+                let mask = 1u8 << f.bit;
+                if buf[0] != 0 {
+                    data[f.offset] |= mask;
+                } else {
+                    data[f.offset] &= !mask;
+                }
+            } else {
+                data[f.offset..f.offset + buf.len()].copy_from_slice(&buf);
+            }
+        }
+        Ok(data)
+    }
+}
+
+fn field_size(datatype: &ETagtype) -> usize {
+    match datatype {
+        ETagtype::BOOL => 1,
+        ETagtype::INT => 2,
+        ETagtype::DINT | ETagtype::REAL => 4,
+    }
+}
+
+pub struct Partner {
+    handle: S7Object,
+    active: bool,
+}
+
+unsafe impl Send for Partner {}
+
+impl Partner {
+    // An active partner opens the connection, a passive one waits for the PLC
+    pub fn new(active: bool) -> Self {
+        Self {
+            handle: unsafe { Par_Create(active as c_int) },
+            active,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn start(
+        &mut self,
+        local: &str,
+        remote: &str,
+        local_tsap: u16,
+        remote_tsap: u16,
+    ) -> Result<(), String> {
+        let local = CString::new(local).map_err(|e| e.to_string())?;
+        let remote = CString::new(remote).map_err(|e| e.to_string())?;
+        let res;
+        unsafe {
+            res = Par_StartTo(
+                self.handle,
+                local.as_ptr(),
+                remote.as_ptr(),
+                local_tsap,
+                remote_tsap,
+            ) as i32;
+        }
+        check(res)
+    }
+
+    pub fn stop(&mut self) {
+        unsafe {
+            Par_Stop(self.handle);
+        }
+    }
+
+    pub fn status(&self) -> S7PartnerStatus {
+        let mut status: c_int = 0;
+        unsafe {
+            Par_GetStatus(self.handle, &mut status);
+        }
+        S7PartnerStatus::from_code(status)
+    }
+
+    pub fn linked(&self) -> bool {
+        matches!(
+            self.status(),
+            S7PartnerStatus::Linked | S7PartnerStatus::Sending | S7PartnerStatus::Receiving
+        )
+    }
+
+    pub fn stats(&self) -> S7PartnerStats {
+        let mut stats = S7PartnerStats {
+            bytes_sent: 0,
+            bytes_recv: 0,
+            send_errors: 0,
+            recv_errors: 0,
+        };
+        unsafe {
+            Par_GetStats(
+                self.handle,
+                &mut stats.bytes_sent,
+                &mut stats.bytes_recv,
+                &mut stats.send_errors,
+                &mut stats.recv_errors,
+            );
+        }
+        stats
+    }
+
+    pub fn bsend(&self, r_id: u32, data: &[u8]) -> Result<(), String> {
+        if data.len() > MAX_BSEND_SIZE {
+            return Err(format!("BSEND record too large: {} bytes", data.len()));
+        }
+        let mut buf = data.to_vec();
+        let res;
+        unsafe {
+            res = Par_BSend(
+                self.handle,
+                r_id,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len() as c_int,
+            ) as i32;
+        }
+        check(res)
+    }
+
+    pub fn bsend_udt(&self, r_id: u32, udt: &Udt, value: &ETagValue) -> Result<(), String> {
+        self.bsend(r_id, &udt.encode(value)?)
+    }
+
+    // Waits up to `timeout` for the next record pushed by the PLC
+    pub fn brecv(&self, timeout: Duration) -> Result<S7Record, String> {
+        let mut buf = vec![0u8; MAX_BSEND_SIZE];
+        let mut r_id: longword = 0;
+        let mut size: c_int = 0;
+        let res;
+        unsafe {
+            res = Par_BRecv(
+                self.handle,
+                &mut r_id,
+                buf.as_mut_ptr() as *mut c_void,
+                &mut size,
+                timeout.as_millis() as longword,
+            ) as i32;
+        }
+        check(res)?;
+        buf.truncate(size.max(0) as usize);
+        Ok(S7Record { r_id, data: buf })
+    }
+
+    pub fn brecv_udt(&self, timeout: Duration, udt: &Udt) -> Result<(u32, ETagValue), String> {
+        let record = self.brecv(timeout)?;
+        Ok((record.r_id, udt.decode(&record.data)?))
+    }
+}
+
+impl Drop for Partner {
+    fn drop(&mut self) {
+        self.stop();
+
+        unsafe {
+            Par_Destroy(&mut self.handle);
+        }
+    }
+}
+
+fn check(res: i32) -> Result<(), String> {
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(partner_error_text(res))
+    }
+}
+
+pub fn partner_error_text(code: i32) -> String {
+    let mut err = vec![0u8; 1024];
+    unsafe {
+        Par_ErrorText(
+            code as c_int,
+            err.as_mut_ptr() as *mut std::os::raw::c_char,
+            err.len() as c_int,
+        );
+    }
+    if let Some(i) = err.iter().position(|&r| r == 0) {
+        err.truncate(i);
+    }
+    String::from_utf8_lossy(&err).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, offset: usize, bit: u8, datatype: ETagtype) -> UdtField {
+        UdtField {
+            name: name.to_owned(),
+            offset,
+            bit,
+            datatype,
+        }
+    }
+
+    // Two bools packed in byte 0, a pad byte before the INT and a pad word
+    // before the REAL, as the PLC aligns them
+    fn udt() -> Udt {
+        Udt {
+            name: String::from("Station"),
+            fields: vec![
+                field("running", 0, 0, ETagtype::BOOL),
+                field("fault", 0, 3, ETagtype::BOOL),
+                field("count", 2, 0, ETagtype::INT),
+                field("total", 4, 0, ETagtype::DINT),
+                field("speed", 10, 0, ETagtype::REAL),
+            ],
+        }
+    }
+
+    fn record() -> Vec<u8> {
+        let mut data = vec![0b0000_1001, 0x00];
+        data.extend_from_slice(&(-2i16).to_be_bytes());
+        data.extend_from_slice(&100_000i32.to_be_bytes());
+        data.extend_from_slice(&[0x00, 0x00]);
+        data.extend_from_slice(&1.5f32.to_be_bytes());
+        data
+    }
+
+    fn value() -> ETagValue {
+        ETagValue::Struct(vec![
+            EField::new("running", true),
+            EField::new("fault", true),
+            EField::new("count", -2i64),
+            EField::new("total", 100_000i64),
+            EField::new("speed", 1.5),
+        ])
+    }
+
+    #[test]
+    fn decodes_fields_in_declaration_order() {
+        assert_eq!(udt().size(), 14);
+        assert_eq!(udt().decode(&record()), Ok(value()));
+    }
+
+    #[test]
+    fn round_trips_through_the_record() {
+        let udt = udt();
+        assert_eq!(udt.encode(&value()), Ok(record()));
+        assert_eq!(udt.decode(&udt.encode(&value()).unwrap()), Ok(value()));
+        assert_eq!(udt.encode(&udt.decode(&record()).unwrap()), Ok(record()));
+    }
+
+    #[test]
+    fn packs_bools_without_touching_their_neighbours() {
+        let udt = udt();
+        let only_fault = ETagValue::Struct(vec![
+            EField::new("running", false),
+            EField::new("fault", true),
+        ]);
+        let data = udt.encode(&only_fault).unwrap();
+        assert_eq!(data[0], 0b0000_1000);
+        assert!(data[1..].iter().all(|b| *b == 0));
+        let decoded = udt.decode(&data).unwrap();
+        assert_eq!(decoded.field("running"), Some(&ETagValue::Bool(false)));
+        assert_eq!(decoded.field("fault"), Some(&ETagValue::Bool(true)));
+    }
+
+    #[test]
+    fn rejects_what_does_not_fit_the_udt() {
+        let udt = udt();
+        assert_eq!(
+            udt.decode(&record()[..13]),
+            Err(String::from("Station.speed: record too short (13 bytes)"))
+        );
+        assert_eq!(
+            udt.encode(&ETagValue::Struct(vec![EField::new("level", 1i64)])),
+            Err(String::from("Station has no field level"))
+        );
+        assert_eq!(
+            udt.encode(&ETagValue::Int(1)),
+            Err(String::from("Station needs a Struct, got Int"))
+        );
+        assert!(udt
+            .encode(&ETagValue::Struct(vec![EField::new("count", 40_000i64)]))
+            .unwrap_err()
+            .starts_with("Station.count: "));
+    }
+}
@@ -0,0 +1,141 @@
+use super::Client;
+use crate::plc_driver::{ETag, ETagRW, ETagValue};
+use log::{info, warn};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Several connections to the same PLC, concurrent calls are spread over
+// the idle ones and fall back to round robin when all of them are busy.
+pub struct Pool {
+    clients: Vec<Client>,
+    next: AtomicUsize,
+}
+
+impl Pool {
+    pub fn connect(host: &str, rack: i32, slot: i32, size: usize) -> Result<Self, String> {
+        let mut first = Client::new();
+        first.connect(host, rack, slot);
+        if !first.connected() {
+            return Err(format!("Cannot connect to {}", host));
+        }
+        let limit = match first.get_cp_info() {
+            Ok(info) if info.max_connections > 0 => size.min(info.max_connections),
+            _ => size,
+        };
+        let mut clients = vec![first];
+        while clients.len() < limit {
+            let mut client = Client::new();
+            client.connect(host, rack, slot);
+            if !client.connected() {
+                warn!(
+                    "{} refused connection {}, pool limited to {}",
+                    host,
+                    clients.len() + 1,
+                    clients.len()
+                );
+                break;
+            }
+            clients.push(client);
+        }
+        info!("Pool to {}: {} connections", host, clients.len());
+        Ok(Self {
+            clients,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn clients(&self) -> &Vec<Client> {
+        &self.clients
+    }
+
+    pub fn set_session_password(&mut self, password: &str) -> Result<(), String> {
+        self.clients
+            .iter_mut()
+            .try_for_each(|client| client.set_session_password(password))
+    }
+
+    pub fn get(&self) -> &Client {
+        pick(&self.clients, &self.next, Client::is_busy)
+    }
+}
+
+// Starts at the next client in turn and takes the first idle one from there,
+// or the one in turn when all of them are busy
+fn pick<'a, C>(clients: &'a [C], next: &AtomicUsize, is_busy: impl Fn(&C) -> bool) -> &'a C {
+    let start = next.fetch_add(1, Ordering::Relaxed) % clients.len();
+    (0..clients.len())
+        .map(|i| &clients[(start + i) % clients.len()])
+        .find(|client| !is_busy(client))
+        .unwrap_or(&clients[start])
+}
+
+impl ETagRW for Pool {
+    fn read_tag(&self, tag: &ETag) -> Result<ETagValue, String> {
+        self.get().read_tag(tag)
+    }
+    fn read_list(&self, tags: &Vec<ETag>) -> Result<Vec<Result<ETagValue, String>>, String> {
+        self.get().read_list(tags)
+    }
+    fn write_tag(&self, tag: &ETag, write: ETagValue) -> Result<bool, String> {
+        self.get().write_tag(tag, write)
+    }
+    fn write_list(
+        &self,
+        tags: &Vec<(ETag, ETagValue)>,
+    ) -> Result<Vec<Result<bool, String>>, String> {
+        self.get().write_list(tags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Stands in for Client, busy while its lock is held
+    fn busy(client: &Mutex<()>) -> bool {
+        client.try_lock().is_err()
+    }
+
+    fn index(clients: &[Mutex<()>], client: &Mutex<()>) -> usize {
+        clients
+            .iter()
+            .position(|c| std::ptr::eq(c, client))
+            .unwrap()
+    }
+
+    #[test]
+    fn idle_clients_take_turns() {
+        let clients: Vec<Mutex<()>> = (0..3).map(|_| Mutex::new(())).collect();
+        let next = AtomicUsize::new(0);
+        let picked: Vec<_> = (0..6)
+            .map(|_| index(&clients, pick(&clients, &next, busy)))
+            .collect();
+        assert_eq!(picked, vec![0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn busy_clients_are_skipped() {
+        let clients: Vec<Mutex<()>> = (0..3).map(|_| Mutex::new(())).collect();
+        let next = AtomicUsize::new(0);
+        let _first = clients[0].lock().unwrap();
+        let _second = clients[1].lock().unwrap();
+        assert_eq!(index(&clients, pick(&clients, &next, busy)), 2);
+        assert_eq!(index(&clients, pick(&clients, &next, busy)), 2);
+        assert_eq!(index(&clients, pick(&clients, &next, busy)), 2);
+    }
+
+    #[test]
+    fn all_busy_falls_back_to_round_robin() {
+        let clients: Vec<Mutex<()>> = (0..3).map(|_| Mutex::new(())).collect();
+        let next = AtomicUsize::new(1);
+        let _guards: Vec<_> = clients.iter().map(|c| c.lock().unwrap()).collect();
+        let picked: Vec<_> = (0..4)
+            .map(|_| index(&clients, pick(&clients, &next, busy)))
+            .collect();
+        assert_eq!(picked, vec![1, 2, 0, 1]);
+    }
+}
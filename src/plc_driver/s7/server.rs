@@ -0,0 +1,605 @@
+use super::ffi::*;
+use super::{S7Address, S7Area};
+use crate::plc_driver::s7_address::{address_regex, decode_value, encode_value};
+use crate::plc_driver::{ETag, ETagRW, ETagValue};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::os::raw::{c_int, c_void};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const SRV_AREA_PE: c_int = 0;
+const SRV_AREA_PA: c_int = 1;
+const SRV_AREA_MK: c_int = 2;
+const SRV_AREA_DB: c_int = 5;
+const EVC_DATA_WRITE: u32 = 0x0004_0000;
+const S7_CPU_STATUS_RUN: c_int = 0x08;
+const S7_CPU_STATUS_STOP: c_int = 0x04;
+
+// A gateway tag published at `address` of the virtual PLC
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerMapping {
+    pub address: String,
+    pub tag: ETag,
+}
+
+// A change of an area, copied when it happened. `written` is set for HMI
+// writes and clear for values the gateway published.
+#[derive(Debug, Clone)]
+struct AreaWrite {
+    area: S7Area,
+    index: u16,
+    start: usize,
+    data: Vec<u8>,
+    written: bool,
+}
+
+struct Area {
+    area: S7Area,
+    index: u16,
+    buf: *mut u8,
+    len: usize,
+}
+
+// Shared with the snap7 event callback, boxed so its address stays put
+struct Shared {
+    handle: S7Object,
+    areas: Vec<Area>,
+    events: Mutex<Sender<AreaWrite>>,
+}
+
+type ForwardedWrites = Vec<(ETag, Result<bool, String>)>;
+
+pub struct Server {
+    shared: Box<Shared>,
+    mappings: Vec<(ServerMapping, S7Address)>,
+    // Area contents as of the last change taken from `receiver`, to decode
+    // values and tell which bits of a byte an HMI write changed
+    image: Mutex<BTreeMap<(S7Area, u16), Vec<u8>>>,
+    receiver: Mutex<Receiver<AreaWrite>>,
+}
+
+// Area buffers are shared with the snap7 worker threads and are only
+// touched between Srv_LockArea and Srv_UnlockArea.
+unsafe impl Send for Server {}
+unsafe impl Sync for Server {}
+
+fn srv_area_code(area: S7Area) -> c_int {
+    match area {
+        S7Area::PE => SRV_AREA_PE,
+        S7Area::PA => SRV_AREA_PA,
+        S7Area::MK => SRV_AREA_MK,
+        S7Area::DB => SRV_AREA_DB,
+    }
+}
+
+fn event_area(code: u16) -> Option<S7Area> {
+    match code as c_int {
+        0x81 | SRV_AREA_PE => Some(S7Area::PE),
+        0x82 | SRV_AREA_PA => Some(S7Area::PA),
+        0x83 | SRV_AREA_MK => Some(S7Area::MK),
+        0x84 | SRV_AREA_DB => Some(S7Area::DB),
+        _ => None,
+    }
+}
+
+// Copies the written bytes right away, a later publish may overwrite them
+// before the write is forwarded
+unsafe extern "system" fn on_event(usr: *mut c_void, event: PSrvEvent, _size: c_int) {
+    let event = *event;
+    if event.EvtCode != EVC_DATA_WRITE || event.EvtRetCode != 0 {
+        return;
+    }
+    if let Some(area) = event_area(event.EvtParam1) {
+        let shared = &*(usr as *const Shared);
+        let index = if area == S7Area::DB {
+            event.EvtParam2
+        } else {
+            0
+        };
+        let start = event.EvtParam3 as usize;
+        let mut data = Vec::new();
+        let copied = shared.with_area(area, index, start, event.EvtParam4 as usize, |d| {
+            data.extend_from_slice(d)
+        });
+        match copied {
+            Ok(()) => shared.send(AreaWrite {
+                area,
+                index,
+                start,
+                data,
+                written: true,
+            }),
+            Err(err) => warn!("Write to {:?} {}: {}", area, index, err),
+        }
+    }
+}
+
+impl Shared {
+    fn with_area<F: FnOnce(&mut [u8])>(
+        &self,
+        area: S7Area,
+        index: u16,
+        start: usize,
+        size: usize,
+        f: F,
+    ) -> Result<(), String> {
+        let area = self
+            .areas
+            .iter()
+            .find(|a| a.area == area && a.index == index)
+            .ok_or_else(|| String::from("Area not registered"))?;
+        let end = start + size;
+        if end > area.len {
+            return Err(String::from("Address out of range"));
+        }
+        unsafe {
+            Srv_LockArea(self.handle, srv_area_code(area.area), area.index);
+            f(&mut std::slice::from_raw_parts_mut(area.buf, area.len)[start..end]);
+            Srv_UnlockArea(self.handle, srv_area_code(area.area), area.index);
+        }
+        Ok(())
+    }
+
+    fn send(&self, write: AreaWrite) {
+        let _ = self.events.lock().unwrap().send(write);
+    }
+}
+
+impl Server {
+    pub fn new(mappings: Vec<ServerMapping>) -> Result<Self, String> {
+        let reg = address_regex();
+        let mappings = mappings
+            .into_iter()
+            .map(|m| match S7Address::parse(&reg, m.address.as_str(), m.tag.datatype) {
+                Ok(addr) => Ok((m, addr)),
+                Err(err) => Err(format!("{}: {}", err, m.address)),
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let mut sizes: BTreeMap<(S7Area, u16), usize> = BTreeMap::new();
+        for (_, addr) in &mappings {
+            let end = addr.start as usize + addr.size as usize;
+            let size = sizes.entry((addr.area, addr.dbnb)).or_insert(0);
+            *size = (*size).max(end);
+        }
+
+        // Areas start zeroed
+        let image = sizes
+            .iter()
+            .map(|(key, len)| (*key, vec![0u8; *len]))
+            .collect();
+
+        let (sender, receiver) = channel();
+        let mut shared = Box::new(Shared {
+            handle: unsafe { Srv_Create() },
+            areas: Vec::new(),
+            events: Mutex::new(sender),
+        });
+        for ((area, index), len) in sizes {
+            let buf = Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8;
+            shared.areas.push(Area {
+                area,
+                index,
+                buf,
+                len,
+            });
+        }
+        let server = Self {
+            shared,
+            mappings,
+            image: Mutex::new(image),
+            receiver: Mutex::new(receiver),
+        };
+        for area in &server.shared.areas {
+            let res = unsafe {
+                Srv_RegisterArea(
+                    server.shared.handle,
+                    srv_area_code(area.area),
+                    area.index,
+                    area.buf as *mut c_void,
+                    area.len as c_int,
+                )
+            };
+            if res != 0 {
+                return Err(server_error_text(res));
+            }
+        }
+
+        let res = unsafe {
+            Srv_SetEventsCallback(
+                server.shared.handle,
+                Some(on_event),
+                &*server.shared as *const Shared as *mut c_void,
+            )
+        };
+        if res == 0 {
+            Ok(server)
+        } else {
+            Err(server_error_text(res))
+        }
+    }
+
+    pub fn start(&mut self, address: &str) -> Result<(), String> {
+        let address = CString::new(address).map_err(|e| e.to_string())?;
+        let res = unsafe { Srv_StartTo(self.shared.handle, address.as_ptr()) };
+        if res == 0 {
+            info!("S7 server started with {} areas", self.shared.areas.len());
+            Ok(())
+        } else {
+            Err(server_error_text(res))
+        }
+    }
+
+    pub fn stop(&mut self) {
+        unsafe {
+            Srv_Stop(self.shared.handle);
+        }
+    }
+
+    pub fn set_cpu_running(&self, run: bool) {
+        let status = if run {
+            S7_CPU_STATUS_RUN
+        } else {
+            S7_CPU_STATUS_STOP
+        };
+        unsafe {
+            Srv_SetCpuStatus(self.shared.handle, status);
+        }
+    }
+
+    pub fn clients_count(&self) -> usize {
+        let mut server_status: c_int = 0;
+        let mut cpu_status: c_int = 0;
+        let mut clients: c_int = 0;
+        unsafe {
+            Srv_GetStatus(
+                self.shared.handle,
+                &mut server_status,
+                &mut cpu_status,
+                &mut clients,
+            );
+        }
+        clients as usize
+    }
+
+    pub fn mappings(&self) -> Vec<&ServerMapping> {
+        self.mappings.iter().map(|m| &m.0).collect()
+    }
+
+    pub fn publish(&self, name: &str, value: ETagValue) -> Result<(), String> {
+        let (mapping, addr) = self
+            .mappings
+            .iter()
+            .find(|m| m.0.tag.name == name)
+            .ok_or_else(|| format!("{} is not published", name))?;
+        let mut buf = encode_value(value, addr.datatype, mapping.tag.range)?;
+        let start = addr.start as usize;
+        self.shared
+            .with_area(addr.area, addr.dbnb, start, addr.size as usize, |data| {
+                if addr.datatype.is_bool() {
+                    let mask = 1u8 << addr.bit;
+                    buf[0] = if buf[0] != 0 {
+                        data[0] | mask
+                    } else {
+                        data[0] & !mask
+                    };
+                }
+                data.copy_from_slice(&buf);
+            })?;
+        // Queued behind the HMI writes that came before it
+        self.shared.send(AreaWrite {
+            area: addr.area,
+            index: addr.dbnb,
+            start,
+            data: buf,
+            written: false,
+        });
+        Ok(())
+    }
+
+    // Refreshes every published tag from the device that owns it
+    pub fn publish_from<D: ETagRW + ?Sized>(&self, driver: &D) -> Result<(), String> {
+        let tags: Vec<ETag> = self.mappings.iter().map(|m| m.0.tag.clone()).collect();
+        let values = driver.read_list(&tags)?;
+        for (tag, value) in tags.iter().zip(values) {
+            match value {
+                Ok(value) => self.publish(&tag.name, value)?,
+                Err(err) => warn!("{}: {}", tag.name, err),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn writes(&self) -> Vec<(ETag, ETagValue)> {
+        let receiver = self.receiver.lock().unwrap();
+        let events: Vec<AreaWrite> = receiver.try_iter().collect();
+        decode_writes(&self.mappings, &mut self.image.lock().unwrap(), &events)
+    }
+
+    pub fn wait_writes(&self, timeout: Duration) -> Vec<(ETag, ETagValue)> {
+        let receiver = self.receiver.lock().unwrap();
+        let deadline = Instant::now() + timeout;
+        let mut image = self.image.lock().unwrap();
+        // Published values alone do not end the wait
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let mut events = match receiver.recv_timeout(timeout) {
+                Ok(event) => vec![event],
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {
+                    return Vec::new()
+                }
+            };
+            events.extend(receiver.try_iter());
+            let writes = decode_writes(&self.mappings, &mut image, &events);
+            if !writes.is_empty() {
+                return writes;
+            }
+        }
+    }
+
+    // Forwards HMI writes to the device and returns what was written
+    pub fn forward_writes<D: ETagRW + ?Sized>(
+        &self,
+        driver: &D,
+    ) -> Result<ForwardedWrites, String> {
+        let writes = self.writes();
+        if writes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let results = driver.write_list(&writes)?;
+        Ok(writes.into_iter().map(|w| w.0).zip(results).collect())
+    }
+}
+
+// Replays area changes in the order they happened and returns the HMI writes
+// in mapping order, the last one winning when a tag was written twice. A byte
+// write covers every bit mapped in it, only the bits it changed count.
+fn decode_writes(
+    mappings: &[(ServerMapping, S7Address)],
+    image: &mut BTreeMap<(S7Area, u16), Vec<u8>>,
+    events: &[AreaWrite],
+) -> Vec<(ETag, ETagValue)> {
+    let mut writes: BTreeMap<usize, ETagValue> = BTreeMap::new();
+    for event in events {
+        let buf = match image.get_mut(&(event.area, event.index)) {
+            Some(buf) => buf,
+            None => continue,
+        };
+        let end = (event.start + event.data.len()).min(buf.len());
+        if event.start >= end {
+            continue;
+        }
+        let before = buf.clone();
+        buf[event.start..end].copy_from_slice(&event.data[..end - event.start]);
+        if !event.written {
+            continue;
+        }
+        for (i, (mapping, addr)) in mappings.iter().enumerate() {
+            let start = addr.start as usize;
+            let stop = start + addr.size as usize;
+            if addr.area != event.area
+                || addr.dbnb != event.index
+                || stop <= event.start
+                || end <= start
+            {
+                continue;
+            }
+            let value = match decode_value(&buf[start..stop], &addr.datatype, addr.bit) {
+                Ok(value) => value,
+                Err(err) => {
+                    warn!("{}: {}", mapping.tag.name, err);
+                    continue;
+                }
+            };
+            if addr.datatype.is_bool()
+                && decode_value(&before[start..stop], &addr.datatype, addr.bit).as_ref()
+                    == Ok(&value)
+            {
+                continue;
+            }
+            writes.insert(i, value);
+        }
+    }
+    writes
+        .into_iter()
+        .map(|(i, value)| (mappings[i].0.tag.clone(), value))
+        .collect()
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop();
+
+        unsafe {
+            Srv_Destroy(&mut self.shared.handle);
+            for area in &self.shared.areas {
+                drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                    area.buf, area.len,
+                )));
+            }
+        }
+    }
+}
+
+pub fn server_error_text(code: i32) -> String {
+    let mut err = vec![0u8; 1024];
+    unsafe {
+        Srv_ErrorText(
+            code as c_int,
+            err.as_mut_ptr() as *mut std::os::raw::c_char,
+            err.len() as c_int,
+        );
+    }
+    if let Some(i) = err.iter().position(|&r| r == 0) {
+        err.truncate(i);
+    }
+    String::from_utf8_lossy(&err).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plc_driver::tests::{tag, Memory};
+    use crate::plc_driver::ETagtype;
+
+    fn mapping(address: &str, datatype: ETagtype) -> (ServerMapping, S7Address) {
+        let addr = S7Address::parse(&address_regex(), address, datatype).unwrap();
+        let mapping = ServerMapping {
+            address: address.to_owned(),
+            tag: tag(address, datatype),
+        };
+        (mapping, addr)
+    }
+
+    fn change(start: usize, data: &[u8], written: bool) -> AreaWrite {
+        AreaWrite {
+            area: S7Area::DB,
+            index: 1,
+            start,
+            data: data.to_vec(),
+            written,
+        }
+    }
+
+    fn image(len: usize) -> BTreeMap<(S7Area, u16), Vec<u8>> {
+        vec![((S7Area::DB, 1), vec![0u8; len])]
+            .into_iter()
+            .collect()
+    }
+
+    fn names(writes: &[(ETag, ETagValue)]) -> Vec<(&str, ETagValue)> {
+        writes
+            .iter()
+            .map(|(tag, value)| (tag.name.as_str(), value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn hmi_write_outlives_a_later_publish() {
+        let mappings = vec![mapping("DB1W0", ETagtype::INT)];
+        let mut image = image(2);
+        let events = vec![change(0, &[0, 42], true), change(0, &[0, 7], false)];
+        let writes = decode_writes(&mappings, &mut image, &events);
+        assert_eq!(names(&writes), vec![("DB1W0", ETagValue::Int(42))]);
+        assert_eq!(image[&(S7Area::DB, 1)], vec![0, 7]);
+    }
+
+    #[test]
+    fn only_changed_bits_are_forwarded() {
+        let mappings = vec![
+            mapping("DB1X0.1", ETagtype::BOOL),
+            mapping("DB1X0.3", ETagtype::BOOL),
+        ];
+        // HMI sets bit 1, then the gateway publishes bit 3
+        let mut image = image(1);
+        let events = vec![change(0, &[0x02], true), change(0, &[0x0A], false)];
+        let writes = decode_writes(&mappings, &mut image, &events);
+        assert_eq!(names(&writes), vec![("DB1X0.1", ETagValue::Bool(true))]);
+
+        // The gateway clears bit 3, then the HMI clears bit 1 in a byte that
+        // still has it
+        let events = vec![change(0, &[0x02], false), change(0, &[0x00], true)];
+        let writes = decode_writes(&mappings, &mut image, &events);
+        assert_eq!(names(&writes), vec![("DB1X0.1", ETagValue::Bool(false))]);
+    }
+
+    #[test]
+    fn partial_writes_keep_the_other_bytes() {
+        let mappings = vec![mapping("DB1D4", ETagtype::DINT)];
+        let mut image = image(8);
+        let events = vec![
+            change(4, &[0x00, 0x01, 0x00, 0x02], false),
+            change(7, &[0x05], true),
+        ];
+        let writes = decode_writes(&mappings, &mut image, &events);
+        assert_eq!(names(&writes), vec![("DB1D4", ETagValue::Int(0x0001_0005))]);
+    }
+
+    #[test]
+    fn last_write_wins_in_mapping_order() {
+        let mappings = vec![
+            mapping("DB1W0", ETagtype::INT),
+            mapping("DB1W2", ETagtype::INT),
+        ];
+        let mut image = image(4);
+        let events = vec![
+            change(2, &[0, 1], true),
+            change(0, &[0, 2], true),
+            change(2, &[0, 3], true),
+            // Neither another DB nor the published values are HMI writes
+            AreaWrite {
+                index: 2,
+                ..change(0, &[0, 9], true)
+            },
+            change(0, &[0, 4], false),
+        ];
+        let writes = decode_writes(&mappings, &mut image, &events);
+        assert_eq!(
+            names(&writes),
+            vec![("DB1W0", ETagValue::Int(2)), ("DB1W2", ETagValue::Int(3))]
+        );
+        assert!(decode_writes(&mappings, &mut image, &[]).is_empty());
+    }
+
+    // What snap7 does for a client writing DB1: update the area, then raise
+    // the event
+    fn hmi_write(server: &Server, start: usize, data: &[u8]) {
+        server
+            .shared
+            .with_area(S7Area::DB, 1, start, data.len(), |d| {
+                d.copy_from_slice(data)
+            })
+            .unwrap();
+        let mut event: TSrvEvent = unsafe { std::mem::zeroed() };
+        event.EvtCode = EVC_DATA_WRITE;
+        event.EvtParam1 = SRV_AREA_DB as u16;
+        event.EvtParam2 = 1;
+        event.EvtParam3 = start as u16;
+        event.EvtParam4 = data.len() as u16;
+        unsafe {
+            on_event(
+                &*server.shared as *const Shared as *mut c_void,
+                &mut event,
+                std::mem::size_of::<TSrvEvent>() as c_int,
+            );
+        }
+    }
+
+    #[test]
+    fn forwards_hmi_writes_published_over() {
+        let plc = Memory::default();
+        let a = tag("DB1W0", ETagtype::INT);
+        let b = tag("DB1W2", ETagtype::INT);
+        plc.set(&a.address, ETagValue::Int(7));
+        plc.set(&b.address, ETagValue::Int(8));
+        let server = Server::new(vec![
+            ServerMapping {
+                address: a.address.clone(),
+                tag: a.clone(),
+            },
+            ServerMapping {
+                address: b.address.clone(),
+                tag: b.clone(),
+            },
+        ])
+        .unwrap();
+        server.publish_from(&plc).unwrap();
+
+        hmi_write(&server, 0, &42i16.to_be_bytes());
+        // The next refresh overwrites the area before the write is forwarded
+        server.publish_from(&plc).unwrap();
+        let forwarded = server.forward_writes(&plc).unwrap();
+        let forwarded: Vec<_> = forwarded
+            .into_iter()
+            .map(|(tag, result)| (tag.name, result))
+            .collect();
+        assert_eq!(forwarded, vec![(a.name.clone(), Ok(true))]);
+        assert_eq!(plc.get(&a.address), Some(ETagValue::Int(42)));
+        assert_eq!(plc.get(&b.address), Some(ETagValue::Int(8)));
+        assert!(server.writes().is_empty());
+    }
+}
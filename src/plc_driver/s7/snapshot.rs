@@ -0,0 +1,422 @@
+use super::ffi::*;
+use super::{error_text, Client, S7Area, S7BlockType, S7WL};
+use crate::plc_driver::{ETag, ETagValue};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::os::raw::{c_int, c_void};
+use std::path::Path;
+
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+const MAX_DB_SIZE: usize = 0x10000;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DbImage {
+    pub number: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub version: u32,
+    pub label: String,
+    pub host: String,
+    pub taken_at: DateTime<Utc>,
+    pub blocks: Vec<DbImage>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ByteChange {
+    pub offset: usize,
+    pub before: Vec<u8>,
+    pub after: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DbChange {
+    Added,
+    Removed,
+    Resized { before: usize, after: usize },
+    Changed(Vec<ByteChange>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DbDiff {
+    pub number: u16,
+    pub change: DbChange,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TagChange {
+    pub name: String,
+    pub address: String,
+    pub before: Option<ETagValue>,
+    pub after: Option<ETagValue>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SnapshotDiff {
+    pub blocks: Vec<DbDiff>,
+    pub tags: Vec<TagChange>,
+}
+
+pub type DbResult = (u16, Result<(), String>);
+
+impl DbImage {
+    // A DB image only goes back into a DB of the same size
+    fn check_size(&self, plc_size: usize) -> Result<(), String> {
+        if plc_size == self.data.len() {
+            Ok(())
+        } else {
+            Err(format!(
+                "DB{} is {} bytes on the PLC but {} bytes in the snapshot",
+                self.number,
+                plc_size,
+                self.data.len()
+            ))
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn block(&self, number: u16) -> Option<&DbImage> {
+        self.blocks.iter().find(|b| b.number == number)
+    }
+
+    pub fn numbers(&self) -> Vec<u16> {
+        self.blocks.iter().map(|b| b.number).collect()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self).map_err(|e| e.to_string())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let snapshot: Snapshot =
+            serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string())?;
+        if snapshot.version > SNAPSHOT_VERSION {
+            Err(format!(
+                "Unsupported snapshot version {}, expected {} or lower",
+                snapshot.version, SNAPSHOT_VERSION
+            ))
+        } else {
+            Ok(snapshot)
+        }
+    }
+
+    pub fn diff_bytes(&self, other: &Snapshot) -> Vec<DbDiff> {
+        let mut numbers: Vec<u16> = self.numbers();
+        numbers.extend(other.numbers());
+        numbers.sort();
+        numbers.dedup();
+        numbers
+            .into_iter()
+            .filter_map(|number| {
+                let change = match (self.block(number), other.block(number)) {
+                    (Some(_), None) => DbChange::Removed,
+                    (None, Some(_)) => DbChange::Added,
+                    (Some(a), Some(b)) if a.data.len() != b.data.len() => DbChange::Resized {
+                        before: a.data.len(),
+                        after: b.data.len(),
+                    },
+                    (Some(a), Some(b)) => {
+                        let changes = byte_changes(&a.data, &b.data);
+                        if changes.is_empty() {
+                            return None;
+                        }
+                        DbChange::Changed(changes)
+                    }
+                    (None, None) => return None,
+                };
+                Some(DbDiff { number, change })
+            })
+            .collect()
+    }
+}
+
+fn byte_changes(before: &[u8], after: &[u8]) -> Vec<ByteChange> {
+    let mut changes = Vec::new();
+    let mut i = 0;
+    while i < before.len() {
+        if before[i] == after[i] {
+            i += 1;
+            continue;
+        }
+        let offset = i;
+        while i < before.len() && before[i] != after[i] {
+            i += 1;
+        }
+        changes.push(ByteChange {
+            offset,
+            before: before[offset..i].to_vec(),
+            after: after[offset..i].to_vec(),
+        });
+    }
+    changes
+}
+
+impl Client {
+    pub fn db_get(&self, number: u16) -> Result<Vec<u8>, String> {
+        let _guard = self.guard();
+        let mut buf = vec![0u8; MAX_DB_SIZE];
+        let mut size = buf.len() as c_int;
+        let res;
+        unsafe {
+            res = Cli_DBGet(
+                self.handle,
+                number as c_int,
+                buf.as_mut_ptr() as *mut c_void,
+                &mut size,
+            ) as i32;
+        }
+        if res == 0 {
+            buf.truncate(size as usize);
+            Ok(buf)
+        } else {
+            Err(error_text(res))
+        }
+    }
+
+    pub fn snapshot(&self, dbs: Option<&[u16]>, label: &str) -> Result<Snapshot, String> {
+        let numbers = match dbs {
+            Some(dbs) => dbs.to_vec(),
+            None => self.list_blocks_of_type(S7BlockType::DB)?,
+        };
+        let blocks = numbers
+            .iter()
+            .map(|number| {
+                self.db_get(*number)
+                    .map(|data| DbImage {
+                        number: *number,
+                        data,
+                    })
+                    .map_err(|err| format!("DB{}: {}", number, err))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            label: label.to_owned(),
+            host: self.host.clone(),
+            taken_at: Utc::now(),
+            blocks,
+        })
+    }
+
+    pub fn restore(
+        &self,
+        snapshot: &Snapshot,
+        dbs: Option<&[u16]>,
+    ) -> Result<Vec<DbResult>, String> {
+        self.check_write_access()?;
+        let images: Vec<&DbImage> = match dbs {
+            Some(dbs) => dbs
+                .iter()
+                .map(|number| {
+                    snapshot
+                        .block(*number)
+                        .ok_or_else(|| format!("DB{} is not in the snapshot", number))
+                })
+                .collect::<Result<Vec<_>, String>>()?,
+            None => snapshot.blocks.iter().collect(),
+        };
+        let results = images
+            .into_iter()
+            .map(|image| (image.number, self.restore_db(image)))
+            .collect();
+        Ok(results)
+    }
+
+    fn restore_db(&self, image: &DbImage) -> Result<(), String> {
+        image.check_size(self.block_info(S7BlockType::DB, image.number)?.mc7_size)?;
+        let _guard = self.guard();
+        let res;
+        unsafe {
+            res = Cli_WriteArea(
+                self.handle,
+                S7Area::DB as c_int,
+                image.number as c_int,
+                0,
+                image.data.len() as c_int,
+                S7WL::S7WLByte as c_int,
+                image.data.as_ptr() as *mut c_void,
+            ) as i32;
+        }
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(error_text(res))
+        }
+    }
+
+    pub fn diff_snapshots(
+        &self,
+        before: &Snapshot,
+        after: &Snapshot,
+        tags: &[ETag],
+    ) -> SnapshotDiff {
+        let tags = tags
+            .iter()
+            .filter_map(|tag| {
+                let before_value = self.snapshot_value(before, tag);
+                let after_value = self.snapshot_value(after, tag);
+                if before_value == after_value {
+                    None
+                } else {
+                    Some(TagChange {
+                        name: tag.name.clone(),
+                        address: tag.address.clone(),
+                        before: before_value,
+                        after: after_value,
+                    })
+                }
+            })
+            .collect();
+        SnapshotDiff {
+            blocks: before.diff_bytes(after),
+            tags,
+        }
+    }
+
+    pub fn diff_live(&self, before: &Snapshot, tags: &[ETag]) -> Result<SnapshotDiff, String> {
+        let live = self.snapshot(Some(&before.numbers()), "live")?;
+        Ok(self.diff_snapshots(before, &live, tags))
+    }
+
+    fn snapshot_value(&self, snapshot: &Snapshot, tag: &ETag) -> Option<ETagValue> {
+        let addr = self.conv_address(tag.address.as_str(), tag.datatype).ok()?;
+        if addr.area != S7Area::DB {
+            return None;
+        }
+        let image = snapshot.block(addr.dbnb)?;
+        let end = addr.start as usize + addr.size as usize;
+        if end > image.data.len() {
+            return None;
+        }
+        let buf = image.data[addr.start as usize..end].to_vec();
+        self.conv_value(&buf, &tag.datatype, addr.bit).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(blocks: Vec<(u16, Vec<u8>)>) -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            label: String::from("test"),
+            host: String::from("127.0.0.1"),
+            taken_at: Utc::now(),
+            blocks: blocks
+                .into_iter()
+                .map(|(number, data)| DbImage { number, data })
+                .collect(),
+        }
+    }
+
+    fn change(offset: usize, before: &[u8], after: &[u8]) -> ByteChange {
+        ByteChange {
+            offset,
+            before: before.to_vec(),
+            after: after.to_vec(),
+        }
+    }
+
+    #[test]
+    fn byte_changes_group_adjacent_bytes() {
+        assert_eq!(byte_changes(&[1, 2, 3], &[1, 2, 3]), vec![]);
+        assert_eq!(
+            byte_changes(&[0, 1, 2, 3, 4, 5], &[9, 1, 7, 7, 4, 6]),
+            vec![
+                change(0, &[0], &[9]),
+                change(2, &[2, 3], &[7, 7]),
+                change(5, &[5], &[6]),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_bytes_reports_each_db_once() {
+        let before = snapshot(vec![
+            (1, vec![0, 0]),
+            (2, vec![0, 0]),
+            (3, vec![0, 0]),
+            (4, vec![5, 5]),
+        ]);
+        let after = snapshot(vec![
+            (2, vec![0, 0, 0]),
+            (3, vec![0, 8]),
+            (4, vec![5, 5]),
+            (5, vec![0]),
+        ]);
+        assert_eq!(
+            before.diff_bytes(&after),
+            vec![
+                DbDiff {
+                    number: 1,
+                    change: DbChange::Removed
+                },
+                DbDiff {
+                    number: 2,
+                    change: DbChange::Resized {
+                        before: 2,
+                        after: 3
+                    }
+                },
+                DbDiff {
+                    number: 3,
+                    change: DbChange::Changed(vec![change(1, &[0], &[8])])
+                },
+                DbDiff {
+                    number: 5,
+                    change: DbChange::Added
+                },
+            ]
+        );
+        assert_eq!(after.diff_bytes(&after), vec![]);
+    }
+
+    #[test]
+    fn load_rejects_newer_versions() {
+        let path =
+            std::env::temp_dir().join(format!("box-edge-snapshot-{}.json", std::process::id()));
+        let mut saved = snapshot(vec![(1, vec![1, 2, 3])]);
+        saved.save(&path).unwrap();
+        let loaded = Snapshot::load(&path).unwrap();
+        assert_eq!(loaded.version, SNAPSHOT_VERSION);
+        assert_eq!(loaded.taken_at, saved.taken_at);
+        assert_eq!(loaded.block(1).unwrap().data, vec![1, 2, 3]);
+
+        saved.version = SNAPSHOT_VERSION + 1;
+        saved.save(&path).unwrap();
+        let result = Snapshot::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            result.unwrap_err(),
+            format!(
+                "Unsupported snapshot version {}, expected {} or lower",
+                SNAPSHOT_VERSION + 1,
+                SNAPSHOT_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn restore_needs_a_db_of_the_same_size() {
+        let image = DbImage {
+            number: 7,
+            data: vec![0; 4],
+        };
+        assert_eq!(image.check_size(4), Ok(()));
+        assert_eq!(
+            image.check_size(6),
+            Err(String::from(
+                "DB7 is 6 bytes on the PLC but 4 bytes in the snapshot"
+            ))
+        );
+    }
+}
@@ -0,0 +1,445 @@
+use super::ffi::*;
+use super::{error_text, Client};
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::os::raw::c_int;
+
+pub const SZL_LED_STATUS: u16 = 0x0019;
+pub const SZL_MODULE_STATUS: u16 = 0x0091;
+pub const SZL_DIAG_BUFFER: u16 = 0x00A0;
+pub const SZL_INTERRUPT_STATUS: u16 = 0x0222;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Szl {
+    pub id: u16,
+    pub index: u16,
+    pub record_len: usize,
+    pub records: Vec<Vec<u8>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DiagEvent {
+    pub id: u16,
+    pub class: Option<String>,
+    pub text: Option<String>,
+    pub incoming: bool,
+    pub priority: u8,
+    pub ob: u8,
+    pub dat_id: u16,
+    pub info1: u16,
+    pub info2: u32,
+    pub timestamp: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModuleStatus {
+    pub adr1: u16,
+    pub adr2: u16,
+    pub logical_address: u16,
+    pub expected_type: u16,
+    pub actual_type: u16,
+    pub fault: bool,
+    pub present: bool,
+    pub unavailable: bool,
+    pub disabled: bool,
+    pub station_fault: bool,
+    pub area: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LedStatus {
+    pub led: String,
+    pub on: bool,
+    pub blinking: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct CycleTime {
+    pub last_ms: u16,
+    pub min_ms: u16,
+    pub max_ms: u16,
+}
+
+impl Client {
+    pub fn read_szl(&self, id: u16, index: u16) -> Result<Szl, String> {
+        let _guard = self.guard();
+        let mut szl: Box<TS7SZL> = Box::new(unsafe { std::mem::zeroed() });
+        let mut size = std::mem::size_of::<TS7SZL>() as c_int;
+        let res = unsafe {
+            Cli_ReadSZL(
+                self.handle,
+                id as c_int,
+                index as c_int,
+                &mut *szl,
+                &mut size,
+            )
+        };
+        if res == 0 {
+            let record_len = szl.Header.LENTHDR as usize;
+            let count = szl.Header.N_DR as usize;
+            let records = if record_len == 0 {
+                Vec::new()
+            } else {
+                szl.Data
+                    .chunks_exact(record_len)
+                    .take(count)
+                    .map(|r| r.to_vec())
+                    .collect()
+            };
+            Ok(Szl {
+                id,
+                index,
+                record_len,
+                records,
+            })
+        } else {
+            Err(error_text(res))
+        }
+    }
+
+    pub fn read_szl_list(&self) -> Result<Vec<u16>, String> {
+        let _guard = self.guard();
+        let mut list: Box<TS7SZLList> = Box::new(unsafe { std::mem::zeroed() });
+        // The list field is unaligned in the packed struct, so work on a copy
+        let capacity = { list.List }.len();
+        let mut count = capacity as c_int;
+        let res = unsafe { Cli_ReadSZLList(self.handle, &mut *list, &mut count) };
+        if res == 0 {
+            let ids = list.List;
+            Ok(ids[..(count as usize).min(capacity)].to_vec())
+        } else {
+            Err(error_text(res))
+        }
+    }
+
+    pub fn diagnostic_buffer(&self) -> Result<Vec<DiagEvent>, String> {
+        let szl = self.read_szl(SZL_DIAG_BUFFER, 0)?;
+        szl.records.iter().map(|r| decode_diag_event(r)).collect()
+    }
+
+    pub fn module_status(&self) -> Result<Vec<ModuleStatus>, String> {
+        let szl = self.read_szl(SZL_MODULE_STATUS, 0)?;
+        szl.records
+            .iter()
+            .map(|r| decode_module_status(r))
+            .collect()
+    }
+
+    pub fn led_status(&self) -> Result<Vec<LedStatus>, String> {
+        let szl = self.read_szl(SZL_LED_STATUS, 0)?;
+        szl.records.iter().map(|r| decode_led_status(r)).collect()
+    }
+
+    pub fn cycle_time(&self) -> Result<CycleTime, String> {
+        // The OB1 start information carries the cycle time statistics
+        let szl = self.read_szl(SZL_INTERRUPT_STATUS, 1)?;
+        match szl.records.first() {
+            Some(r) => decode_cycle_time(r),
+            None => Err(String::from("Invalid SZL 0x0222 record")),
+        }
+    }
+}
+
+pub fn decode_cycle_time(r: &[u8]) -> Result<CycleTime, String> {
+    if r.len() < 12 {
+        return Err(String::from("Invalid SZL 0x0222 record"));
+    }
+    Ok(CycleTime {
+        last_ms: be_u16(r, 6),
+        min_ms: be_u16(r, 8),
+        max_ms: be_u16(r, 10),
+    })
+}
+
+pub fn decode_diag_event(r: &[u8]) -> Result<DiagEvent, String> {
+    if r.len() < 20 {
+        return Err(String::from("Invalid diagnostic buffer record"));
+    }
+    let id = be_u16(r, 0);
+    Ok(DiagEvent {
+        id,
+        class: event_class(id).map(String::from),
+        text: event_text(id).map(String::from),
+        incoming: id & 0x0100 != 0,
+        priority: r[2],
+        ob: r[3],
+        dat_id: be_u16(r, 4),
+        info1: be_u16(r, 6),
+        info2: u32::from_be_bytes(r[8..12].try_into().unwrap()),
+        timestamp: decode_date_and_time(&r[12..20]),
+    })
+}
+
+pub fn decode_module_status(r: &[u8]) -> Result<ModuleStatus, String> {
+    if r.len() < 16 {
+        return Err(String::from("Invalid module status record"));
+    }
+    let eastat = be_u16(r, 12);
+    Ok(ModuleStatus {
+        adr1: be_u16(r, 0),
+        adr2: be_u16(r, 2),
+        logical_address: be_u16(r, 4),
+        expected_type: be_u16(r, 6),
+        actual_type: be_u16(r, 8),
+        fault: eastat & 0x0001 != 0,
+        present: eastat & 0x0002 != 0,
+        unavailable: eastat & 0x0004 != 0,
+        disabled: eastat & 0x0008 != 0,
+        station_fault: eastat & 0x0010 != 0,
+        area: be_u16(r, 14),
+    })
+}
+
+pub fn decode_led_status(r: &[u8]) -> Result<LedStatus, String> {
+    if r.len() < 4 {
+        return Err(String::from("Invalid LED status record"));
+    }
+    let id = be_u16(r, 0);
+    let led = match id & 0x00FF {
+        0x01 => String::from("SF"),
+        0x02 => String::from("INTF"),
+        0x03 => String::from("EXTF"),
+        0x04 => String::from("RUN"),
+        0x05 => String::from("STOP"),
+        0x06 => String::from("FRCE"),
+        0x07 => String::from("CRST"),
+        0x08 => String::from("BAF"),
+        0x09 => String::from("USR"),
+        0x0A => String::from("USR1"),
+        0x0B => String::from("BUS1F"),
+        0x0C => String::from("BUS2F"),
+        0x0D => String::from("REDF"),
+        0x0E => String::from("MSTR"),
+        0x0F => String::from("RACK0"),
+        0x10 => String::from("RACK1"),
+        0x11 => String::from("RACK2"),
+        0x12 => String::from("IFM1F"),
+        0x13 => String::from("IFM2F"),
+        _ => format!("{:#06x}", id),
+    };
+    Ok(LedStatus {
+        led,
+        on: r[2] == 1,
+        blinking: r[3] != 0,
+    })
+}
+
+pub fn event_class(id: u16) -> Option<&'static str> {
+    match id >> 12 {
+        0x1 => Some("Standard OB event"),
+        0x2 => Some("Synchronous error"),
+        0x3 => Some("Asynchronous error"),
+        0x4 => Some("Mode transition"),
+        0x8 => Some("Module diagnostics"),
+        0x9 => Some("Standard user event"),
+        0xA | 0xB => Some("Free user event"),
+        _ => None,
+    }
+}
+
+pub fn event_text(id: u16) -> Option<&'static str> {
+    match id {
+        0x1381 => Some("Request for manual warm restart"),
+        0x1382 => Some("Request for automatic warm restart"),
+        0x1383 => Some("Request for manual hot restart"),
+        0x1384 => Some("Request for automatic hot restart"),
+        0x1385 => Some("Request for manual cold restart"),
+        0x1386 => Some("Request for automatic cold restart"),
+        0x2521 => Some("BCD conversion error"),
+        0x2522 => Some("Area length error when reading"),
+        0x2523 => Some("Area length error when writing"),
+        0x2524 => Some("Area error when reading"),
+        0x2525 => Some("Area error when writing"),
+        0x2526 => Some("Timer number error"),
+        0x2527 => Some("Counter number error"),
+        0x2528 => Some("Alignment error when reading"),
+        0x2529 => Some("Alignment error when writing"),
+        0x2530 => Some("Write error when accessing the DB"),
+        0x2531 => Some("Write error when accessing the DI"),
+        0x2532 => Some("Block number error when opening a DB"),
+        0x2533 => Some("Block number error when opening a DI"),
+        0x2534 => Some("Block number error when calling an FC"),
+        0x2535 => Some("Block number error when calling an FB"),
+        0x253A => Some("DB not loaded"),
+        0x253C => Some("FC not loaded"),
+        0x253E => Some("FB not loaded"),
+        0x3501 => Some("Cycle time exceeded"),
+        0x4300 => Some("Backed-up power on"),
+        0x4301 => Some("Mode transition from STOP to STARTUP"),
+        0x4302 => Some("Mode transition from STARTUP to RUN"),
+        0x4303 => Some("STOP caused by stop switch being activated"),
+        0x4304 => Some("STOP caused by PG STOP operation or by SFB 20 STOP"),
+        0x4305 => Some("HOLD: breakpoint reached"),
+        0x4306 => Some("HOLD: breakpoint exited"),
+        0x4307 => Some("Memory reset started by PG operation"),
+        0x4308 => Some("Memory reset started by switch setting"),
+        0x4309 => Some("Memory reset started automatically (power on not backed up)"),
+        0x4520 => Some("DEFECTIVE: STOP not possible"),
+        0x4521 => Some("DEFECTIVE: failure of instruction processing processor"),
+        0x4522 => Some("DEFECTIVE: failure of clock chip"),
+        0x4562 => Some("STOP caused by programming error (OB not loaded or not possible)"),
+        0x4563 => Some("STOP caused by I/O access error (OB not loaded or not possible)"),
+        0x4568 => Some("STOP caused by time error (OB not loaded or not possible)"),
+        0x456A => Some("STOP caused by diagnostic interrupt (OB not loaded or not possible)"),
+        _ => None,
+    }
+}
+
+// S7 DATE_AND_TIME: BCD encoded yy mm dd hh mm ss, then ms in the following 3 nibbles
+pub fn decode_date_and_time(buf: &[u8]) -> Option<NaiveDateTime> {
+    let bcd = |b: u8| -> u32 { ((b >> 4) * 10 + (b & 0x0F)) as u32 };
+    let yy = bcd(buf[0]) as i32;
+    let year = if yy >= 90 { 1900 + yy } else { 2000 + yy };
+    let ms = bcd(buf[6]) * 10 + (buf[7] >> 4) as u32;
+    NaiveDate::from_ymd_opt(year, bcd(buf[1]), bcd(buf[2]))?.and_hms_milli_opt(
+        bcd(buf[3]),
+        bcd(buf[4]),
+        bcd(buf[5]),
+        ms,
+    )
+}
+
+fn be_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32, ms: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, mo, d)
+            .unwrap()
+            .and_hms_milli_opt(h, mi, s, ms)
+            .unwrap()
+    }
+
+    #[test]
+    fn decodes_diagnostic_buffer_records() {
+        // Mode transition from STARTUP to RUN in the SZL 0x00A0 layout
+        let record = [
+            0x43, 0x02, 0xFF, 0x84, 0xC6, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x20, 0x10,
+            0x07, 0x15, 0x10, 0x22, 0x33, 0x45,
+        ];
+        let event = decode_diag_event(&record).unwrap();
+        assert_eq!(event.id, 0x4302);
+        assert_eq!(event.class.as_deref(), Some("Mode transition"));
+        assert_eq!(
+            event.text.as_deref(),
+            Some("Mode transition from STARTUP to RUN")
+        );
+        assert!(event.incoming);
+        assert_eq!(event.priority, 0xFF);
+        assert_eq!(event.ob, 0x84);
+        assert_eq!(event.dat_id, 0xC600);
+        assert_eq!(event.info1, 0);
+        assert_eq!(event.info2, 0x0100);
+        assert_eq!(event.timestamp, Some(time(2020, 10, 7, 15, 10, 22, 334)));
+
+        // Module removed, outgoing, with no text of its own
+        let record = [
+            0x38, 0x61, 0x1A, 0x53, 0x54, 0xC4, 0x01, 0x00, 0x00, 0x00, 0x00, 0x06, 0x98, 0x12,
+            0x31, 0x23, 0x59, 0x59, 0x99, 0x92,
+        ];
+        let event = decode_diag_event(&record).unwrap();
+        assert_eq!(event.class.as_deref(), Some("Asynchronous error"));
+        assert_eq!(event.text, None);
+        assert!(!event.incoming);
+        assert_eq!(event.info1, 0x0100);
+        assert_eq!(event.info2, 6);
+        assert_eq!(event.timestamp, Some(time(1998, 12, 31, 23, 59, 59, 999)));
+
+        assert!(decode_diag_event(&record[..19]).is_err());
+    }
+
+    #[test]
+    fn decodes_bcd_date_and_time() {
+        assert_eq!(
+            decode_date_and_time(&[0x89, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x07]),
+            Some(time(2089, 1, 1, 0, 0, 0, 0))
+        );
+        assert_eq!(
+            decode_date_and_time(&[0x90, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x02]),
+            Some(time(1990, 1, 1, 0, 0, 0, 0))
+        );
+        assert_eq!(
+            decode_date_and_time(&[0x24, 0x02, 0x29, 0x08, 0x30, 0x15, 0x05, 0x05]),
+            Some(time(2024, 2, 29, 8, 30, 15, 50))
+        );
+        // Month 13 and 30 February
+        assert_eq!(
+            decode_date_and_time(&[0x24, 0x13, 0x01, 0x00, 0x00, 0x00, 0x00, 0x01]),
+            None
+        );
+        assert_eq!(
+            decode_date_and_time(&[0x24, 0x02, 0x30, 0x00, 0x00, 0x00, 0x00, 0x01]),
+            None
+        );
+    }
+
+    #[test]
+    fn decodes_module_status_records() {
+        let record = [
+            0x00, 0x00, 0x00, 0x04, 0x01, 0x00, 0xC0, 0x00, 0xC4, 0x00, 0x00, 0x00, 0x00, 0x15,
+            0x00, 0x01,
+        ];
+        let status = decode_module_status(&record).unwrap();
+        assert_eq!(status.adr1, 0);
+        assert_eq!(status.adr2, 4);
+        assert_eq!(status.logical_address, 0x0100);
+        assert_eq!(status.expected_type, 0xC000);
+        assert_eq!(status.actual_type, 0xC400);
+        assert!(status.fault);
+        assert!(!status.present);
+        assert!(status.unavailable);
+        assert!(!status.disabled);
+        assert!(status.station_fault);
+        assert_eq!(status.area, 1);
+
+        let mut record = record;
+        record[13] = 0x0A;
+        let status = decode_module_status(&record).unwrap();
+        assert!(!status.fault);
+        assert!(status.present);
+        assert!(!status.unavailable);
+        assert!(status.disabled);
+        assert!(!status.station_fault);
+
+        assert!(decode_module_status(&record[..15]).is_err());
+    }
+
+    #[test]
+    fn decodes_led_status_records() {
+        let run = decode_led_status(&[0x00, 0x04, 0x01, 0x00]).unwrap();
+        assert_eq!(run.led, "RUN");
+        assert!(run.on);
+        assert!(!run.blinking);
+
+        let sf = decode_led_status(&[0x00, 0x01, 0x00, 0x01]).unwrap();
+        assert_eq!(sf.led, "SF");
+        assert!(!sf.on);
+        assert!(sf.blinking);
+
+        // The rack byte is ignored, unknown LEDs keep their id
+        assert_eq!(decode_led_status(&[0x01, 0x13, 0, 0]).unwrap().led, "IFM2F");
+        assert_eq!(
+            decode_led_status(&[0x00, 0x20, 0, 0]).unwrap().led,
+            "0x0020"
+        );
+        assert!(decode_led_status(&[0x00, 0x04, 0x01]).is_err());
+    }
+
+    #[test]
+    fn decodes_cycle_time_from_the_ob1_record() {
+        let record = [
+            0x00, 0x01, 0x11, 0x01, 0x00, 0x00, 0x00, 0x05, 0x00, 0x02, 0x01, 0x2C, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let cycle = decode_cycle_time(&record).unwrap();
+        assert_eq!(cycle.last_ms, 5);
+        assert_eq!(cycle.min_ms, 2);
+        assert_eq!(cycle.max_ms, 300);
+        assert!(decode_cycle_time(&record[..11]).is_err());
+    }
+}
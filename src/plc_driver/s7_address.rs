@@ -0,0 +1,179 @@
+use super::{ERangePolicy, ETagValue, ETagtype};
+use bit_vec::BitVec;
+use regex::Regex;
+use std::convert::TryInto;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum S7Area {
+    PE = 0x81,
+    PA = 0x82,
+    MK = 0x83,
+    DB = 0x84,
+}
+
+#[derive(Debug)]
+pub enum S7WL {
+    S7WLBit = 0x01,
+    S7WLByte = 0x02,
+    S7WLWord = 0x04,
+    S7WLDWord = 0x06,
+    S7WLReal = 0x08,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct S7Address {
+    pub(crate) area: S7Area,
+    pub(crate) dbnb: u16,
+    pub(crate) bit: u8,
+    pub(crate) start: u32,
+    pub(crate) size: u8,
+    pub(crate) datatype: ETagtype,
+}
+
+const MAX_START: u32 = 0x1F_FFFF;
+
+pub fn address_regex() -> Regex {
+    Regex::new(r"^(M|I|Q|(?:DB(\d+)))(W|D|X)(\d+)(?:\.([0-7]))?$").unwrap()
+}
+
+impl S7Address {
+    pub fn parse(reg: &Regex, address: &str, datatype: ETagtype) -> Result<S7Address, String> {
+        if let Some(r) = &reg.captures(address) {
+            let area: S7Area = match r.get(1).unwrap().as_str() {
+                "M" => S7Area::MK,
+                "I" => S7Area::PE,
+                "Q" => S7Area::PA,
+                _ => S7Area::DB,
+            };
+            let dbnb: u16 = match area {
+                S7Area::DB => r
+                    .get(2)
+                    .unwrap()
+                    .as_str()
+                    .parse()
+                    .map_err(|_| format!("DB number out of range: {}", address))?,
+                _ => 0,
+            };
+            let dd = r.get(3).unwrap().as_str();
+            let size: u8 = match dd {
+                "W" => 2,
+                "D" => 4,
+                _ => 1,
+            };
+            // The protocol carries byte and bit offset as a 24 bit bit address
+            let start: u32 = r
+                .get(4)
+                .unwrap()
+                .as_str()
+                .parse()
+                .ok()
+                .filter(|start| *start <= MAX_START)
+                .ok_or_else(|| format!("Byte offset out of range: {}", address))?;
+            let bit: u8 = if r.get(5).is_none() {
+                0
+            } else {
+                r.get(5).unwrap().as_str().parse().unwrap()
+            };
+            let addr = S7Address {
+                area,
+                dbnb,
+                size,
+                start,
+                bit,
+                datatype,
+            };
+            match datatype {
+                ETagtype::BOOL if dd == "X" => Ok(addr),
+                ETagtype::INT if dd == "W" => Ok(addr),
+                _ if dd == "D" => Ok(addr),
+                _ => Err(String::from("Invalid S7 addree")),
+            }
+        } else {
+            Err(String::from("Invalid S7 addree"))
+        }
+    }
+}
+
+pub fn decode_value(buf: &[u8], datatype: &ETagtype, bit: u8) -> Result<ETagValue, String> {
+    match datatype {
+        ETagtype::INT => Ok(ETagValue::Int(
+            i16::from_be_bytes(buf[0..2].try_into().unwrap()) as i64,
+        )),
+
+        ETagtype::DINT => Ok(ETagValue::Int(
+            i32::from_be_bytes(buf[0..4].try_into().unwrap()) as i64,
+        )),
+
+        ETagtype::REAL => Ok(ETagValue::Real(f32::from_bits(u32::from_be_bytes(
+            buf[0..4].try_into().unwrap(),
+        )) as f64)),
+        ETagtype::BOOL => {
+            let bv = BitVec::from_bytes(buf);
+            Ok(ETagValue::Bool(bv.get((7 - bit) as usize).unwrap()))
+        }
+    }
+}
+
+// BOOL is encoded as a single 0/1 byte, which is what a bit transport write
+// expects; byte oriented writers have to merge it into the current byte.
+pub fn encode_value(
+    write: ETagValue,
+    datatype: ETagtype,
+    range: ERangePolicy,
+) -> Result<Vec<u8>, String> {
+    let write = datatype.coerce(write, range)?;
+    match (datatype, write) {
+        (ETagtype::INT, ETagValue::Int(v)) => Ok((v as i16).to_be_bytes().to_vec()),
+        (ETagtype::DINT, ETagValue::Int(v)) => Ok((v as i32).to_be_bytes().to_vec()),
+        (ETagtype::REAL, ETagValue::Real(v)) => Ok((v as f32).to_bits().to_be_bytes().to_vec()),
+        (ETagtype::BOOL, ETagValue::Bool(v)) => Ok(vec![v as u8]),
+        _ => Err(String::from("Invalid datatype for write value")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(address: &str, datatype: ETagtype) -> Result<S7Address, String> {
+        S7Address::parse(&address_regex(), address, datatype)
+    }
+
+    #[test]
+    fn parses_large_db_numbers_and_offsets() {
+        let addr = parse("DB300W0", ETagtype::INT).unwrap();
+        assert_eq!(
+            (addr.area, addr.dbnb, addr.start, addr.size),
+            (S7Area::DB, 300, 0, 2)
+        );
+        let addr = parse("DB2W300", ETagtype::INT).unwrap();
+        assert_eq!((addr.dbnb, addr.start), (2, 300));
+        let addr = parse("DB65535X2097151.7", ETagtype::BOOL).unwrap();
+        assert_eq!((addr.dbnb, addr.start, addr.bit), (65535, 0x1F_FFFF, 7));
+        let addr = parse("MD1000", ETagtype::REAL).unwrap();
+        assert_eq!((addr.area, addr.dbnb, addr.start), (S7Area::MK, 0, 1000));
+    }
+
+    #[test]
+    fn rejects_out_of_range_numbers() {
+        assert_eq!(
+            parse("DB65536W0", ETagtype::INT).unwrap_err(),
+            "DB number out of range: DB65536W0"
+        );
+        assert_eq!(
+            parse("DB1W2097152", ETagtype::INT).unwrap_err(),
+            "Byte offset out of range: DB1W2097152"
+        );
+        assert_eq!(
+            parse("MW99999999999", ETagtype::INT).unwrap_err(),
+            "Byte offset out of range: MW99999999999"
+        );
+    }
+
+    #[test]
+    fn rejects_mismatched_sizes() {
+        assert!(parse("DB1W0", ETagtype::BOOL).is_err());
+        assert!(parse("DB1X0.8", ETagtype::BOOL).is_err());
+        assert!(parse("MX0.0", ETagtype::INT).is_err());
+    }
+}
@@ -0,0 +1,658 @@
+pub use super::s7_address::{S7Address, S7Area, S7WL};
+
+use super::registry::{self, param, Registry};
+use super::s7_address::{address_regex, decode_value, encode_value};
+use super::{ETag, ETagRW, ETagValue, ETagtype};
+use log::info;
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const ISO_TCP_PORT: u16 = 102;
+const TPKT_HEADER_LEN: usize = 4;
+const COTP_DT_HEADER: [u8; 3] = [0x02, 0xF0, 0x80];
+const S7_PROTOCOL_ID: u8 = 0x32;
+const S7_JOB: u8 = 0x01;
+const S7_ACK_DATA: u8 = 0x03;
+const S7_FUNC_READ_VAR: u8 = 0x04;
+const S7_FUNC_WRITE_VAR: u8 = 0x05;
+const S7_FUNC_SETUP_COMM: u8 = 0xF0;
+const S7_PDU_REQUEST: u16 = 480;
+const S7_JOB_HEADER_LEN: usize = 10;
+const S7_ACK_HEADER_LEN: usize = 12;
+const S7_ITEM_SPEC_LEN: usize = 12;
+const S7_TS_BIT: u8 = 0x01;
+const S7_TS_BYTE: u8 = 0x02;
+const S7_DATA_TS_BIT: u8 = 0x03;
+const S7_DATA_TS_BYTE: u8 = 0x04;
+const S7_ITEM_OK: u8 = 0xFF;
+
+struct Connection {
+    stream: TcpStream,
+    pdu_ref: u16,
+    pdu_len: usize,
+    broken: bool,
+}
+
+#[derive(Debug)]
+pub struct Client {
+    host: String,
+    timeout: Duration,
+    reg: regex::Regex,
+    conn: Mutex<Option<Connection>>,
+}
+
+impl std::fmt::Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Connection")
+            .field("peer", &self.stream.peer_addr().ok())
+            .field("pdu_len", &self.pdu_len)
+            .finish()
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self {
+            host: String::new(),
+            timeout: Duration::from_secs(3),
+            reg: address_regex(),
+            conn: Mutex::new(None),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn connect(&mut self, host: &str, rack: i32, slot: i32) {
+        self.host = host.to_owned();
+        match self.open(host, rack, slot) {
+            Ok(conn) => {
+                info!("Get PDU: {}, {}", S7_PDU_REQUEST, conn.pdu_len);
+                *self.conn.lock().unwrap() = Some(conn);
+            }
+            Err(err) => {
+                info!("Connect to {} failed: {}", host, err);
+                *self.conn.lock().unwrap() = None;
+            }
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.conn.lock().unwrap().take();
+    }
+
+    pub fn connected(&mut self) -> bool {
+        self.conn.lock().unwrap().is_some()
+    }
+
+    pub fn conv_address(&self, address: &str, datatype: ETagtype) -> Result<S7Address, String> {
+        S7Address::parse(&self.reg, address, datatype)
+    }
+
+    fn open(&self, host: &str, rack: i32, slot: i32) -> Result<Connection, String> {
+        let addr = if host.contains(':') {
+            host.to_socket_addrs()
+        } else {
+            (host, ISO_TCP_PORT).to_socket_addrs()
+        }
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("Cannot resolve {}", host))?;
+        let stream = TcpStream::connect_timeout(&addr, self.timeout).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(|e| e.to_string())?;
+        stream
+            .set_write_timeout(Some(self.timeout))
+            .map_err(|e| e.to_string())?;
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        let mut conn = Connection {
+            stream,
+            pdu_ref: 0,
+            pdu_len: S7_PDU_REQUEST as usize,
+            broken: false,
+        };
+
+        // COTP connection request, PG connection to rack/slot
+        let remote_tsap = [0x01, (rack * 0x20 + slot) as u8];
+        let cr = [
+            0x11, 0xE0, 0x00, 0x00, 0x00, 0x01, 0x00, 0xC0, 0x01, 0x0A, 0xC1, 0x02, 0x01, 0x00,
+            0xC2, 0x02, remote_tsap[0], remote_tsap[1],
+        ];
+        conn.send_tpkt(&cr)?;
+        let cc = conn.recv_tpkt()?;
+        if cc.len() < 2 || cc[1] != 0xD0 {
+            return Err(String::from("ISO connection refused"));
+        }
+
+        let mut param = vec![S7_FUNC_SETUP_COMM, 0x00, 0x00, 0x01, 0x00, 0x01];
+        param.extend_from_slice(&S7_PDU_REQUEST.to_be_bytes());
+        let (param, _) = conn.exchange(&param, &[])?;
+        if param.len() < 8 {
+            return Err(String::from("Invalid setup communication response"));
+        }
+        conn.pdu_len = u16::from_be_bytes(param[6..8].try_into().unwrap()) as usize;
+        Ok(conn)
+    }
+
+    fn with_conn<T, F>(&self, f: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, String>,
+    {
+        let mut guard = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let result = match guard.as_mut() {
+            Some(conn) => f(conn),
+            None => return Err(String::from("Not connected")),
+        };
+        if guard.as_ref().is_some_and(|c| c.broken) {
+            guard.take();
+        }
+        result
+    }
+}
+
+impl Connection {
+    fn send_tpkt(&mut self, payload: &[u8]) -> Result<(), String> {
+        let len = (TPKT_HEADER_LEN + payload.len()) as u16;
+        let mut frame = vec![0x03, 0x00];
+        frame.extend_from_slice(&len.to_be_bytes());
+        frame.extend_from_slice(payload);
+        let r = self.stream.write_all(&frame);
+        self.io(r)
+    }
+
+    // The stream is left in an unknown state after an I/O error
+    fn io<T>(&mut self, r: std::io::Result<T>) -> Result<T, String> {
+        r.map_err(|e| {
+            self.broken = true;
+            e.to_string()
+        })
+    }
+
+    fn recv_tpkt(&mut self) -> Result<Vec<u8>, String> {
+        let mut header = [0u8; TPKT_HEADER_LEN];
+        let r = self.stream.read_exact(&mut header);
+        self.io(r)?;
+        if header[0] != 0x03 {
+            self.broken = true;
+            return Err(String::from("Invalid TPKT header"));
+        }
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        if len < TPKT_HEADER_LEN {
+            self.broken = true;
+            return Err(String::from("Invalid TPKT length"));
+        }
+        let mut payload = vec![0u8; len - TPKT_HEADER_LEN];
+        let r = self.stream.read_exact(&mut payload);
+        self.io(r)?;
+        Ok(payload)
+    }
+
+    fn recv_s7(&mut self) -> Result<Vec<u8>, String> {
+        let mut pdu = Vec::new();
+        loop {
+            let frame = self.recv_tpkt()?;
+            if frame.len() < 3 || frame[1] != 0xF0 {
+                return Err(String::from("Invalid COTP data frame"));
+            }
+            pdu.extend_from_slice(&frame[3..]);
+            if frame[2] & 0x80 != 0 {
+                return Ok(pdu);
+            }
+        }
+    }
+
+    fn exchange(&mut self, param: &[u8], data: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+        self.pdu_ref = self.pdu_ref.wrapping_add(1);
+        let mut frame = COTP_DT_HEADER.to_vec();
+        frame.extend_from_slice(&[S7_PROTOCOL_ID, S7_JOB, 0x00, 0x00]);
+        frame.extend_from_slice(&self.pdu_ref.to_be_bytes());
+        frame.extend_from_slice(&(param.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        frame.extend_from_slice(param);
+        frame.extend_from_slice(data);
+        self.send_tpkt(&frame)?;
+
+        let pdu = self.recv_s7()?;
+        if pdu.len() < S7_ACK_HEADER_LEN || pdu[0] != S7_PROTOCOL_ID || pdu[1] != S7_ACK_DATA {
+            return Err(String::from("Invalid S7 response"));
+        }
+        if u16::from_be_bytes([pdu[4], pdu[5]]) != self.pdu_ref {
+            self.broken = true;
+            return Err(String::from("S7 PDU reference mismatch"));
+        }
+        if pdu[10] != 0 || pdu[11] != 0 {
+            return Err(format!("S7 error class {:#04x} code {:#04x}", pdu[10], pdu[11]));
+        }
+        let param_len = u16::from_be_bytes([pdu[6], pdu[7]]) as usize;
+        let data_len = u16::from_be_bytes([pdu[8], pdu[9]]) as usize;
+        let param_end = S7_ACK_HEADER_LEN + param_len;
+        if pdu.len() < param_end + data_len {
+            return Err(String::from("Short S7 response"));
+        }
+        Ok((
+            pdu[S7_ACK_HEADER_LEN..param_end].to_vec(),
+            pdu[param_end..param_end + data_len].to_vec(),
+        ))
+    }
+
+    fn read_items(&mut self, addrs: &[S7Address]) -> Result<Vec<Result<Vec<u8>, String>>, String> {
+        let mut results = Vec::with_capacity(addrs.len());
+        for chunk in chunk_items(addrs, self.pdu_len, false) {
+            let mut param = vec![S7_FUNC_READ_VAR, chunk.len() as u8];
+            for addr in chunk {
+                param.extend_from_slice(&item_spec(addr));
+            }
+            let (_, data) = self.exchange(&param, &[])?;
+            let mut offset = 0;
+            for addr in chunk {
+                if data.len() < offset + 4 {
+                    return Err(String::from("Short S7 read response"));
+                }
+                let code = data[offset];
+                let ts = data[offset + 1];
+                let len = u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize;
+                let len = match ts {
+                    S7_DATA_TS_BIT | S7_DATA_TS_BYTE => len.div_ceil(8),
+                    _ => len,
+                };
+                offset += 4;
+                if code != S7_ITEM_OK {
+                    results.push(Err(item_error_text(code)));
+                    continue;
+                }
+                if data.len() < offset + len {
+                    return Err(String::from("Short S7 read response"));
+                }
+                let mut buf = data[offset..offset + len].to_vec();
+                if addr.datatype.is_bool() {
+                    // Bit reads come back as 0/1, move it where decode_value expects it
+                    buf = vec![(buf[0] & 0x01) << addr.bit];
+                }
+                results.push(Ok(buf));
+                offset += len + len % 2;
+            }
+        }
+        Ok(results)
+    }
+
+    fn write_items(
+        &mut self,
+        items: &[(S7Address, Vec<u8>)],
+    ) -> Result<Vec<Result<bool, String>>, String> {
+        let addrs: Vec<S7Address> = items.iter().map(|t| t.0).collect();
+        let mut results = Vec::with_capacity(items.len());
+        let mut done = 0;
+        for chunk in chunk_items(&addrs, self.pdu_len, true) {
+            let mut param = vec![S7_FUNC_WRITE_VAR, chunk.len() as u8];
+            let mut data = Vec::new();
+            for (i, addr) in chunk.iter().enumerate() {
+                param.extend_from_slice(&item_spec(addr));
+                let buf = &items[done + i].1;
+                let (ts, bits) = if addr.datatype.is_bool() {
+                    (S7_DATA_TS_BIT, 1)
+                } else {
+                    (S7_DATA_TS_BYTE, buf.len() * 8)
+                };
+                data.extend_from_slice(&[0x00, ts]);
+                data.extend_from_slice(&(bits as u16).to_be_bytes());
+                data.extend_from_slice(buf);
+                if i + 1 < chunk.len() && buf.len() % 2 == 1 {
+                    data.push(0x00);
+                }
+            }
+            let (_, ack) = self.exchange(&param, &data)?;
+            if ack.len() < chunk.len() {
+                return Err(String::from("Short S7 write response"));
+            }
+            for code in &ack[0..chunk.len()] {
+                if *code == S7_ITEM_OK {
+                    results.push(Ok(true));
+                } else {
+                    results.push(Err(item_error_text(*code)));
+                }
+            }
+            done += chunk.len();
+        }
+        Ok(results)
+    }
+}
+
+fn item_spec(addr: &S7Address) -> [u8; S7_ITEM_SPEC_LEN] {
+    let (ts, amount, bit_addr) = if addr.datatype.is_bool() {
+        (S7_TS_BIT, 1u16, addr.start * 8 + addr.bit as u32)
+    } else {
+        (S7_TS_BYTE, addr.size as u16, addr.start * 8)
+    };
+    let amount = amount.to_be_bytes();
+    let db = addr.dbnb.to_be_bytes();
+    let bit_addr = bit_addr.to_be_bytes();
+    [
+        0x12,
+        0x0A,
+        0x10,
+        ts,
+        amount[0],
+        amount[1],
+        db[0],
+        db[1],
+        addr.area as u8,
+        bit_addr[1],
+        bit_addr[2],
+        bit_addr[3],
+    ]
+}
+
+// Splits items so that both the request and its response fit in one PDU
+fn chunk_items(addrs: &[S7Address], pdu_len: usize, write: bool) -> Vec<&[S7Address]> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut request = S7_JOB_HEADER_LEN + 2;
+    let mut response = S7_ACK_HEADER_LEN + 2;
+    for (i, addr) in addrs.iter().enumerate() {
+        let data = 4 + addr.size as usize + 1;
+        let req_item = S7_ITEM_SPEC_LEN + if write { data } else { 0 };
+        let resp_item = if write { 1 } else { data };
+        if i > start
+            && (request + req_item > pdu_len || response + resp_item > pdu_len || i - start >= 20)
+        {
+            chunks.push(&addrs[start..i]);
+            start = i;
+            request = S7_JOB_HEADER_LEN + 2;
+            response = S7_ACK_HEADER_LEN + 2;
+        }
+        request += req_item;
+        response += resp_item;
+    }
+    if start < addrs.len() {
+        chunks.push(&addrs[start..]);
+    }
+    chunks
+}
+
+fn item_error_text(code: u8) -> String {
+    match code {
+        0x01 => String::from("CPU : Hardware fault"),
+        0x03 => String::from("CPU : Accessing the object not allowed"),
+        0x05 => String::from("CPU : Address out of range"),
+        0x06 => String::from("CPU : Data type not supported"),
+        0x07 => String::from("CPU : Data type inconsistent"),
+        0x0A => String::from("CPU : Object does not exist"),
+        _ => format!("CPU : Item error {:#04x}", code),
+    }
+}
+
+impl ETagRW for Client {
+    fn read_tag(&self, tag: &ETag) -> Result<ETagValue, String> {
+        let addr = self.conv_address(tag.address.as_str(), tag.datatype)?;
+        let buf = self
+            .with_conn(|conn| conn.read_items(&[addr]))?
+            .pop()
+            .unwrap()?;
+        decode_value(&buf, &tag.datatype, addr.bit)
+    }
+    fn read_list(&self, tags: &Vec<ETag>) -> Result<Vec<Result<ETagValue, String>>, String> {
+        let addrs = tags
+            .iter()
+            .map(|tag| self.conv_address(tag.address.as_str(), tag.datatype))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        let bufs = self.with_conn(|conn| conn.read_items(&addrs))?;
+        Ok(bufs
+            .into_iter()
+            .zip(addrs)
+            .map(|(buf, addr)| buf.and_then(|buf| decode_value(&buf, &addr.datatype, addr.bit)))
+            .collect())
+    }
+    fn write_tag(&self, tag: &ETag, write: ETagValue) -> Result<bool, String> {
+        let addr = self.conv_address(tag.address.as_str(), tag.datatype)?;
+        let buf = encode_value(write, tag.datatype, tag.range)?;
+        self.with_conn(|conn| conn.write_items(&[(addr, buf)]))?
+            .pop()
+            .unwrap()
+    }
+    fn write_list(
+        &self,
+        tags: &Vec<(ETag, ETagValue)>,
+    ) -> Result<Vec<Result<bool, String>>, String> {
+        let addrs = tags
+            .iter()
+            .map(|t| self.conv_address(t.0.address.as_str(), t.0.datatype))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|_| String::from("Address error"))?;
+        let converted: Vec<_> = tags
+            .iter()
+            .map(|t| encode_value(t.1.clone(), t.0.datatype, t.0.range))
+            .collect();
+        let items: Vec<(S7Address, Vec<u8>)> = addrs
+            .iter()
+            .zip(converted.iter())
+            .filter_map(|(addr, buf)| buf.as_ref().ok().map(|buf| (*addr, buf.clone())))
+            .collect();
+        let mut written = self.with_conn(|conn| conn.write_items(&items))?.into_iter();
+        Ok(converted
+            .into_iter()
+            .map(|buf| match buf {
+                Ok(_) => written.next().unwrap(),
+                Err(err) => Err(err),
+            })
+            .collect())
+    }
+}
+
+// s7comm://host[:port]?rack=0&slot=1, also s7:// unless snap7 is built
+pub fn register(registry: &mut Registry) {
+    let schemes: &[&str] = if cfg!(feature = "snap7") {
+        &["s7comm"]
+    } else {
+        &["s7comm", "s7"]
+    };
+    for scheme in schemes {
+        registry.register_device(
+            scheme,
+            |url| {
+                let host = registry::host(url)?;
+                let mut client = Client::new();
+                client.connect(
+                    host.as_str(),
+                    param(url, "rack", 0)?,
+                    param(url, "slot", 1)?,
+                );
+                if client.connected() {
+                    Ok(client)
+                } else {
+                    Err(format!("Connect to {} failed", host))
+                }
+            },
+            Client::connected,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plc_driver::tests::tag;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn read_tpkt(stream: &mut TcpStream) -> Option<Vec<u8>> {
+        let mut header = [0u8; TPKT_HEADER_LEN];
+        stream.read_exact(&mut header).ok()?;
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let mut payload = vec![0u8; len - TPKT_HEADER_LEN];
+        stream.read_exact(&mut payload).ok()?;
+        Some(payload)
+    }
+
+    fn write_ack(stream: &mut TcpStream, pdu_ref: &[u8], param: &[u8], data: &[u8]) {
+        let mut frame = COTP_DT_HEADER.to_vec();
+        frame.extend_from_slice(&[S7_PROTOCOL_ID, S7_ACK_DATA, 0x00, 0x00]);
+        frame.extend_from_slice(pdu_ref);
+        frame.extend_from_slice(&(param.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&[0x00, 0x00]);
+        frame.extend_from_slice(param);
+        frame.extend_from_slice(data);
+        let len = (TPKT_HEADER_LEN + frame.len()) as u16;
+        let mut tpkt = vec![0x03, 0x00];
+        tpkt.extend_from_slice(&len.to_be_bytes());
+        tpkt.extend_from_slice(&frame);
+        stream.write_all(&tpkt).unwrap();
+    }
+
+    // A single DB of 64 bytes answering setup, read var and write var jobs
+    fn serve(mut stream: TcpStream, db: Arc<Mutex<Vec<u8>>>) {
+        while let Some(payload) = read_tpkt(&mut stream) {
+            if payload[1] == 0xE0 {
+                let mut cc = payload.clone();
+                cc[1] = 0xD0;
+                let len = (TPKT_HEADER_LEN + cc.len()) as u16;
+                let mut tpkt = vec![0x03, 0x00];
+                tpkt.extend_from_slice(&len.to_be_bytes());
+                tpkt.extend_from_slice(&cc);
+                stream.write_all(&tpkt).unwrap();
+                continue;
+            }
+            let pdu = &payload[3..];
+            let pdu_ref = &pdu[4..6];
+            let param_len = u16::from_be_bytes([pdu[6], pdu[7]]) as usize;
+            let param = &pdu[S7_JOB_HEADER_LEN..S7_JOB_HEADER_LEN + param_len];
+            let mut data = &pdu[S7_JOB_HEADER_LEN + param_len..];
+            let items = param.get(2..).unwrap_or(&[]).chunks(S7_ITEM_SPEC_LEN);
+            let mut db = db.lock().unwrap();
+            match param[0] {
+                S7_FUNC_SETUP_COMM => {
+                    let mut ack = param.to_vec();
+                    ack[6..8].copy_from_slice(&240u16.to_be_bytes());
+                    write_ack(&mut stream, pdu_ref, &ack, &[]);
+                }
+                S7_FUNC_READ_VAR => {
+                    let mut out = Vec::new();
+                    for item in items {
+                        let amount = u16::from_be_bytes([item[4], item[5]]) as usize;
+                        let bit_addr = u32::from_be_bytes([0, item[9], item[10], item[11]]);
+                        let start = (bit_addr / 8) as usize;
+                        if item[3] == S7_TS_BIT {
+                            let v = (db[start] >> (bit_addr % 8)) & 0x01;
+                            out.extend_from_slice(&[S7_ITEM_OK, S7_DATA_TS_BIT, 0x00, 0x01, v]);
+                        } else {
+                            out.extend_from_slice(&[S7_ITEM_OK, S7_DATA_TS_BYTE]);
+                            out.extend_from_slice(&((amount * 8) as u16).to_be_bytes());
+                            out.extend_from_slice(&db[start..start + amount]);
+                        }
+                        if out.len() % 2 == 1 {
+                            out.push(0x00);
+                        }
+                    }
+                    write_ack(&mut stream, pdu_ref, &param[0..2], &out);
+                }
+                S7_FUNC_WRITE_VAR => {
+                    let mut out = Vec::new();
+                    for item in items {
+                        let bit_addr = u32::from_be_bytes([0, item[9], item[10], item[11]]);
+                        let start = (bit_addr / 8) as usize;
+                        let bits = u16::from_be_bytes([data[2], data[3]]) as usize;
+                        let len = if data[1] == S7_DATA_TS_BIT {
+                            let mask = 1u8 << (bit_addr % 8);
+                            db[start] = if data[4] != 0 {
+                                db[start] | mask
+                            } else {
+                                db[start] & !mask
+                            };
+                            1
+                        } else {
+                            let len = bits / 8;
+                            db[start..start + len].copy_from_slice(&data[4..4 + len]);
+                            len
+                        };
+                        data = &data[(4 + len + len % 2).min(data.len())..];
+                        out.push(S7_ITEM_OK);
+                    }
+                    write_ack(&mut stream, pdu_ref, &param[0..2], &out);
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn server() -> (String, Arc<Mutex<Vec<u8>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let db = Arc::new(Mutex::new(vec![0u8; 64]));
+        let shared = db.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                serve(stream.unwrap(), shared.clone());
+            }
+        });
+        (host, db)
+    }
+
+    fn client(host: &str) -> Client {
+        let mut client = Client::new();
+        client.connect(host, 0, 1);
+        assert!(client.connected());
+        client
+    }
+
+    #[test]
+    fn reads_bits_where_the_cpu_has_them() {
+        let (host, db) = server();
+        db.lock().unwrap()[0] = 0b0000_1001;
+        let client = client(&host);
+        let values = client
+            .read_list(&vec![
+                tag("DB1X0.0", ETagtype::BOOL),
+                tag("DB1X0.1", ETagtype::BOOL),
+                tag("DB1X0.3", ETagtype::BOOL),
+                tag("DB1X0.7", ETagtype::BOOL),
+            ])
+            .unwrap();
+        assert_eq!(
+            values,
+            vec![
+                Ok(ETagValue::Bool(true)),
+                Ok(ETagValue::Bool(false)),
+                Ok(ETagValue::Bool(true)),
+                Ok(ETagValue::Bool(false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn write_then_read_back() {
+        let (host, db) = server();
+        let client = client(&host);
+        let writes = vec![
+            (tag("DB1X1.5", ETagtype::BOOL), ETagValue::Bool(true)),
+            (tag("DB1W2", ETagtype::INT), ETagValue::Int(-1234)),
+            (tag("DB1D4", ETagtype::DINT), ETagValue::Int(100_000)),
+            (tag("DB1D8", ETagtype::REAL), ETagValue::Real(2.5)),
+        ];
+        let written = client.write_list(&writes).unwrap();
+        assert!(written.iter().all(|r| r == &Ok(true)));
+        assert_eq!(db.lock().unwrap()[1], 0b0010_0000);
+        assert_eq!(&db.lock().unwrap()[2..4], &(-1234i16).to_be_bytes());
+        let tags: Vec<ETag> = writes.iter().map(|t| t.0.clone()).collect();
+        let values: Vec<ETagValue> = client
+            .read_list(&tags)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+        let expected: Vec<ETagValue> = writes.into_iter().map(|t| t.1).collect();
+        assert_eq!(values, expected);
+    }
+}
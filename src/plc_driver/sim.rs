@@ -0,0 +1,671 @@
+use super::registry::{param, Registry};
+use super::{ETag, ETagRW, ETagValue, ETagtype};
+use log::info;
+use regex::Regex;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// Steps a random walk or counter may catch up in a single read
+const MAX_CATCH_UP: u64 = 100_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Generator {
+    Constant(f64),
+    // Sawtooth from min to max, restarting every period
+    Ramp {
+        min: f64,
+        max: f64,
+        period: f64,
+    },
+    Sine {
+        amplitude: f64,
+        offset: f64,
+        period: f64,
+        phase: f64,
+    },
+    Square {
+        low: f64,
+        high: f64,
+        period: f64,
+        duty: f64,
+    },
+    RandomWalk {
+        start: f64,
+        step: f64,
+        min: f64,
+        max: f64,
+        interval: f64,
+    },
+    // Wraps back to start once it passes max, or min when counting down
+    Counter {
+        start: f64,
+        step: f64,
+        min: Option<f64>,
+        max: Option<f64>,
+        interval: f64,
+    },
+    // Replays one column of a CSV file, one row per interval, looping
+    Csv {
+        path: String,
+        column: String,
+        interval: f64,
+    },
+}
+
+pub fn address_regex() -> Regex {
+    Regex::new(r"^\s*([a-z_]+)\s*(?:\((.*)\))?\s*$").unwrap()
+}
+
+struct Params {
+    address: String,
+    positional: Option<String>,
+    named: HashMap<String, String>,
+}
+
+impl Params {
+    fn parse(address: &str, args: &str) -> Result<Self, String> {
+        let mut params = Params {
+            address: address.to_owned(),
+            positional: None,
+            named: HashMap::new(),
+        };
+        for (i, arg) in args
+            .split(',')
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+            .enumerate()
+        {
+            match arg.find('=') {
+                Some(pos) => {
+                    let name = arg[..pos].trim();
+                    if params
+                        .named
+                        .insert(name.to_owned(), arg[pos + 1..].trim().to_owned())
+                        .is_some()
+                    {
+                        return Err(format!("Duplicate parameter {} in {}", name, address));
+                    }
+                }
+                None if i == 0 => params.positional = Some(arg.to_owned()),
+                None => return Err(format!("Invalid parameter {} in {}", arg, address)),
+            }
+        }
+        Ok(params)
+    }
+
+    fn text(&mut self, name: &str) -> Option<String> {
+        self.named.remove(name)
+    }
+
+    fn opt(&mut self, name: &str) -> Result<Option<f64>, String> {
+        match self.named.remove(name) {
+            Some(v) => parse_number(&v)
+                .map(Some)
+                .ok_or_else(|| format!("Invalid value {} for {} in {}", v, name, self.address)),
+            None => Ok(None),
+        }
+    }
+
+    fn num(&mut self, name: &str, default: f64) -> Result<f64, String> {
+        self.opt(name).map(|v| v.unwrap_or(default))
+    }
+
+    fn period(&mut self, name: &str, default: f64) -> Result<f64, String> {
+        let v = self.num(name, default)?;
+        if v > 0.0 {
+            Ok(v)
+        } else {
+            Err(format!("{} must be positive in {}", name, self.address))
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match (self.positional, self.named.keys().next()) {
+            (Some(p), _) => Err(format!("Unexpected parameter {} in {}", p, self.address)),
+            (_, Some(k)) => Err(format!("Unknown parameter {} in {}", k, self.address)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn parse_number(s: &str) -> Option<f64> {
+    match s.trim() {
+        "true" | "TRUE" | "True" => Some(1.0),
+        "false" | "FALSE" | "False" => Some(0.0),
+        s => s.parse().ok(),
+    }
+}
+
+impl Generator {
+    // `const(5)`, `ramp(min=0, max=100, period=60)`, `sine(amplitude=10, period=30)`,
+    // `square(period=2)`, `random(step=0.5, min=0, max=10)`, `counter(step=-1, min=0)`,
+    // `csv(path=data/line1.csv, column=speed, interval=1)`
+    pub fn parse(reg: &Regex, address: &str) -> Result<Self, String> {
+        let r = reg
+            .captures(address)
+            .ok_or_else(|| format!("Invalid sim address {}", address))?;
+        let mut p = Params::parse(address, r.get(2).map_or("", |m| m.as_str()))?;
+        let generator = match r.get(1).unwrap().as_str() {
+            "const" | "constant" => {
+                let value = match p.positional.take() {
+                    Some(v) => parse_number(&v)
+                        .ok_or_else(|| format!("Invalid value {} in {}", v, address))?,
+                    None => p.num("value", 0.0)?,
+                };
+                Generator::Constant(value)
+            }
+            "ramp" => Generator::Ramp {
+                min: p.num("min", 0.0)?,
+                max: p.num("max", 100.0)?,
+                period: p.period("period", 60.0)?,
+            },
+            "sine" => Generator::Sine {
+                amplitude: p.num("amplitude", 1.0)?,
+                offset: p.num("offset", 0.0)?,
+                period: p.period("period", 60.0)?,
+                phase: p.num("phase", 0.0)?,
+            },
+            "square" => Generator::Square {
+                low: p.num("low", 0.0)?,
+                high: p.num("high", 1.0)?,
+                period: p.period("period", 2.0)?,
+                duty: p.num("duty", 0.5)?,
+            },
+            "random" => Generator::RandomWalk {
+                start: p.num("start", 0.0)?,
+                step: p.num("step", 1.0)?,
+                min: p.num("min", f64::MIN)?,
+                max: p.num("max", f64::MAX)?,
+                interval: p.period("interval", 1.0)?,
+            },
+            "counter" => Generator::Counter {
+                start: p.num("start", 0.0)?,
+                step: p.num("step", 1.0)?,
+                min: p.opt("min")?,
+                max: p.opt("max")?,
+                interval: p.period("interval", 1.0)?,
+            },
+            "csv" => Generator::Csv {
+                path: p
+                    .text("path")
+                    .or_else(|| p.positional.take())
+                    .ok_or_else(|| format!("Missing path in {}", address))?,
+                column: p.text("column").unwrap_or_else(|| String::from("0")),
+                interval: p.period("interval", 1.0)?,
+            },
+            name => return Err(format!("Unknown generator {} in {}", name, address)),
+        };
+        p.finish()?;
+        Ok(generator)
+    }
+
+    // Step interval of the generators that keep a state
+    fn interval(&self) -> Option<f64> {
+        match self {
+            Generator::RandomWalk { interval, .. } | Generator::Counter { interval, .. } => {
+                Some(*interval)
+            }
+            _ => None,
+        }
+    }
+}
+
+// Per address: the position of stateful generators and written values
+#[derive(Debug, Default)]
+struct State {
+    value: f64,
+    steps: u64,
+    written: Option<ETagValue>,
+}
+
+// Splitmix64 finalizer: spreads neighbouring seeds apart and keeps the
+// xorshift state non-zero
+fn scramble(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (z ^ (z >> 31)).max(1)
+}
+
+#[derive(Debug)]
+pub struct Client {
+    start: Instant,
+    reg: Regex,
+    rng: Mutex<u64>,
+    states: Mutex<HashMap<String, State>>,
+    csv: Mutex<HashMap<String, Vec<Vec<String>>>>,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Client {
+    pub fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self::with_seed(seed)
+    }
+
+    // Fixed seed for reproducible random walks
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            start: Instant::now(),
+            reg: address_regex(),
+            rng: Mutex::new(scramble(seed)),
+            states: Mutex::new(HashMap::new()),
+            csv: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn conv_address(&self, address: &str) -> Result<Generator, String> {
+        Generator::parse(&self.reg, address)
+    }
+
+    // Forgets written values and restarts every generator
+    pub fn reset(&mut self) {
+        self.start = Instant::now();
+        self.states.lock().unwrap().clear();
+        self.csv.lock().unwrap().clear();
+    }
+
+    fn random(&self) -> f64 {
+        let mut x = self.rng.lock().unwrap();
+        *x ^= *x << 13;
+        *x ^= *x >> 7;
+        *x ^= *x << 17;
+        (*x >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn sample(&self, address: &str, generator: &Generator, t: f64) -> Result<f64, String> {
+        let cycle = |period: f64| (t / period).fract();
+        Ok(match generator {
+            Generator::Constant(v) => *v,
+            Generator::Ramp { min, max, period } => min + (max - min) * cycle(*period),
+            Generator::Sine {
+                amplitude,
+                offset,
+                period,
+                phase,
+            } => offset + amplitude * (2.0 * PI * t / period + phase.to_radians()).sin(),
+            Generator::Square {
+                low,
+                high,
+                period,
+                duty,
+            } => {
+                if cycle(*period) < *duty {
+                    *high
+                } else {
+                    *low
+                }
+            }
+            Generator::RandomWalk {
+                start,
+                step,
+                min,
+                max,
+                interval,
+            } => self.advance(address, *start, *interval, t, |v| {
+                (v + step * (2.0 * self.random() - 1.0)).max(*min).min(*max)
+            }),
+            Generator::Counter {
+                start,
+                step,
+                min,
+                max,
+                interval,
+            } => self.advance(address, *start, *interval, t, |v| match (min, max) {
+                (_, Some(max)) if v + step > *max => *start,
+                (Some(min), _) if v + step < *min => *start,
+                _ => v + step,
+            }),
+            Generator::Csv {
+                path,
+                column,
+                interval,
+            } => self.replay(path, column, (t / interval) as usize)?,
+        })
+    }
+
+    // Applies `next` once for every interval elapsed since the last read
+    fn advance<F>(&self, address: &str, start: f64, interval: f64, t: f64, next: F) -> f64
+    where
+        F: Fn(f64) -> f64,
+    {
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(address.to_owned()).or_insert_with(|| State {
+            value: start,
+            ..State::default()
+        });
+        let steps = (t / interval) as u64;
+        let behind = steps.saturating_sub(state.steps).min(MAX_CATCH_UP);
+        for _ in 0..behind {
+            state.value = next(state.value);
+        }
+        state.steps = steps;
+        state.value
+    }
+
+    fn replay(&self, path: &str, column: &str, row: usize) -> Result<f64, String> {
+        let mut files = self.csv.lock().unwrap();
+        if !files.contains_key(path) {
+            let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            let rows: Vec<Vec<String>> = text
+                .lines()
+                .filter(|l| !l.trim().is_empty())
+                .map(|l| l.split(',').map(|c| c.trim().to_owned()).collect())
+                .collect();
+            info!("Loaded {} rows from {}", rows.len(), path);
+            files.insert(path.to_owned(), rows);
+        }
+        let rows = &files[path];
+        // A column given by name selects from the header row
+        let (index, data) = match column.parse::<usize>() {
+            Ok(index) => (index, &rows[..]),
+            Err(_) => match rows
+                .first()
+                .and_then(|h| h.iter().position(|c| c == column))
+            {
+                Some(index) => (index, &rows[1..]),
+                None => return Err(format!("No column {} in {}", column, path)),
+            },
+        };
+        // Skip a header row when replaying by index
+        let data = match data.first().and_then(|r| r.get(index)) {
+            Some(cell) if parse_number(cell).is_none() => &data[1..],
+            _ => data,
+        };
+        if data.is_empty() {
+            return Err(format!("No data in {}", path));
+        }
+        let cell = data[row % data.len()]
+            .get(index)
+            .ok_or_else(|| format!("No column {} in {}", column, path))?;
+        parse_number(cell).ok_or_else(|| format!("Invalid value {} in {}", cell, path))
+    }
+
+    fn read_address(&self, tag: &ETag, t: f64) -> Result<ETagValue, String> {
+        let generator = self.conv_address(tag.address.as_str())?;
+        if let Some(written) = self
+            .states
+            .lock()
+            .unwrap()
+            .get(tag.address.as_str())
+            .and_then(|s| s.written.clone())
+        {
+            return Ok(written);
+        }
+        let v = self.sample(tag.address.as_str(), &generator, t)?;
+        Ok(match tag.datatype {
+            ETagtype::BOOL => ETagValue::Bool(v != 0.0),
+            ETagtype::INT | ETagtype::DINT => {
+                let (min, max) = tag.datatype.range().unwrap();
+                ETagValue::Int((v.round() as i64).max(min).min(max))
+            }
+            ETagtype::REAL => ETagValue::Real(v as f32 as f64),
+        })
+    }
+
+    // Counters and random walks continue from a written value, any other
+    // generator is replaced by it
+    fn write_address(&self, tag: &ETag, write: ETagValue) -> Result<bool, String> {
+        let generator = self.conv_address(tag.address.as_str())?;
+        let write = tag.datatype.coerce(write, tag.range)?;
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(tag.address.clone()).or_default();
+        match generator.interval() {
+            Some(interval) => {
+                state.value = write.as_f64().ok_or_else(|| {
+                    format!("Cannot write {} to {}", write.type_name(), tag.address)
+                })?;
+                state.steps = (self.start.elapsed().as_secs_f64() / interval) as u64;
+            }
+            None => state.written = Some(write),
+        }
+        Ok(true)
+    }
+}
+
+impl ETagRW for Client {
+    fn read_tag(&self, tag: &ETag) -> Result<ETagValue, String> {
+        self.read_address(tag, self.start.elapsed().as_secs_f64())
+    }
+    // All tags of a list are sampled at the same instant
+    fn read_list(&self, tags: &Vec<ETag>) -> Result<Vec<Result<ETagValue, String>>, String> {
+        let t = self.start.elapsed().as_secs_f64();
+        Ok(tags.iter().map(|tag| self.read_address(tag, t)).collect())
+    }
+    fn write_tag(&self, tag: &ETag, write: ETagValue) -> Result<bool, String> {
+        self.write_address(tag, write)
+    }
+    fn write_list(
+        &self,
+        tags: &Vec<(ETag, ETagValue)>,
+    ) -> Result<Vec<Result<bool, String>>, String> {
+        Ok(tags
+            .iter()
+            .map(|(tag, write)| self.write_address(tag, write.clone()))
+            .collect())
+    }
+}
+
+// sim:// or sim://?seed=42 for reproducible random walks
+pub fn register(registry: &mut Registry) {
+    registry.register_device(
+        "sim",
+        |url| {
+            if url.query_pairs().any(|(k, _)| k == "seed") {
+                Ok(Client::with_seed(param(url, "seed", 0)?))
+            } else {
+                Ok(Client::new())
+            }
+        },
+        |_| true,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plc_driver::tests::tag;
+
+    fn parse(address: &str) -> Result<Generator, String> {
+        Generator::parse(&address_regex(), address)
+    }
+
+    fn sample(client: &Client, address: &str, t: f64) -> f64 {
+        client.sample(address, &parse(address).unwrap(), t).unwrap()
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn parses_generators() {
+        assert_eq!(parse("const(5)"), Ok(Generator::Constant(5.0)));
+        assert_eq!(parse("constant(value=true)"), Ok(Generator::Constant(1.0)));
+        assert_eq!(
+            parse(" ramp ( min=1, max = 5,period=2 ) "),
+            Ok(Generator::Ramp {
+                min: 1.0,
+                max: 5.0,
+                period: 2.0
+            })
+        );
+        assert_eq!(
+            parse("counter"),
+            Ok(Generator::Counter {
+                start: 0.0,
+                step: 1.0,
+                min: None,
+                max: None,
+                interval: 1.0
+            })
+        );
+        assert_eq!(
+            parse("csv(data/line1.csv, column=speed)"),
+            Ok(Generator::Csv {
+                path: String::from("data/line1.csv"),
+                column: String::from("speed"),
+                interval: 1.0
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_params() {
+        assert_eq!(
+            parse("ramp(min=0, speed=2)"),
+            Err(String::from(
+                "Unknown parameter speed in ramp(min=0, speed=2)"
+            ))
+        );
+        assert_eq!(
+            parse("ramp(min=0, min=2)"),
+            Err(String::from(
+                "Duplicate parameter min in ramp(min=0, min=2)"
+            ))
+        );
+        assert_eq!(
+            parse("ramp(5)"),
+            Err(String::from("Unexpected parameter 5 in ramp(5)"))
+        );
+        assert_eq!(
+            parse("ramp(min=0, 5)"),
+            Err(String::from("Invalid parameter 5 in ramp(min=0, 5)"))
+        );
+        assert_eq!(
+            parse("ramp(max=x)"),
+            Err(String::from("Invalid value x for max in ramp(max=x)"))
+        );
+        assert_eq!(
+            parse("sine(period=0)"),
+            Err(String::from("period must be positive in sine(period=0)"))
+        );
+        assert_eq!(
+            parse("wave()"),
+            Err(String::from("Unknown generator wave in wave()"))
+        );
+        assert_eq!(
+            parse("csv(column=1)"),
+            Err(String::from("Missing path in csv(column=1)"))
+        );
+    }
+
+    #[test]
+    fn samples_periodic_generators() {
+        let client = Client::with_seed(1);
+        let ramp = "ramp(min=10, max=20, period=4)";
+        assert!(close(sample(&client, ramp, 0.0), 10.0));
+        assert!(close(sample(&client, ramp, 1.0), 12.5));
+        assert!(close(sample(&client, ramp, 5.0), 12.5));
+
+        let square = "square(low=2, high=8, period=4, duty=0.25)";
+        assert!(close(sample(&client, square, 0.5), 8.0));
+        assert!(close(sample(&client, square, 1.5), 2.0));
+        assert!(close(sample(&client, square, 4.5), 8.0));
+
+        let sine = "sine(amplitude=2, offset=1, period=4)";
+        assert!(close(sample(&client, sine, 0.0), 1.0));
+        assert!(close(sample(&client, sine, 1.0), 3.0));
+        assert!(close(sample(&client, sine, 3.0), -1.0));
+        let shifted = "sine(amplitude=2, offset=1, period=4, phase=90)";
+        assert!(close(sample(&client, shifted, 0.0), 3.0));
+    }
+
+    #[test]
+    fn counters_wrap_to_start() {
+        let client = Client::with_seed(1);
+        let up = "counter(start=1, step=4, max=10)";
+        let values: Vec<f64> = (0..5).map(|t| sample(&client, up, t as f64)).collect();
+        assert_eq!(values, vec![1.0, 5.0, 9.0, 1.0, 5.0]);
+
+        let down = "counter(start=10, step=-4, min=0)";
+        let values: Vec<f64> = (0..5).map(|t| sample(&client, down, t as f64)).collect();
+        assert_eq!(values, vec![10.0, 6.0, 2.0, 10.0, 6.0]);
+
+        // Missed intervals are caught up on the next read
+        let late = "counter(step=2, interval=0.5)";
+        assert_eq!(sample(&client, late, 0.0), 0.0);
+        assert_eq!(sample(&client, late, 3.0), 12.0);
+    }
+
+    #[test]
+    fn random_walks_repeat_with_a_seed() {
+        let walk = "random(start=5, step=1, min=0, max=10)";
+        let run = |seed| {
+            let client = Client::with_seed(seed);
+            (0..50)
+                .map(|t| sample(&client, walk, t as f64))
+                .collect::<Vec<f64>>()
+        };
+        let first = run(42);
+        assert_eq!(first, run(42));
+        assert_ne!(first, run(43));
+        assert!(first.iter().all(|v| (0.0..=10.0).contains(v)));
+        assert!(first.windows(2).all(|w| (w[1] - w[0]).abs() <= 1.0));
+    }
+
+    #[test]
+    fn replays_csv_columns() {
+        let dir = std::env::temp_dir().join(format!("box-edge-sim-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let with_header = dir.join("header.csv");
+        fs::write(&with_header, "time, speed\n0, 1.5\n\n1, 2.5\n").unwrap();
+        let plain = dir.join("plain.csv");
+        fs::write(&plain, "7,8\n9,10\n").unwrap();
+        let client = Client::with_seed(1);
+        let path = with_header.to_string_lossy();
+
+        let by_name = format!("csv(path={}, column=speed)", path);
+        let values: Vec<f64> = (0..3)
+            .map(|t| sample(&client, &by_name, t as f64))
+            .collect();
+        assert_eq!(values, vec![1.5, 2.5, 1.5]);
+        // By index the header row is skipped as well
+        let by_index = format!("csv(path={}, column=1, interval=2)", path);
+        let values: Vec<f64> = (0..3)
+            .map(|t| sample(&client, &by_index, 2.0 * t as f64))
+            .collect();
+        assert_eq!(values, vec![1.5, 2.5, 1.5]);
+        let first = format!("csv({})", plain.to_string_lossy());
+        assert_eq!(sample(&client, &first, 1.0), 9.0);
+        let missing = format!("csv(path={}, column=level)", path);
+        assert_eq!(
+            client.sample(&missing, &parse(&missing).unwrap(), 0.0),
+            Err(format!("No column level in {}", path))
+        );
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn written_values_are_read_back() {
+        let client = Client::with_seed(1);
+        let ramp = tag("ramp(period=10)", ETagtype::REAL);
+        assert_eq!(client.write_tag(&ramp, ETagValue::Real(55.5)), Ok(true));
+        assert_eq!(client.read_tag(&ramp), Ok(ETagValue::Real(55.5)));
+
+        // A counter continues from the written value
+        let counter = tag("counter(step=1, interval=3600)", ETagtype::INT);
+        assert_eq!(client.write_tag(&counter, ETagValue::Int(40)), Ok(true));
+        assert_eq!(client.read_tag(&counter), Ok(ETagValue::Int(40)));
+        let t = client.start.elapsed().as_secs_f64() + 3600.0;
+        assert_eq!(client.read_address(&counter, t), Ok(ETagValue::Int(41)));
+
+        assert_eq!(
+            client.write_list(&vec![(counter, ETagValue::Bool(true))]),
+            Ok(vec![Err(String::from(
+                "Invalid datatype for write value Bool(true) to INT"
+            ))])
+        );
+    }
+}
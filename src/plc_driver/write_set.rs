@@ -0,0 +1,323 @@
+use super::{ETag, ETagRW, ETagValue};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum EWriteOutcome {
+    Written,
+    NotWritten(String),
+    RolledBack,
+    RollbackFailed(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EWriteSetItem {
+    pub tag: ETag,
+    pub previous: ETagValue,
+    pub value: ETagValue,
+    pub outcome: EWriteOutcome,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EWriteSetReport {
+    pub committed: bool,
+    pub error: Option<String>,
+    pub items: Vec<EWriteSetItem>,
+}
+
+// Writes all targets or none of them: current values are captured first and
+// written back when any item of the set fails.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EWriteSet {
+    pub writes: Vec<(ETag, ETagValue)>,
+}
+
+impl EWriteSet {
+    pub fn new() -> Self {
+        Self { writes: Vec::new() }
+    }
+
+    pub fn add(&mut self, tag: ETag, value: ETagValue) -> &mut Self {
+        self.writes.push((tag, value));
+        self
+    }
+
+    pub fn apply<D: ETagRW + ?Sized>(&self, driver: &D) -> Result<EWriteSetReport, String> {
+        let tags: Vec<ETag> = self.writes.iter().map(|t| t.0.clone()).collect();
+        let previous = driver
+            .read_list(&tags)?
+            .into_iter()
+            .zip(&tags)
+            .map(|(r, tag)| r.map_err(|err| format!("Cannot capture {}: {}", tag.name, err)))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let (outcomes, request_failed) = match driver.write_list(&self.writes) {
+            Err(err) => (
+                vec![EWriteOutcome::NotWritten(err); self.writes.len()],
+                true,
+            ),
+            Ok(results) => (
+                (0..self.writes.len())
+                    .map(|i| match confirmed(results.get(i)) {
+                        Ok(()) => EWriteOutcome::Written,
+                        Err(err) => EWriteOutcome::NotWritten(err),
+                    })
+                    .collect(),
+                false,
+            ),
+        };
+        let error = outcomes.iter().find_map(|o| match o {
+            EWriteOutcome::NotWritten(err) => Some(err.clone()),
+            _ => None,
+        });
+
+        let mut items: Vec<EWriteSetItem> = self
+            .writes
+            .iter()
+            .zip(previous)
+            .zip(outcomes)
+            .map(|(((tag, value), previous), outcome)| EWriteSetItem {
+                tag: tag.clone(),
+                previous,
+                value: value.clone(),
+                outcome,
+            })
+            .collect();
+
+        if error.is_none() {
+            return Ok(EWriteSetReport {
+                committed: true,
+                error,
+                items,
+            });
+        }
+
+        warn!("Write set failed, rolling back: {:?}", error);
+        // A failed request may have written part of the set before it broke
+        // off, so then every captured value goes back
+        let rollback: Vec<usize> = (0..items.len())
+            .filter(|i| request_failed || items[*i].outcome == EWriteOutcome::Written)
+            .collect();
+        let restore: Vec<(ETag, ETagValue)> = rollback
+            .iter()
+            .map(|i| (items[*i].tag.clone(), items[*i].previous.clone()))
+            .collect();
+        let restored = if restore.is_empty() {
+            Vec::new()
+        } else {
+            match driver.write_list(&restore) {
+                Ok(results) => results,
+                Err(err) => vec![Err(err); restore.len()],
+            }
+        };
+        for (n, i) in rollback.into_iter().enumerate() {
+            items[i].outcome = match confirmed(restored.get(n)) {
+                Ok(()) => EWriteOutcome::RolledBack,
+                Err(err) => {
+                    error!("Rollback of {} failed: {}", items[i].tag.name, err);
+                    EWriteOutcome::RollbackFailed(err)
+                }
+            };
+        }
+        Ok(EWriteSetReport {
+            committed: false,
+            error,
+            items,
+        })
+    }
+}
+
+fn confirmed(result: Option<&Result<bool, String>>) -> Result<(), String> {
+    match result {
+        Some(Ok(true)) => Ok(()),
+        Some(Ok(false)) => Err(String::from("Write was not confirmed")),
+        Some(Err(err)) => Err(err.clone()),
+        None => Err(String::from("No result for the write")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plc_driver::tests::tag;
+    use crate::plc_driver::ETagtype;
+    use std::sync::Mutex;
+
+    type Answer = Result<Vec<Result<bool, String>>, String>;
+
+    // Reads back 0 and answers each write request from a script, `Ok(true)`
+    // for every item once the script runs out
+    struct Device {
+        answers: Mutex<Vec<Answer>>,
+        writes: Mutex<Vec<Vec<(ETag, ETagValue)>>>,
+    }
+
+    impl Device {
+        fn new(mut answers: Vec<Answer>) -> Self {
+            answers.reverse();
+            Self {
+                answers: Mutex::new(answers),
+                writes: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn writes(&self) -> Vec<Vec<(ETag, ETagValue)>> {
+            self.writes.lock().unwrap().clone()
+        }
+    }
+
+    impl ETagRW for Device {
+        fn read_tag(&self, _tag: &ETag) -> Result<ETagValue, String> {
+            Ok(ETagValue::Int(0))
+        }
+        fn read_list(&self, tags: &Vec<ETag>) -> Result<Vec<Result<ETagValue, String>>, String> {
+            Ok(tags.iter().map(|t| self.read_tag(t)).collect())
+        }
+        fn write_tag(&self, tag: &ETag, write: ETagValue) -> Result<bool, String> {
+            self.write_list(&vec![(tag.clone(), write)])?.pop().unwrap()
+        }
+        fn write_list(
+            &self,
+            tags: &Vec<(ETag, ETagValue)>,
+        ) -> Result<Vec<Result<bool, String>>, String> {
+            self.writes.lock().unwrap().push(tags.clone());
+            self.answers
+                .lock()
+                .unwrap()
+                .pop()
+                .unwrap_or_else(|| Ok(vec![Ok(true); tags.len()]))
+        }
+    }
+
+    fn set() -> EWriteSet {
+        let mut set = EWriteSet::new();
+        for name in &["a", "b", "c"] {
+            set.add(tag(name, ETagtype::INT), ETagValue::Int(1));
+        }
+        set
+    }
+
+    fn restored(write: &[(ETag, ETagValue)]) -> Vec<(String, ETagValue)> {
+        write
+            .iter()
+            .map(|(tag, value)| (tag.name.clone(), value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn written_set_commits() {
+        let device = Device::new(vec![]);
+        let report = set().apply(&device).unwrap();
+        assert!(report.committed);
+        assert_eq!(report.error, None);
+        assert!(report
+            .items
+            .iter()
+            .all(|item| item.outcome == EWriteOutcome::Written));
+        assert_eq!(device.writes().len(), 1);
+    }
+
+    #[test]
+    fn rejected_item_rolls_back_the_rest() {
+        let device = Device::new(vec![Ok(vec![
+            Ok(true),
+            Err(String::from("Rejected")),
+            Ok(true),
+        ])]);
+        let report = set().apply(&device).unwrap();
+        assert!(!report.committed);
+        assert_eq!(report.error, Some(String::from("Rejected")));
+        assert_eq!(report.items[0].outcome, EWriteOutcome::RolledBack);
+        assert_eq!(
+            report.items[1].outcome,
+            EWriteOutcome::NotWritten(String::from("Rejected"))
+        );
+        assert_eq!(report.items[2].outcome, EWriteOutcome::RolledBack);
+        let writes = device.writes();
+        assert_eq!(writes.len(), 2);
+        assert_eq!(
+            restored(&writes[1]),
+            vec![
+                (String::from("a"), ETagValue::Int(0)),
+                (String::from("c"), ETagValue::Int(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_request_restores_every_item() {
+        let device = Device::new(vec![Err(String::from("Connection lost"))]);
+        let report = set().apply(&device).unwrap();
+        assert!(!report.committed);
+        assert_eq!(report.error, Some(String::from("Connection lost")));
+        assert!(report
+            .items
+            .iter()
+            .all(|item| item.outcome == EWriteOutcome::RolledBack));
+        let writes = device.writes();
+        assert_eq!(writes.len(), 2);
+        assert_eq!(
+            restored(&writes[1]),
+            vec![
+                (String::from("a"), ETagValue::Int(0)),
+                (String::from("b"), ETagValue::Int(0)),
+                (String::from("c"), ETagValue::Int(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn rollback_results_are_checked_per_item() {
+        let device = Device::new(vec![
+            Err(String::from("Connection lost")),
+            Ok(vec![Ok(true), Err(String::from("Rejected")), Ok(false)]),
+        ]);
+        let report = set().apply(&device).unwrap();
+        assert!(!report.committed);
+        assert_eq!(report.items[0].outcome, EWriteOutcome::RolledBack);
+        assert_eq!(
+            report.items[1].outcome,
+            EWriteOutcome::RollbackFailed(String::from("Rejected"))
+        );
+        assert_eq!(
+            report.items[2].outcome,
+            EWriteOutcome::RollbackFailed(String::from("Write was not confirmed"))
+        );
+    }
+
+    #[test]
+    fn failed_rollback_request_fails_every_item() {
+        let device = Device::new(vec![
+            Ok(vec![Ok(true), Ok(true), Err(String::from("Rejected"))]),
+            Err(String::from("Connection lost")),
+        ]);
+        let report = set().apply(&device).unwrap();
+        assert_eq!(
+            report.items[0].outcome,
+            EWriteOutcome::RollbackFailed(String::from("Connection lost"))
+        );
+        assert_eq!(
+            report.items[1].outcome,
+            EWriteOutcome::RollbackFailed(String::from("Connection lost"))
+        );
+        assert_eq!(
+            report.items[2].outcome,
+            EWriteOutcome::NotWritten(String::from("Rejected"))
+        );
+    }
+
+    #[test]
+    fn missing_results_count_as_failed() {
+        let device = Device::new(vec![Ok(vec![Ok(true)]), Ok(vec![])]);
+        let report = set().apply(&device).unwrap();
+        assert_eq!(report.error, Some(String::from("No result for the write")));
+        assert_eq!(
+            report.items[0].outcome,
+            EWriteOutcome::RollbackFailed(String::from("No result for the write"))
+        );
+        assert_eq!(
+            report.items[1].outcome,
+            EWriteOutcome::NotWritten(String::from("No result for the write"))
+        );
+    }
+}